@@ -0,0 +1,222 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+// With --diff, the normalized contents of each file that would be modified
+// are compared with the original, and the ranges of bytes which differ are
+// listed, with the old and new values in hex. This is meant for checking
+// that a handler only touches the fields it is supposed to.
+
+use anyhow::Result;
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// At most this many ranges are listed for each file
+pub const MAX_RANGES: usize = 16;
+
+/// At most this many bytes are shown for each range
+pub const MAX_BYTES: usize = 16;
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// A range of bytes which differ. If the files have different sizes, the
+/// part that is only present in one of them is a range too.
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub start: u64,
+    pub end: u64,
+    /// The first MAX_BYTES of the range in the original
+    pub old: Vec<u8>,
+    /// The first MAX_BYTES of the range in the normalized file
+    pub new: Vec<u8>,
+}
+
+/// The changes between two files, and their sizes
+#[derive(Debug, PartialEq)]
+pub struct Diff {
+    /// The first MAX_RANGES changes
+    pub changes: Vec<Change>,
+    /// The number of changes, including those which were not stored
+    pub count: u64,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+/// Like `read_exact`, but a short read at the end of the file is not an
+/// error. Returns the number of bytes read.
+fn read_block(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match input.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Compare two streams block by block
+pub fn compare(mut old: impl Read, mut new: impl Read) -> Result<Diff> {
+    let mut diff = Diff { changes: vec![], count: 0, old_size: 0, new_size: 0 };
+    let mut current: Option<Change> = None;
+
+    let mut old_buf = vec![0; BLOCK_SIZE];
+    let mut new_buf = vec![0; BLOCK_SIZE];
+    let mut pos = 0;
+
+    loop {
+        let old_len = read_block(&mut old, &mut old_buf)?;
+        let new_len = read_block(&mut new, &mut new_buf)?;
+        if old_len == 0 && new_len == 0 {
+            break;
+        }
+        diff.old_size += old_len as u64;
+        diff.new_size += new_len as u64;
+
+        for i in 0..old_len.max(new_len) {
+            let old_byte = old_buf[..old_len].get(i);
+            let new_byte = new_buf[..new_len].get(i);
+
+            if old_byte == new_byte {
+                if let Some(change) = current.take() {
+                    diff.push(change);
+                }
+                continue;
+            }
+
+            let change = current.get_or_insert_with(|| Change {
+                start: pos + i as u64,
+                end: pos + i as u64,
+                old: vec![],
+                new: vec![],
+            });
+            change.end += 1;
+            if let Some(byte) = old_byte.filter(|_| change.old.len() < MAX_BYTES) {
+                change.old.push(*byte);
+            }
+            if let Some(byte) = new_byte.filter(|_| change.new.len() < MAX_BYTES) {
+                change.new.push(*byte);
+            }
+        }
+
+        pos += old_len.max(new_len) as u64;
+    }
+
+    if let Some(change) = current.take() {
+        diff.push(change);
+    }
+
+    Ok(diff)
+}
+
+impl Diff {
+    fn push(&mut self, change: Change) {
+        self.count += 1;
+        if self.changes.len() < MAX_RANGES {
+            self.changes.push(change);
+        }
+    }
+
+    /// One line for each change, prefixed by the path
+    pub fn format(&self, path: &Path) -> String {
+        let hex = |data: &[u8], len: u64| {
+            let mut s = data.iter().fold(String::new(), |mut s, b| {
+                let _ = write!(s, "{b:02x}");
+                s
+            });
+            if (data.len() as u64) < len {
+                s.push('…');
+            }
+            if s.is_empty() {
+                s.push('-');
+            }
+            s
+        };
+
+        let mut out = String::new();
+        for change in &self.changes {
+            let old_len = self.old_size.clamp(change.start, change.end) - change.start;
+            let new_len = self.new_size.clamp(change.start, change.end) - change.start;
+            let _ = writeln!(out, "{}: 0x{:x}..0x{:x}: {} → {}",
+                             path.display(), change.start, change.end,
+                             hex(&change.old, old_len),
+                             hex(&change.new, new_len));
+        }
+        if self.count > self.changes.len() as u64 {
+            let _ = writeln!(out, "{}: … and {} more ranges",
+                             path.display(), self.count - self.changes.len() as u64);
+        }
+        if self.old_size != self.new_size {
+            let _ = writeln!(out, "{}: size {} → {}",
+                             path.display(), self.old_size, self.new_size);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(old: &[u8], new: &[u8]) -> Vec<(u64, u64)> {
+        compare(old, new).unwrap().changes.iter().map(|c| (c.start, c.end)).collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(ranges(b"", b""), vec![]);
+        assert_eq!(ranges(b"abc", b"abc"), vec![]);
+        assert_eq!(ranges(b"abcdef", b"aXYdeZ"), vec![(1, 3), (5, 6)]);
+
+        // The part only present in one of the files
+        assert_eq!(ranges(b"abc", b"abcde"), vec![(3, 5)]);
+        assert_eq!(ranges(b"abcde", b"aXc"), vec![(1, 2), (3, 5)]);
+    }
+
+    #[test]
+    fn test_gzip_mtime() {
+        // The MTIME field of the gzip header is at offset 4
+        let old = b"\x1f\x8b\x08\x00\x12\x34\x56\x78\x00\x03rest of the stream";
+        let new = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03rest of the stream";
+
+        let diff = compare(&old[..], &new[..]).unwrap();
+        assert_eq!(diff.changes, vec![
+            Change { start: 4, end: 8, old: vec![0x12, 0x34, 0x56, 0x78], new: vec![0; 4] },
+        ]);
+        assert_eq!(diff.format(Path::new("foo.gz")), "foo.gz: 0x4..0x8: 12345678 → 00000000\n");
+    }
+
+    #[test]
+    fn test_across_blocks() {
+        let old = vec![0; BLOCK_SIZE * 2];
+        let mut new = old.clone();
+        new[BLOCK_SIZE - 2 .. BLOCK_SIZE + 2].fill(1);
+
+        let start = BLOCK_SIZE as u64 - 2;
+        assert_eq!(ranges(&old, &new), vec![(start, start + 4)]);
+    }
+
+    #[test]
+    fn test_caps() {
+        let old = vec![0; 100];
+        let mut new = old.clone();
+        for i in (0..100).step_by(2) {
+            new[i] = 1;
+        }
+
+        let diff = compare(&old[..], &new[..]).unwrap();
+        assert_eq!(diff.changes.len(), MAX_RANGES);
+        assert_eq!(diff.count, 50);
+        assert!(diff.format(Path::new("x")).ends_with("x: … and 34 more ranges\n"));
+
+        let diff = compare(&[0; 20][..], &[1; 30][..]).unwrap();
+        assert_eq!(diff.count, 1);
+        assert_eq!(diff.format(Path::new("x")),
+                   format!("x: 0x0..0x1e: {}… → {}…\nx: size 20 → 30\n",
+                           "00".repeat(MAX_BYTES), "01".repeat(MAX_BYTES)));
+
+        assert_eq!(compare(&b"ab"[..], &b"abcd"[..]).unwrap().format(Path::new("x")),
+                   "x: 0x2..0x4: - → 6364\nx: size 2 → 4\n");
+    }
+}
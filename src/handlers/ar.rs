@@ -9,12 +9,97 @@ use std::rc::Rc;
 
 use crate::handlers::InputOutputHelper;
 use crate::options;
+use crate::trace;
 
 const MAGIC: &[u8] = b"!<arch>\n";
 
 const FILE_HEADER_LENGTH: usize = 60;
 const FILE_HEADER_MAGIC: &[u8] = &[0o140, 0o012];
 
+/// Parse a System V/GNU symbol table: the number of symbols, the offsets
+/// of the members which define them, and the symbol names. The numbers are
+/// big-endian, 4 bytes for the "/" member and 8 bytes for the "/SYM64/"
+/// member. Returns the number of symbols.
+fn check_symbol_table(data: &[u8], word_size: usize, archive_size: u64) -> Result<u64> {
+    let word = |i: usize| -> Result<u64> {
+        let offset = i * word_size;
+        let bytes = data.get(offset .. offset + word_size)
+            .ok_or(super::Error::UnexpectedEOF(offset as u64, word_size))?;
+        Ok(bytes.iter().fold(0, |acc, b| acc << 8 | *b as u64))
+    };
+
+    let count = word(0)?;
+
+    // Check before casting to usize, so that we don't overflow
+    if count.saturating_mul(word_size as u64) >= data.len() as u64 {
+        return Err(super::Error::Other(
+            format!("symbol table with {count} symbols is larger than {} bytes", data.len())
+        ).into());
+    }
+
+    for i in 1..=count as usize {
+        let offset = word(i)?;
+        if offset < MAGIC.len() as u64
+            || offset.checked_add(FILE_HEADER_LENGTH as u64).is_none_or(|end| end > archive_size) {
+            return Err(super::Error::Other(
+                format!("symbol table offset 0x{offset:x} is outside of the archive")
+            ).into());
+        }
+    }
+
+    let names = &data[(count as usize + 1) * word_size..];
+    let n_names = names.iter().filter(|b| **b == 0).count() as u64;
+    if n_names < count {
+        return Err(super::Error::Other(
+            format!("symbol table has {count} symbols, but only {n_names} names")
+        ).into());
+    }
+
+    Ok(count)
+}
+
+/// Convert a "/SYM64/" symbol table to the "/" form, if all offsets fit in
+/// 32 bits after the table has been shrunk. The table must have been
+/// verified with check_symbol_table.
+fn shrink_symbol_table(data: &[u8]) -> Option<Vec<u8>> {
+    let word = |i: usize| u64::from_be_bytes(data[i * 8 .. i * 8 + 8].try_into().unwrap());
+
+    let count = word(0) as usize;
+
+    // All members that follow move by this much
+    let delta = (count as u64 + 1) * 4;
+
+    let mut table = Vec::with_capacity(data.len() - delta as usize);
+    table.extend_from_slice(&u32::try_from(count).ok()?.to_be_bytes());
+    for i in 1..=count {
+        let offset = u32::try_from(word(i).checked_sub(delta)?).ok()?;
+        table.extend_from_slice(&offset.to_be_bytes());
+    }
+    table.extend_from_slice(&data[(count + 1) * 8..]);
+    Some(table)
+}
+
+/// The name of a member, with names of the form "/123" looked up in the
+/// table of long filenames, and the "/" terminator of GNU names removed.
+fn member_name(name: &str, long_names: &[u8]) -> String {
+    let long_name = name.strip_prefix('/')
+        .and_then(|offset| offset.parse::<usize>().ok())
+        .and_then(|offset| long_names.get(offset..));
+
+    let name = match long_name {
+        Some(rest) => {
+            let end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+            String::from_utf8_lossy(&rest[..end]).into_owned()
+        }
+        None => name.to_string(),
+    };
+
+    match name.strip_suffix('/') {
+        Some(stem) => stem.to_string(),
+        None => name,
+    }
+}
+
 pub struct Ar {
     config: Rc<options::Config>,
 }
@@ -58,102 +143,248 @@ impl super::Processor for Ar {
     }
 
     fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
-        let mut have_mod = false;
-        let (mut io, mut input) = InputOutputHelper::open(input_path, self.config.check)?;
+        process_archive(&self.config, input_path, &mut |_, _| Ok(false))
+    }
+}
 
-        let mut buf = [0; MAGIC.len()];
-        input.read_exact(&mut buf)?;
-        if buf != MAGIC {
-            return Err(super::Error::BadMagic(0, buf.to_vec(), MAGIC).into());
+/// Normalize the headers of the members of an archive. The contents of each
+/// member, except for the symbol tables and the table of long filenames,
+/// are passed to `member` with the name of the member. It may modify the
+/// contents in place, and returns true if it did. The size of members must
+/// not change, because the symbol table refers to members by offset.
+pub fn process_archive(
+    config: &options::Config,
+    input_path: &Path,
+    member: &mut dyn FnMut(&str, &mut [u8]) -> Result<bool>,
+) -> Result<super::ProcessResult> {
+
+    let mut have_mod = false;
+    let (mut io, mut input) = InputOutputHelper::open(input_path, config)?;
+
+    let mut buf = [0; MAGIC.len()];
+    input.read_exact(&mut buf)?;
+    if buf != MAGIC {
+        return Err(super::Error::BadMagic(0, buf.to_vec(), MAGIC).into());
+    }
+
+    io.open_output()?;
+    let mut output = BufWriter::new(io.output.as_mut().unwrap());
+
+    output.write_all(&buf)?;
+
+    let mut long_names = vec![];
+
+    loop {
+        let pos = input.stream_position()?;
+        let mut buf = [0; FILE_HEADER_LENGTH];
+
+        debug!("{}: reading file header at offset {pos}", io.input_path.display());
+        if !read_exact_or_zero(&mut input, &mut buf)? {
+            break;
         }
 
-        io.open_output()?;
-        let mut output = BufWriter::new(io.output.as_mut().unwrap());
+        // https://en.wikipedia.org/wiki/Ar_(Unix)
+        // from   to     Name                      Format
+        // 0      15     File name                 ASCII
+        // 16     27     File modification date    Decimal
+        // 28     33     Owner ID                  Decimal
+        // 34     39     Group ID                  Decimal
+        // 40     47     File mode                 Octal
+        // 48     57     File size in bytes        Decimal
+        // 58     59     File magic                \140\012
+
+        if &buf[58..] != FILE_HEADER_MAGIC {
+            return Err(
+                super::Error::BadMagic(pos, buf[58..].to_vec(), FILE_HEADER_MAGIC).into());
+        }
 
-        output.write_all(&buf)?;
+        let name = std::str::from_utf8(&buf[0..16])?.trim_end_matches(' ').to_string();
 
-        loop {
-            let pos = input.stream_position()?;
-            let mut buf = [0; FILE_HEADER_LENGTH];
+        let size = std::str::from_utf8(&buf[48..58])?.trim_end_matches(' ');
+        let mut size = size.parse::<u64>()?;
 
-            debug!("{}: reading file header at offset {pos}", io.input_path.display());
-            if !read_exact_or_zero(&mut input, &mut buf)? {
-                break;
-            }
+        let padded_size = size + size % 2;
 
-            // https://en.wikipedia.org/wiki/Ar_(Unix)
-            // from   to     Name                      Format
-            // 0      15     File name                 ASCII
-            // 16     27     File modification date    Decimal
-            // 28     33     Owner ID                  Decimal
-            // 34     39     Group ID                  Decimal
-            // 40     47     File mode                 Octal
-            // 48     57     File size in bytes        Decimal
-            // 58     59     File magic                \140\012
-
-            if &buf[58..] != FILE_HEADER_MAGIC {
-                return Err(
-                    super::Error::BadMagic(pos, buf[58..].to_vec(), FILE_HEADER_MAGIC).into());
-            }
+        // Don't trust the header with the allocation
+        let start = pos + FILE_HEADER_LENGTH as u64;
+        if padded_size > io.input_metadata.len().saturating_sub(start) {
+            return Err(super::Error::UnexpectedEOF(start, padded_size as usize).into());
+        }
 
-            let name = std::str::from_utf8(&buf[0..16])?.trim_end_matches(' ');
+        let mut data = vec![0; padded_size as usize];
+        input.read_exact(&mut data)?;
+
+        let word_size = match name.as_str() {
+            "/" => Some(4),
+            "/SYM64/" => Some(8),
+            _ => None,
+        };
+
+        if let Some(word_size) = word_size {
+            // The symbol table maps symbol names to the offsets of the
+            // members that define them. We don't change the size of any
+            // other members, so the offsets remain valid. The order of symbols
+            // is the order of members, which matters for the linker,
+            // so the table is kept in order.
+            let count = check_symbol_table(&data[..size as usize], word_size,
+                                           io.input_metadata.len())?;
+            debug!("{}: symbol table {:?} with {} symbols",
+                   io.input_path.display(), name, count);
+
+            // llvm-ar can be told to always use the 64-bit table and
+            // other tools use it for large archives, while GNU ar uses it
+            // only when the offsets do not fit. Convert to the 32-bit form
+            // when possible. This only works for the first member, because
+            // the offsets of the members that follow are stored in it.
+            if word_size == 8 && pos == MAGIC.len() as u64 {
+                if let Some(table) = shrink_symbol_table(&data[..size as usize]) {
+                    trace::event(io.input_path, "field",
+                                 format_args!("symbol table /SYM64/ → /"));
+                    buf[0..16].copy_from_slice(format!("{:<16}", "/").as_bytes());
+                    size = table.len() as u64;
+                    data = table;
+                    if size % 2 == 1 {
+                        data.push(b'\n');
+                    }
+                    have_mod = true;
+                }
+            }
+        }
 
-            let size = std::str::from_utf8(&buf[48..58])?.trim_end_matches(' ');
-            let size = size.parse::<u32>()?;
+        if name == "//" {
+            // System V/GNU table of long filenames
+            debug!("{}: long filename index, size={}", io.input_path.display(), size);
+            long_names = data[..size as usize].to_vec();
+        } else {
+            let mtime = std::str::from_utf8(&buf[16..28])?.trim_end_matches(' ');
+            let mtime = mtime.parse::<i64>()?;
 
-            if name == "//" {
-                // System V/GNU table of long filenames
-                debug!("{}: long filename index, size={}", io.input_path.display(), size);
-            } else {
-                let mtime = std::str::from_utf8(&buf[16..28])?.trim_end_matches(' ');
-                let mtime = mtime.parse::<i64>()?;
+            let uid = std::str::from_utf8(&buf[28..34])?.trim_end_matches(' ');
+            let uid = uid.parse::<u64>()?;
 
-                let uid = std::str::from_utf8(&buf[28..34])?.trim_end_matches(' ');
-                let uid = uid.parse::<u64>()?;
+            let gid = std::str::from_utf8(&buf[34..40])?.trim_end_matches(' ');
+            let gid = gid.parse::<u64>()?;
 
-                let gid = std::str::from_utf8(&buf[34..40])?.trim_end_matches(' ');
-                let gid = gid.parse::<u64>()?;
+            let mode = std::str::from_utf8(&buf[40..48])?.trim_end_matches(' ');
+            let mode = u64::from_str_radix(mode, 8)?;
 
-                let mode = std::str::from_utf8(&buf[40..48])?.trim_end_matches(' ');
-                let mode = mode.parse::<u64>()?;
+            debug!("{}: file {:?}, mtime={}, {}:{}, mode={:o}, size={}",
+                   io.input_path.display(), name, mtime, uid, gid, mode, size);
 
-                debug!("{}: file {:?}, mtime={}, {}:{}, mode={:o}, size={}",
-                       io.input_path.display(), name, mtime, uid, gid, mode, size);
+            let new_mtime = config.clamp_mtime(mtime);
+            if new_mtime != mtime {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: mtime {} → {}", name, mtime, new_mtime));
+            }
 
-                if self.config.source_date_epoch.is_some() && mtime > self.config.source_date_epoch.unwrap() {
-                    let source_date_epoch_str = format!("{:<12}", self.config.source_date_epoch.unwrap());
+            if uid != 0 || gid != 0 {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: owner {}:{} → 0:0", name, uid, gid));
+            }
 
-                    buf[16..28].copy_from_slice(source_date_epoch_str.as_bytes());
-                    have_mod = true;
-                }
+            // GNU ar stores the file type bits, llvm-ar only the permissions.
+            // Members are always regular files, so the type is redundant.
+            let new_mode = mode & 0o7777;
+            if new_mode != mode {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: mode {:o} → {:o}", name, mode, new_mode));
+            }
 
-                if uid != 0 || gid != 0 {
-                    buf[28..34].copy_from_slice(b"0     ");
-                    buf[34..40].copy_from_slice(b"0     ");
-                    have_mod = true;
-                }
+            // Write all fields in the canonical form: left-aligned and
+            // padded with spaces, without leading zeros.
+            let fields = format!("{:<12}{:<6}{:<6}{:<8o}", new_mtime, 0, 0, new_mode);
+            if buf[16..48] != *fields.as_bytes() {
+                buf[16..48].copy_from_slice(fields.as_bytes());
+                have_mod = true;
             }
 
-            output.write_all(&buf)?;
+            if word_size.is_none()
+                && member(&member_name(&name, &long_names), &mut data[..size as usize])? {
+                have_mod = true;
+            }
+        }
 
-            let padded_size = size + size % 2;
+        let size_field = format!("{:<10}", size);
+        if buf[48..58] != *size_field.as_bytes() {
+            buf[48..58].copy_from_slice(size_field.as_bytes());
+            have_mod = true;
+        }
 
-            let mut buf = vec![0; padded_size.try_into().unwrap()];
-            input.read_exact(&mut buf)?;
+        output.write_all(&buf)?;
 
-            output.write_all(&buf)?;
+        // Odd-sized members are padded with a newline
+        if data.len() as u64 > size && data[data.len() - 1] != b'\n' {
+            let last = data.len() - 1;
+            data[last] = b'\n';
+            have_mod = true;
         }
 
-        output.flush()?;
-        drop(output);
-        io.finalize(have_mod)
+        output.write_all(&data)?;
     }
+
+    output.flush()?;
+    drop(output);
+    io.finalize(have_mod)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_check_symbol_table() {
+        // one symbol at offset 0x52
+        let table = b"\0\0\0\x01\0\0\0\x52main\0\0";
+        assert_eq!(check_symbol_table(table, 4, 1000).unwrap(), 1);
+        assert!(check_symbol_table(table, 4, 0x52 + 59).is_err());
+
+        let table64 = b"\0\0\0\0\0\0\0\x02\0\0\0\x01\0\0\0\x00\0\0\0\0\0\0\0\x08foo\0bar\0";
+        assert_eq!(check_symbol_table(table64, 8, 0x1_0000_0000 + 60).unwrap(), 2);
+        // The offset needs more than 32 bits
+        assert!(check_symbol_table(table64, 8, 0x1_0000_0000).is_err());
+        // The offset plus the header length does not fit in 64 bits
+        let table64 = b"\0\0\0\0\0\0\0\x01\xff\xff\xff\xff\xff\xff\xff\xf0foo\0";
+        assert!(check_symbol_table(table64, 8, u64::MAX).is_err());
+
+        // Not enough names
+        assert!(check_symbol_table(b"\0\0\0\x01\0\0\0\x52main", 4, 1000).is_err());
+        // Insane count
+        assert!(check_symbol_table(b"\xff\xff\xff\xff\0\0\0\x52main\0", 4, 1000).is_err());
+        assert!(check_symbol_table(b"\xff\xff\xff\xff\xff\xff\xff\xff", 8, 1000).is_err());
+        // Truncated
+        assert!(check_symbol_table(b"\0\0", 4, 1000).is_err());
+
+        assert_eq!(check_symbol_table(b"\0\0\0\0", 4, 1000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_shrink_symbol_table() {
+        // Two symbols at 0x52 and 0x1000, the table shrinks by 12 bytes
+        let table64 = b"\0\0\0\0\0\0\0\x02\0\0\0\0\0\0\0\x52\0\0\0\0\0\0\x10\0foo\0bar\0";
+        assert_eq!(shrink_symbol_table(table64).unwrap(),
+                   b"\0\0\0\x02\0\0\0\x46\0\0\x0f\xf4foo\0bar\0");
+
+        let table64 = b"\0\0\0\0\0\0\0\0";
+        assert_eq!(shrink_symbol_table(table64).unwrap(), b"\0\0\0\0");
+
+        // The offset needs more than 32 bits even after shrinking
+        let table64 = b"\0\0\0\0\0\0\0\x01\0\0\0\x01\0\0\0\x08main\0\0";
+        assert!(shrink_symbol_table(table64).is_none());
+    }
+
+    #[test]
+    fn test_member_name() {
+        let long_names = b"hello.hello.a702ce77795520e3-cgu.0.rcgu.o/\nfoo.o/\n";
+
+        assert_eq!(member_name("lib.rmeta/", long_names), "lib.rmeta");
+        assert_eq!(member_name("/0", long_names), "hello.hello.a702ce77795520e3-cgu.0.rcgu.o");
+        assert_eq!(member_name("/43", long_names), "foo.o");
+        // BSD names are not terminated
+        assert_eq!(member_name("foo.o", long_names), "foo.o");
+        // Offset outside of the table
+        assert_eq!(member_name("/100", long_names), "/100");
+    }
+
     #[test]
     fn filter_a() {
         let cfg = Rc::new(options::Config::empty(0, true));
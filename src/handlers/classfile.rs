@@ -0,0 +1,407 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC: &[u8] = b"\xca\xfe\xba\xbe";
+
+// https://docs.oracle.com/javase/specs/jvms/se21/html/jvms-4.html#jvms-4.4
+const CONSTANT_UTF8: u8 = 1;
+
+struct Parser<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Parser { data, offset: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.offset + count <= self.data.len() {
+            let slice = &self.data[self.offset .. self.offset + count];
+            self.offset += count;
+            Ok(slice)
+        } else {
+            Err(super::Error::UnexpectedEOF(self.offset as u64, count).into())
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Read a table of attributes, as found in the class, fields, methods,
+    /// and Code attributes
+    fn attributes(&mut self) -> Result<Vec<Attribute<'a>>> {
+        let count = self.u16()?;
+        let mut attributes = vec![];
+        for _ in 0..count {
+            let name_index = self.u16()?;
+            let length = self.u32()?;
+            attributes.push(Attribute { name_index, info: self.take(length as usize)? });
+        }
+        Ok(attributes)
+    }
+}
+
+struct Attribute<'a> {
+    name_index: u16,
+    info: &'a [u8],
+}
+
+fn add_ref(refs: &mut [u32], index: u16) {
+    if let Some(n) = refs.get_mut(index as usize) {
+        *n += 1;
+    }
+}
+
+/// Count the references to Utf8 constants from the attributes, including
+/// their names. Attributes with a layout that is not known here are scanned
+/// for anything that could be an index, so the strings they use are never
+/// considered to be unused.
+fn count_refs(refs: &mut [u32], utf8: &[Option<&[u8]>], attributes: &[Attribute]) -> Result<()> {
+    for attr in attributes {
+        add_ref(refs, attr.name_index);
+        let mut p = Parser::new(attr.info);
+
+        match utf8.get(attr.name_index as usize).copied().flatten() {
+            Some(b"Code") => {
+                p.take(4)?;  // max stack and max locals
+                // Instructions refer to other constants, never directly to strings
+                let code_length = p.u32()?;
+                p.take(code_length as usize)?;
+                let exceptions = p.u16()?;
+                p.take(8 * exceptions as usize)?;
+                count_refs(refs, utf8, &p.attributes()?)?;
+            }
+            Some(b"SourceFile" | b"Signature") => {
+                add_ref(refs, p.u16()?);
+            }
+            Some(b"LocalVariableTable" | b"LocalVariableTypeTable") => {
+                let count = p.u16()?;
+                for _ in 0..count {
+                    p.take(4)?;  // start and length
+                    add_ref(refs, p.u16()?);  // name
+                    add_ref(refs, p.u16()?);  // descriptor or signature
+                    p.take(2)?;  // index
+                }
+            }
+            Some(b"ConstantValue" | b"Deprecated" | b"Synthetic" | b"Exceptions" |
+                 b"LineNumberTable" | b"StackMapTable" | b"SourceDebugExtension" |
+                 b"EnclosingMethod" | b"BootstrapMethods" |
+                 b"NestHost" | b"NestMembers" | b"PermittedSubclasses") => {}
+            _ => {
+                for w in attr.info.windows(2) {
+                    add_ref(refs, u16::from_be_bytes([w[0], w[1]]));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+struct ClassFile<'a> {
+    data: &'a [u8],
+    /// The Utf8 constants, by constant pool index
+    utf8: Vec<Option<&'a [u8]>>,
+    /// The positions of the Utf8 constants in data
+    utf8_offsets: Vec<usize>,
+    /// How often each Utf8 constant is referenced
+    refs: Vec<u32>,
+    /// The end of the constant pool in data
+    pool_end: usize,
+    /// The position of the attribute count of the class
+    attributes_start: usize,
+    attributes: Vec<Attribute<'a>>,
+}
+
+impl<'a> ClassFile<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        let mut p = Parser::new(data);
+
+        let magic = p.take(MAGIC.len())?;
+        if magic != MAGIC {
+            return Err(super::Error::BadMagic(0, magic.to_vec(), MAGIC).into());
+        }
+        p.take(4)?;  // minor and major version
+
+        let pool_count = p.u16()?;
+        let mut utf8 = vec![None; pool_count as usize];
+        let mut utf8_offsets = vec![0; pool_count as usize];
+        let mut refs = vec![0; pool_count as usize];
+
+        // Index 0 is not used
+        let mut index = 1;
+        while index < pool_count as usize {
+            let offset = p.offset;
+            let tag = p.u8()?;
+            let size = match tag {
+                CONSTANT_UTF8 => {
+                    let length = p.u16()?;
+                    utf8[index] = Some(p.take(length as usize)?);
+                    utf8_offsets[index] = offset;
+                    0
+                }
+                7 | 8 | 16 | 19 | 20 => {
+                    // Class, String, MethodType, Module, Package
+                    add_ref(&mut refs, p.u16()?);
+                    0
+                }
+                12 => {
+                    // NameAndType
+                    add_ref(&mut refs, p.u16()?);
+                    add_ref(&mut refs, p.u16()?);
+                    0
+                }
+                15 => 3,                    // MethodHandle
+                3 | 4 | 9 | 10 | 11 | 17 | 18 => 4,
+                5 | 6 => {
+                    // Long and Double take two entries
+                    index += 1;
+                    8
+                }
+                _ => {
+                    return Err(super::Error::Other(
+                        format!("unknown constant pool tag {tag} at 0x{offset:x}")
+                    ).into());
+                }
+            };
+            p.take(size)?;
+            index += 1;
+        }
+        let pool_end = p.offset;
+
+        p.take(6)?;  // access flags, this class, super class
+        let interfaces_count = p.u16()?;
+        p.take(2 * interfaces_count as usize)?;
+
+        // fields and methods
+        for _ in 0..2 {
+            let count = p.u16()?;
+            for _ in 0..count {
+                p.take(2)?;  // access flags
+                add_ref(&mut refs, p.u16()?);  // name
+                add_ref(&mut refs, p.u16()?);  // descriptor
+                count_refs(&mut refs, &utf8, &p.attributes()?)?;
+            }
+        }
+
+        let attributes_start = p.offset;
+        let attributes = p.attributes()?;
+        count_refs(&mut refs, &utf8, &attributes)?;
+
+        if p.offset != data.len() {
+            return Err(super::Error::Other(
+                format!("{} bytes of trailing garbage", data.len() - p.offset)
+            ).into());
+        }
+
+        Ok(ClassFile { data, utf8, utf8_offsets, refs, pool_end, attributes_start, attributes })
+    }
+
+    fn get_utf8(&self, index: u16) -> Option<&'a [u8]> {
+        self.utf8.get(index as usize).copied().flatten()
+    }
+}
+
+/// Return the file name without the directories
+fn base_name(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|c| *c == b'/' || *c == b'\\') {
+        Some(pos) => &path[pos + 1..],
+        None => path,
+    }
+}
+
+pub struct Classfile {
+    config: Rc<options::Config>,
+}
+
+impl Classfile {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+
+    /// Return the class file with the SourceDebugExtension attributes
+    /// dropped and the directories removed from SourceFile, or None if
+    /// there is nothing to do. If SourceFile is the only user of the
+    /// string with the path, the string is replaced. Otherwise, an existing
+    /// string with the base name is used, or it is appended at the end of
+    /// the constant pool. In either case, no indices change.
+    fn strip_source_info(&self, input_path: &Path, class: &ClassFile) -> Result<Option<Vec<u8>>> {
+        let mut new_pool = vec![];
+        let mut pool_count = class.utf8.len();
+        let mut replaced = vec![];
+        let mut attributes = vec![];
+        let mut have_mod = false;
+
+        for attr in &class.attributes {
+            match class.get_utf8(attr.name_index) {
+                Some(b"SourceDebugExtension") => {
+                    trace::event(input_path, "field",
+                                 format_args!("SourceDebugExtension dropped ({} bytes)", attr.info.len()));
+                    have_mod = true;
+                    continue;
+                }
+
+                Some(b"SourceFile") => {
+                    let index = attr.info.try_into().map(u16::from_be_bytes)
+                        .map_err(|_| super::Error::Other("SourceFile attribute has wrong length".to_string()))?;
+                    let name = class.get_utf8(index)
+                        .ok_or_else(|| super::Error::Other(format!("SourceFile refers to bad index {index}")))?;
+
+                    let base = base_name(name);
+                    if base != name && class.refs[index as usize] == 1 {
+                        trace::event(input_path, "field",
+                                     format_args!("SourceFile {} → {} (string replaced)",
+                                                  String::from_utf8_lossy(name),
+                                                  String::from_utf8_lossy(base)));
+                        replaced.push((index as usize, base));
+                        have_mod = true;
+
+                    } else if base != name {
+                        // The path is used elsewhere too.
+                        // An existing string can be used, otherwise a new one is added.
+                        let new_index = match class.utf8.iter().position(|s| *s == Some(base)) {
+                            Some(n) => n,
+                            None if pool_count < u16::MAX as usize => {
+                                new_pool.push(CONSTANT_UTF8);
+                                new_pool.extend_from_slice(&(base.len() as u16).to_be_bytes());
+                                new_pool.extend_from_slice(base);
+                                pool_count += 1;
+                                pool_count - 1
+                            }
+                            None => {
+                                return Err(super::Error::Other("constant pool is full".to_string()).into());
+                            }
+                        };
+
+                        trace::event(input_path, "field",
+                                     format_args!("SourceFile {} → {}",
+                                                  String::from_utf8_lossy(name),
+                                                  String::from_utf8_lossy(base)));
+                        attributes.push((attr.name_index, (new_index as u16).to_be_bytes().to_vec()));
+                        have_mod = true;
+                        continue;
+                    }
+                }
+
+                _ => {}
+            }
+
+            attributes.push((attr.name_index, attr.info.to_vec()));
+        }
+
+        if !have_mod {
+            return Ok(None);
+        }
+
+        let mut out = class.data[..8].to_vec();
+        out.extend_from_slice(&(pool_count as u16).to_be_bytes());
+
+        replaced.sort();
+        let mut pos = 10;
+        for (index, base) in replaced {
+            let offset = class.utf8_offsets[index];
+            out.extend_from_slice(&class.data[pos..offset]);
+            out.push(CONSTANT_UTF8);
+            out.extend_from_slice(&(base.len() as u16).to_be_bytes());
+            out.extend_from_slice(base);
+            pos = offset + 3 + class.utf8[index].unwrap().len();
+        }
+        out.extend_from_slice(&class.data[pos .. class.pool_end]);
+        out.extend_from_slice(&new_pool);
+        out.extend_from_slice(&class.data[class.pool_end .. class.attributes_start]);
+
+        out.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        for (name_index, info) in attributes {
+            out.extend_from_slice(&name_index.to_be_bytes());
+            out.extend_from_slice(&(info.len() as u32).to_be_bytes());
+            out.extend_from_slice(&info);
+        }
+
+        Ok(Some(out))
+    }
+}
+
+impl super::Processor for Classfile {
+    fn name(&self) -> &str {
+        "classfile"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        // Without the option, class files are not modified
+        Ok(self.config.classfile_strip_source_info && path.extension().is_some_and(|x| x == "class"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+
+        let class = ClassFile::parse(&data)?;
+        debug!("{}: class file with {} constants and {} attributes",
+               input_path.display(), class.utf8.len(), class.attributes.len());
+
+        let output = if self.config.classfile_strip_source_info {
+            self.strip_source_info(input_path, &class)?
+        } else {
+            None
+        };
+
+        let have_mod = output.is_some();
+        if let Some(output) = output {
+            io.open_output()?;
+            io.output.as_mut().unwrap().write_all(&output)?;
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_name() {
+        assert_eq!(base_name(b"Foo.java"), b"Foo.java");
+        assert_eq!(base_name(b"/builddir/build/src/Foo.java"), b"Foo.java");
+        assert_eq!(base_name(b"C:\\src\\Foo.java"), b"Foo.java");
+        assert_eq!(base_name(b""), b"");
+    }
+
+    #[test]
+    fn filter_classfile() {
+        let mut cfg = options::Config::empty(0, false);
+        cfg.classfile_strip_source_info = true;
+        let h = Classfile::boxed(&Rc::new(cfg));
+
+        assert!( h.filter(Path::new("/some/path/Foo.class")).unwrap());
+        assert!( h.filter(Path::new("/some/path/Foo$1.class")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/Foo.java")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/class")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Classfile::boxed(&cfg);
+        assert!(!h.filter(Path::new("/some/path/Foo.class")).unwrap());
+    }
+}
@@ -0,0 +1,239 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC_NEWC: &[u8] = b"070701";
+const MAGIC_CRC: &[u8] = b"070702";
+
+const HEADER_LENGTH: usize = 110;
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+
+// https://www.kernel.org/doc/Documentation/early-userspace/buffer-format.txt
+// The header is the magic and 13 fields, each 8 hex digits.
+const FIELD_INO: usize = 0;
+const FIELD_MODE: usize = 1;
+const FIELD_UID: usize = 2;
+const FIELD_GID: usize = 3;
+const FIELD_NLINK: usize = 4;
+const FIELD_MTIME: usize = 5;
+const FIELD_FILESIZE: usize = 6;
+const FIELD_DEVMAJOR: usize = 7;
+const FIELD_DEVMINOR: usize = 8;
+const FIELD_NAMESIZE: usize = 11;
+
+struct Header {
+    buf: [u8; HEADER_LENGTH],
+}
+
+impl Header {
+    fn range(field: usize) -> std::ops::Range<usize> {
+        let start = MAGIC_NEWC.len() + field * 8;
+        start .. start + 8
+    }
+
+    fn get(&self, field: usize) -> Result<u32> {
+        let text = std::str::from_utf8(&self.buf[Self::range(field)])?;
+        u32::from_str_radix(text, 16)
+            .map_err(|e| super::Error::Other(format!("bad header field {text:?}: {e}")).into())
+    }
+
+    fn set(&mut self, field: usize, value: u32) {
+        // Different implementations use upper or lower case, keep what we have
+        let lower = self.buf[MAGIC_NEWC.len()..].iter().any(|b| (b'a'..=b'f').contains(b));
+        let text = if lower { format!("{value:08x}") } else { format!("{value:08X}") };
+        self.buf[Self::range(field)].copy_from_slice(text.as_bytes());
+    }
+}
+
+/// The number of bytes needed to pad size to a multiple of 4
+fn padding(size: u64) -> u64 {
+    (4 - size % 4) % 4
+}
+
+pub struct Cpio {
+    config: Rc<options::Config>,
+}
+
+impl Cpio {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+fn copy_exact(input: &mut BufReader<File>, output: &mut impl Write, size: u64) -> Result<()> {
+    let pos = input.stream_position()?;
+    let n = io::copy(&mut input.take(size), output)?;
+    if n < size {
+        return Err(super::Error::UnexpectedEOF(pos, size as usize).into());
+    }
+    Ok(())
+}
+
+impl super::Processor for Cpio {
+    fn name(&self) -> &str {
+        "cpio"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "cpio"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let mut have_mod = false;
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        io.open_output()?;
+        let mut output = BufWriter::new(io.output.as_mut().unwrap());
+
+        // Members which are hardlinked share the device and inode numbers.
+        // Those numbers are renumbered in order of appearance, so that the
+        // links are preserved.
+        let mut inodes = HashMap::new();
+
+        loop {
+            let pos = input.stream_position()?;
+
+            let mut header = Header { buf: [0; HEADER_LENGTH] };
+            if let Err(e) = input.read_exact(&mut header.buf) {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    return Err(super::Error::UnexpectedEOF(pos, HEADER_LENGTH).into());
+                }
+                return Err(e.into());
+            }
+
+            let magic = &header.buf[..MAGIC_NEWC.len()];
+            if magic != MAGIC_NEWC && magic != MAGIC_CRC {
+                return Err(super::Error::BadMagic(pos, magic.to_vec(), MAGIC_NEWC).into());
+            }
+
+            let namesize = header.get(FIELD_NAMESIZE)? as u64;
+            let mut name = vec![0; (namesize + padding(HEADER_LENGTH as u64 + namesize)) as usize];
+            input.read_exact(&mut name)?;
+            let name_str = String::from_utf8_lossy(&name[..namesize as usize])
+                .trim_end_matches('\0')
+                .to_string();
+
+            let filesize = header.get(FIELD_FILESIZE)? as u64;
+
+            if name_str.as_bytes() == TRAILER {
+                debug!("{}: trailer at offset {pos}", io.input_path.display());
+                output.write_all(&header.buf)?;
+                output.write_all(&name)?;
+
+                // The archive may be padded to a block size, copy whatever follows
+                io::copy(&mut input, &mut output)?;
+                break;
+            }
+
+            let mode = header.get(FIELD_MODE)?;
+            let mtime = header.get(FIELD_MTIME)?;
+            let (uid, gid) = (header.get(FIELD_UID)?, header.get(FIELD_GID)?);
+            let nlink = header.get(FIELD_NLINK)?;
+            let key = (header.get(FIELD_DEVMAJOR)?, header.get(FIELD_DEVMINOR)?, header.get(FIELD_INO)?);
+
+            debug!("{}: member {:?}, mode={:o}, mtime={}, {}:{}, nlink={}, size={}",
+                   io.input_path.display(), name_str, mode, mtime, uid, gid, nlink, filesize);
+
+            let new_mtime = self.config.clamp_mtime(mtime as i64).max(0) as u32;
+            if new_mtime != mtime {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: mtime {} → {}", name_str, mtime, new_mtime));
+                header.set(FIELD_MTIME, new_mtime);
+                have_mod = true;
+            }
+
+            if uid != 0 || gid != 0 {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: owner {}:{} → 0:0", name_str, uid, gid));
+                header.set(FIELD_UID, 0);
+                header.set(FIELD_GID, 0);
+                have_mod = true;
+            }
+
+            // The link count of directories depends on the file system.
+            // For other files, it is used to detect hardlinks, so keep it.
+            if mode & S_IFMT == S_IFDIR && nlink != 2 {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: nlink {} → 2", name_str, nlink));
+                header.set(FIELD_NLINK, 2);
+                have_mod = true;
+            }
+
+            let next = inodes.len() as u32 + 1;
+            let new_ino = *inodes.entry(key).or_insert(next);
+            if key != (0, 0, new_ino) {
+                trace::event(io.input_path, "field",
+                             format_args!("member {:?}: inode {}:{}:{} → {}",
+                                          name_str, key.0, key.1, key.2, new_ino));
+                header.set(FIELD_DEVMAJOR, 0);
+                header.set(FIELD_DEVMINOR, 0);
+                header.set(FIELD_INO, new_ino);
+                have_mod = true;
+            }
+
+            output.write_all(&header.buf)?;
+            output.write_all(&name)?;
+            copy_exact(&mut input, &mut output, filesize + padding(filesize))?;
+        }
+
+        output.flush()?;
+        drop(output);
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padding() {
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(1), 3);
+        assert_eq!(padding(110 + 2), 0);
+        assert_eq!(padding(110 + 11), 3);
+    }
+
+    #[test]
+    fn test_header_fields() {
+        let mut header = Header { buf: [b'0'; HEADER_LENGTH] };
+        header.buf[..6].copy_from_slice(MAGIC_NEWC);
+        header.buf[Header::range(FIELD_MTIME)].copy_from_slice(b"65a2a4d0");
+
+        assert_eq!(header.get(FIELD_MTIME).unwrap(), 0x65a2a4d0);
+        header.set(FIELD_MTIME, 0x1234abcd);
+        assert_eq!(&header.buf[46..54], b"1234abcd");
+        assert_eq!(header.get(FIELD_MTIME).unwrap(), 0x1234abcd);
+
+        header.buf[Header::range(FIELD_MTIME)].copy_from_slice(b"65A2A4D0");
+        header.set(FIELD_NLINK, 0xff);
+        assert_eq!(&header.buf[38..46], b"000000FF");
+
+        header.buf[Header::range(FIELD_UID)].copy_from_slice(b"0000xyz0");
+        assert!(header.get(FIELD_UID).is_err());
+    }
+
+    #[test]
+    fn filter_cpio() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Cpio::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/initramfs.cpio")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/initramfs.cpio.gz")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/cpio")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+}
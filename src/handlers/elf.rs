@@ -0,0 +1,533 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::{debug, warn};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC: &[u8] = b"\x7fELF";
+
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+
+const SHN_XINDEX: u16 = 0xffff;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_COMPRESSED: u64 = 0x800;
+
+/// Sections with NUL-terminated strings referenced from DWARF debug
+/// information, including the compilation directory and source paths.
+const DEBUG_STRING_SECTIONS: &[&str] = &[".debug_str", ".debug_line_str"];
+
+/// The word size and byte order, from e_ident.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub class64: bool,
+    pub big_endian: bool,
+}
+
+impl Layout {
+    fn u16(&self, buf: &[u8]) -> u16 {
+        let buf = buf[..2].try_into().unwrap();
+        if self.big_endian { u16::from_be_bytes(buf) } else { u16::from_le_bytes(buf) }
+    }
+
+    fn u32(&self, buf: &[u8]) -> u32 {
+        let buf = buf[..4].try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(buf) } else { u32::from_le_bytes(buf) }
+    }
+
+    fn u64(&self, buf: &[u8]) -> u64 {
+        let buf = buf[..8].try_into().unwrap();
+        if self.big_endian { u64::from_be_bytes(buf) } else { u64::from_le_bytes(buf) }
+    }
+
+    /// Elf32_Addr/Elf32_Off/Elf32_Word or Elf64_Addr/Elf64_Off/Elf64_Xword
+    fn word(&self, buf: &[u8]) -> u64 {
+        if self.class64 { self.u64(buf) } else { self.u32(buf) as u64 }
+    }
+
+    fn header_size(&self) -> usize {
+        if self.class64 { 64 } else { 52 }
+    }
+
+    fn section_header_size(&self) -> usize {
+        if self.class64 { 64 } else { 40 }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub offset: u64,
+    pub size: u64,
+    link: u32,
+    name_offset: u32,
+}
+
+fn read_at<R: Read + Seek>(input: &mut R, offset: u64, size: u64, file_size: u64) -> Result<Vec<u8>> {
+    if offset.checked_add(size).is_none_or(|end| end > file_size) {
+        return Err(super::Error::UnexpectedEOF(offset, size as usize).into());
+    }
+
+    let mut buf = vec![0; size as usize];
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn parse_section_header(layout: &Layout, buf: &[u8]) -> Section {
+    // https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.sheader.html
+    // Elf32_Shdr: name@0, type@4, flags@8, addr@12, offset@16, size@20, link@24
+    // Elf64_Shdr: name@0, type@4, flags@8, addr@16, offset@24, size@32, link@40
+    let w = if layout.class64 { 8 } else { 4 };
+
+    Section {
+        name: String::new(), // filled in later from the section name table
+        sh_type: layout.u32(&buf[4..]),
+        flags: layout.word(&buf[8..]),
+        offset: layout.word(&buf[8 + 2 * w..]),
+        size: layout.word(&buf[8 + 3 * w..]),
+        link: layout.u32(&buf[8 + 4 * w..]),
+        name_offset: layout.u32(&buf[0..]),
+    }
+}
+
+/// Parse the ELF header and the section header table and return the sections
+/// with resolved names.
+pub fn read_sections<R: Read + Seek>(input: &mut R, file_size: u64) -> Result<(Layout, Vec<Section>)> {
+    let mut ident = [0; 16];
+    input.seek(SeekFrom::Start(0))?;
+    input.read_exact(&mut ident)?;
+
+    if &ident[..4] != MAGIC {
+        return Err(super::Error::BadMagic(0, ident[..4].to_vec(), MAGIC).into());
+    }
+
+    let class64 = match ident[4] {
+        ELFCLASS32 => false,
+        ELFCLASS64 => true,
+        other => {
+            return Err(super::Error::Other(format!("unknown ELF class {other}")).into());
+        }
+    };
+    let big_endian = match ident[5] {
+        ELFDATA2LSB => false,
+        ELFDATA2MSB => true,
+        other => {
+            return Err(super::Error::Other(format!("unknown ELF data encoding {other}")).into());
+        }
+    };
+    let layout = Layout { class64, big_endian };
+
+    let header = read_at(input, 0, layout.header_size() as u64, file_size)?;
+
+    let (shoff, rest) = if class64 {
+        (layout.u64(&header[40..]), &header[58..])
+    } else {
+        (layout.u32(&header[32..]) as u64, &header[46..])
+    };
+    let shentsize = layout.u16(&rest[0..]);
+    let mut shnum = layout.u16(&rest[2..]) as u64;
+    let mut shstrndx = layout.u16(&rest[4..]) as u32;
+
+    debug!("ELF {}-bit {}, section headers at 0x{shoff:x}, {shnum} sections",
+           if class64 { 64 } else { 32 },
+           if big_endian { "big-endian" } else { "little-endian" });
+
+    if shoff == 0 {
+        return Ok((layout, vec![]));
+    }
+
+    if shentsize as usize != layout.section_header_size() {
+        return Err(super::Error::Other(
+            format!("unexpected section header size {shentsize}")
+        ).into());
+    }
+
+    // If the number of sections or the index of the section name table
+    // don't fit in the header, they are stored in the first section header.
+    let first = read_at(input, shoff, shentsize as u64, file_size)?;
+    let first = parse_section_header(&layout, &first);
+    if shnum == 0 {
+        shnum = first.size;
+    }
+    if shstrndx == SHN_XINDEX as u32 {
+        shstrndx = first.link;
+    }
+
+    let table_size = shnum.checked_mul(shentsize as u64)
+        .ok_or(super::Error::UnexpectedEOF(shoff, usize::MAX))?;
+    let table = read_at(input, shoff, table_size, file_size)?;
+
+    let mut sections: Vec<Section> = table
+        .chunks_exact(shentsize as usize)
+        .map(|buf| parse_section_header(&layout, buf))
+        .collect();
+
+    let strtab = match sections.get(shstrndx as usize) {
+        Some(s) if shstrndx > 0 && s.sh_type != SHT_NOBITS => {
+            read_at(input, s.offset, s.size, file_size)?
+        }
+        _ => vec![],
+    };
+
+    for section in &mut sections {
+        section.name = match strtab.get(section.name_offset as usize..) {
+            Some(tail) => {
+                let end = tail.iter().position(|b| *b == 0).unwrap_or(tail.len());
+                String::from_utf8_lossy(&tail[..end]).into_owned()
+            }
+            None => String::new(),
+        };
+    }
+
+    Ok((layout, sections))
+}
+
+/// Apply --replace-path-prefix to the NUL-terminated strings in data.
+/// The strings in debug sections are referenced by offset, and the linker
+/// merges strings which are suffixes of other strings, so the strings must
+/// not move. If the new prefix is shorter, the path is padded with "/".
+/// A longer prefix is refused. Returns true if data was modified.
+fn replace_string_prefixes(
+    config: &options::Config,
+    input_path: &Path,
+    section_name: &str,
+    data: &mut [u8],
+) -> Result<bool> {
+
+    let mut have_mod = false;
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let end = data[offset..].iter().position(|b| *b == 0).map_or(data.len(), |n| offset + n);
+        let string = &data[offset..end];
+
+        if let Some(rule) = config.match_path_prefix(string) {
+            if rule.new.len() > rule.old.len() {
+                return Err(super::Error::Other(
+                    format!("cannot replace {:?} by longer {:?} in section {} in place",
+                            rule.old, rule.new, section_name)
+                ).into());
+            }
+
+            let mut new = rule.new.as_bytes().to_vec();
+            new.resize(rule.old.len(), b'/');
+            if new != string[..rule.old.len()] {
+                debug!("{}: section {}: {:?} → {:?}",
+                       input_path.display(), section_name,
+                       String::from_utf8_lossy(string), String::from_utf8_lossy(&new));
+                trace::event(input_path, "field",
+                             format_args!("section {}: {:?} prefix replaced",
+                                          section_name, String::from_utf8_lossy(string)));
+                data[offset..offset + rule.old.len()].copy_from_slice(&new);
+                have_mod = true;
+            }
+        }
+
+        offset = end + 1;
+    }
+
+    Ok(have_mod)
+}
+
+/// Find the changes to make in an ELF file: the .comment section is zeroed
+/// with `--elf-strip-comment`, and path prefixes are replaced in the debug
+/// strings. Returns the new contents of the modified sections, as pairs of
+/// file offset and data.
+pub fn find_patches<R: Read + Seek>(
+    config: &options::Config,
+    input_path: &Path,
+    input: &mut R,
+    file_size: u64,
+) -> Result<Vec<(u64, Vec<u8>)>> {
+
+    let (_, sections) = read_sections(input, file_size)?;
+
+    // Sections are only overwritten in place, so all offsets stay valid.
+    let mut patches = vec![];
+
+    for section in &sections {
+        let strip_comment = config.elf_strip_comment && section.name == ".comment";
+        let replace_paths = !config.path_prefix_map.is_empty()
+            && DEBUG_STRING_SECTIONS.contains(&section.name.as_str());
+
+        if !strip_comment && !replace_paths {
+            continue;
+        }
+        if section.sh_type == SHT_NOBITS || section.size == 0 {
+            continue;
+        }
+
+        if section.flags & SHF_ALLOC != 0 {
+            // The section is loaded at runtime, and changing it
+            // might require the segments to be laid out again.
+            return Err(super::Error::Other(
+                format!("section {} is part of a loadable segment, refusing to modify it",
+                        section.name)
+            ).into());
+        }
+
+        if section.flags & SHF_COMPRESSED != 0 {
+            warn!("{}: section {} is compressed, cannot replace paths",
+                  input_path.display(), section.name);
+            continue;
+        }
+
+        let mut data = read_at(input, section.offset, section.size, file_size)?;
+
+        if strip_comment {
+            if data.iter().all(|b| *b == 0) {
+                continue;
+            }
+
+            debug!("{}: section {} at 0x{:x}: {:?}",
+                   input_path.display(), section.name, section.offset,
+                   String::from_utf8_lossy(&data));
+            trace::event(input_path, "field",
+                         format_args!("section {}: {} bytes zeroed", section.name, section.size));
+            data.fill(0);
+
+        } else if !replace_string_prefixes(config, input_path, &section.name, &mut data)? {
+            continue;
+        }
+
+        patches.push((section.offset, data));
+    }
+
+    Ok(patches)
+}
+
+pub struct Elf {
+    config: Rc<options::Config>,
+}
+
+impl Elf {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+/// Object files and shared libraries: foo.o, libfoo.so, libfoo.so.1.2.3
+fn is_elf_file_name(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+        return false;
+    };
+
+    if let Some(stem) = name.strip_suffix(".o").or_else(|| name.strip_suffix(".so")) {
+        return !stem.is_empty();
+    }
+
+    match name.split_once(".so.") {
+        Some((stem, version)) => {
+            !stem.is_empty()
+                && !version.is_empty()
+                && version.split('.').all(|x| !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit()))
+        }
+        None => false,
+    }
+}
+
+impl super::Processor for Elf {
+    fn name(&self) -> &str {
+        "elf"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        // Without either option, there is nothing to do in ELF files
+        let active = self.config.elf_strip_comment || !self.config.path_prefix_map.is_empty();
+        Ok(active && is_elf_file_name(path))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+        let file_size = io.input_metadata.len();
+
+        // libfoo.so is often a linker script, foo.o may be anything
+        let mut magic = [0; 4];
+        if file_size >= magic.len() as u64 {
+            input.read_exact(&mut magic)?;
+        }
+        if magic != MAGIC {
+            debug!("{}: not an ELF file, ignoring", input_path.display());
+            return Ok(super::ProcessResult::Noop);
+        }
+
+        let patches = find_patches(&self.config, input_path, &mut input, file_size)?;
+
+        let have_mod = !patches.is_empty();
+
+        if have_mod {
+            io.open_output()?;
+            let output = io.output.as_mut().unwrap();
+
+            input.seek(SeekFrom::Start(0))?;
+            io::copy(&mut input, output)?;
+
+            for (offset, data) in patches {
+                output.seek(SeekFrom::Start(offset))?;
+                output.write_all(&data)?;
+            }
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Build a minimal object file with the sections
+    /// "", ".shstrtab", ".comment", ".bss"
+    fn make_elf(layout: Layout, comment: &[u8]) -> Vec<u8> {
+        let put = |buf: &mut Vec<u8>, val: u64, size: usize| {
+            let bytes = if layout.big_endian {
+                val.to_be_bytes()[8 - size..].to_vec()
+            } else {
+                val.to_le_bytes()[..size].to_vec()
+            };
+            buf.extend(bytes);
+        };
+        let w = if layout.class64 { 8 } else { 4 };
+
+        let shstrtab = b"\0.shstrtab\0.comment\0.bss\0";
+        let shstrtab_off = layout.header_size() as u64;
+        let comment_off = shstrtab_off + shstrtab.len() as u64;
+        let shoff = comment_off + comment.len() as u64;
+
+        let mut buf = MAGIC.to_vec();
+        buf.push(if layout.class64 { ELFCLASS64 } else { ELFCLASS32 });
+        buf.push(if layout.big_endian { ELFDATA2MSB } else { ELFDATA2LSB });
+        buf.push(1);
+        buf.resize(16, 0);
+        put(&mut buf, 1, 2);  // e_type = ET_REL
+        put(&mut buf, 62, 2); // e_machine
+        put(&mut buf, 1, 4);  // e_version
+        put(&mut buf, 0, w);  // e_entry
+        put(&mut buf, 0, w);  // e_phoff
+        put(&mut buf, shoff, w);
+        put(&mut buf, 0, 4);  // e_flags
+        put(&mut buf, layout.header_size() as u64, 2);
+        put(&mut buf, 0, 2);  // e_phentsize
+        put(&mut buf, 0, 2);  // e_phnum
+        put(&mut buf, layout.section_header_size() as u64, 2);
+        put(&mut buf, 4, 2);  // e_shnum
+        put(&mut buf, 1, 2);  // e_shstrndx
+        assert_eq!(buf.len(), layout.header_size());
+
+        buf.extend(shstrtab);
+        buf.extend(comment);
+
+        for (name, sh_type, flags, offset, size) in [
+            (0, 0, 0, 0, 0),
+            (1, 3, 0, shstrtab_off, shstrtab.len() as u64),
+            (11, 1, 0x30, comment_off, comment.len() as u64),
+            (20, SHT_NOBITS, 3, shoff, 0x1000),
+        ] {
+            put(&mut buf, name, 4);
+            put(&mut buf, sh_type as u64, 4);
+            put(&mut buf, flags, w);
+            put(&mut buf, 0, w);  // sh_addr
+            put(&mut buf, offset, w);
+            put(&mut buf, size, w);
+            buf.resize(buf.len() + layout.section_header_size() - 8 - 4 * w, 0);
+        }
+
+        buf
+    }
+
+    #[test]
+    fn test_read_sections() {
+        let comment = b"\0GCC: (GNU) 14.2.1\0";
+
+        for class64 in [false, true] {
+            for big_endian in [false, true] {
+                let layout = Layout { class64, big_endian };
+                let data = make_elf(layout, comment);
+
+                let (layout2, sections) = read_sections(&mut Cursor::new(&data), data.len() as u64).unwrap();
+                assert_eq!(layout2, layout);
+
+                let names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+                assert_eq!(names, ["", ".shstrtab", ".comment", ".bss"]);
+
+                let s = &sections[2];
+                assert_eq!(s.flags, 0x30);
+                assert_eq!(&data[s.offset as usize..(s.offset + s.size) as usize], comment);
+
+                assert_eq!(sections[3].sh_type, SHT_NOBITS);
+                assert_eq!(sections[3].size, 0x1000);
+
+                // Truncated section header table
+                assert!(read_sections(&mut Cursor::new(&data), data.len() as u64 - 1).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let data = b"\x7fELF\x03\x01\x01\0\0\0\0\0\0\0\0\0";
+        assert!(read_sections(&mut Cursor::new(&data), data.len() as u64).is_err());
+
+        let data = b"!<arch>\n\0\0\0\0\0\0\0\0";
+        assert!(read_sections(&mut Cursor::new(&data), data.len() as u64).is_err());
+
+        // Header is truncated
+        let data = b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0\0\0";
+        assert!(read_sections(&mut Cursor::new(&data), data.len() as u64).is_err());
+    }
+
+    #[test]
+    fn test_replace_string_prefixes() {
+        let mut cfg = options::Config::empty(0, false);
+        cfg.path_prefix_map = vec![options::parse_path_prefix("/build/dir=/src").unwrap()];
+
+        let mut data = b"/build/dir\0/build/dir/foo.c\0foo.c\0/other/build/dir".to_vec();
+        assert!(replace_string_prefixes(&cfg, Path::new("x"), ".debug_str", &mut data).unwrap());
+        assert_eq!(data, b"/src//////\0/src///////foo.c\0foo.c\0/other/build/dir");
+
+        // Idempotent
+        assert!(!replace_string_prefixes(&cfg, Path::new("x"), ".debug_str", &mut data).unwrap());
+
+        cfg.path_prefix_map = vec![options::PathPrefix { old: "/src".to_string(), new: "/usr/src".to_string() }];
+        assert!(replace_string_prefixes(&cfg, Path::new("x"), ".debug_str", &mut data).is_err());
+    }
+
+    #[test]
+    fn filter_elf() {
+        let mut cfg = options::Config::empty(0, false);
+        cfg.elf_strip_comment = true;
+        let h = Elf::boxed(&Rc::new(cfg));
+
+        assert!( h.filter(Path::new("/some/path/foo.o")).unwrap());
+        assert!( h.filter(Path::new("/some/path/a.o")).unwrap());
+        assert!( h.filter(Path::new("/some/path/libfoo.so")).unwrap());
+        assert!( h.filter(Path::new("/some/path/libfoo.so.1")).unwrap());
+        assert!( h.filter(Path::new("/some/path/libfoo.so.1.2.30")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/libfoo.so.1.a")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/libfoo.so.")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/libfoo.so.1..2")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/.so")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/foo.oo")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/so")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+
+        // Without --elf-strip-comment or --replace-path-prefix
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Elf::boxed(&cfg);
+        assert!(!h.filter(Path::new("/some/path/foo.o")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/libfoo.so")).unwrap());
+    }
+}
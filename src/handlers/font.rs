@@ -0,0 +1,232 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC_TRUETYPE: &[u8] = b"\x00\x01\x00\x00";
+const MAGIC_OPENTYPE: &[u8] = b"OTTO";
+
+// https://learn.microsoft.com/en-us/typography/opentype/spec/otff
+const OFFSET_TABLE_LENGTH: usize = 12;
+const TABLE_RECORD_LENGTH: usize = 16;
+
+// https://learn.microsoft.com/en-us/typography/opentype/spec/head
+const HEAD_LENGTH: usize = 54;
+const HEAD_CHECKSUM_ADJUSTMENT: usize = 8;
+const HEAD_MAGIC_NUMBER: usize = 12;
+const HEAD_CREATED: usize = 20;
+const HEAD_MODIFIED: usize = 28;
+const HEAD_MAGIC: u32 = 0x5f0f3cf5;
+
+const CHECKSUM_MAGIC: u32 = 0xb1b0afba;
+
+/// The difference between 1904-01-01, which is the epoch used in
+/// LONGDATETIME fields, and 1970-01-01
+const LONGDATETIME_OFFSET: i64 = 2082844800;
+
+fn be(data: &[u8], offset: usize, size: usize) -> Result<u64> {
+    let bytes = data.get(offset .. offset + size)
+        .ok_or(super::Error::UnexpectedEOF(offset as u64, size))?;
+    Ok(bytes.iter().fold(0, |acc, b| acc << 8 | *b as u64))
+}
+
+/// The sum of big-endian 32-bit words, with the data padded with zeros
+/// to a multiple of 4 bytes.
+pub fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(word)
+        })
+        .fold(0, u32::wrapping_add)
+}
+
+/// A table record in the table directory
+#[derive(Debug, PartialEq)]
+pub struct Table {
+    pub tag: [u8; 4],
+    pub checksum: u32,
+    pub offset: usize,
+    pub length: usize,
+    /// The position of the record itself
+    record: usize,
+}
+
+pub fn read_tables(data: &[u8]) -> Result<Vec<Table>> {
+    let magic = data.get(..4)
+        .ok_or(super::Error::UnexpectedEOF(0, OFFSET_TABLE_LENGTH))?;
+    if magic != MAGIC_TRUETYPE && magic != MAGIC_OPENTYPE {
+        return Err(super::Error::BadMagic(0, magic.to_vec(), MAGIC_TRUETYPE).into());
+    }
+
+    let num_tables = be(data, 4, 2)? as usize;
+
+    let mut tables = vec![];
+    for n in 0..num_tables {
+        let record = OFFSET_TABLE_LENGTH + n * TABLE_RECORD_LENGTH;
+
+        let table = Table {
+            tag: data.get(record .. record + 4)
+                .ok_or(super::Error::UnexpectedEOF(record as u64, TABLE_RECORD_LENGTH))?
+                .try_into().unwrap(),
+            checksum: be(data, record + 4, 4)? as u32,
+            offset: be(data, record + 8, 4)? as usize,
+            length: be(data, record + 12, 4)? as usize,
+            record,
+        };
+
+        if table.offset.saturating_add(table.length) > data.len() {
+            return Err(super::Error::Other(
+                format!("table {} at 0x{:x}+0x{:x} is outside of the file",
+                        String::from_utf8_lossy(&table.tag), table.offset, table.length)
+            ).into());
+        }
+
+        tables.push(table);
+    }
+
+    Ok(tables)
+}
+
+pub struct Font {
+    config: Rc<options::Config>,
+}
+
+impl Font {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+impl super::Processor for Font {
+    fn name(&self) -> &str {
+        "font"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "ttf" || x == "otf"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+
+        let tables = read_tables(&data)?;
+
+        let Some(head) = tables.iter().find(|t| &t.tag == b"head") else {
+            debug!("{}: no head table", input_path.display());
+            return io.finalize(false);
+        };
+
+        if head.length < HEAD_LENGTH {
+            return Err(super::Error::UnexpectedEOF(head.offset as u64, HEAD_LENGTH).into());
+        }
+        let magic = be(&data, head.offset + HEAD_MAGIC_NUMBER, 4)? as u32;
+        if magic != HEAD_MAGIC {
+            return Err(super::Error::Other(
+                format!("head table has bad magic number 0x{magic:08x}")
+            ).into());
+        }
+
+        let mut have_mod = false;
+
+        for (field, offset) in [("created", HEAD_CREATED), ("modified", HEAD_MODIFIED)] {
+            let pos = head.offset + offset;
+            let value = be(&data, pos, 8)? as i64;
+
+            // Values which cannot be converted are left as they are
+            let Some(new_value) = value.checked_sub(LONGDATETIME_OFFSET)
+                .and_then(|v| self.config.clamp_mtime(v).checked_add(LONGDATETIME_OFFSET)) else {
+                debug!("{}: head.{} is out of range: {}", input_path.display(), field, value);
+                continue;
+            };
+
+            if new_value != value {
+                trace::event(input_path, "field",
+                             format_args!("head.{}: {} → {}", field,
+                                          value - LONGDATETIME_OFFSET,
+                                          new_value - LONGDATETIME_OFFSET));
+                data[pos .. pos + 8].copy_from_slice(&new_value.to_be_bytes());
+                have_mod = true;
+            }
+        }
+
+        if have_mod {
+            // The checksum of the head table is calculated with
+            // checksumAdjustment set to 0, and so is the checksum
+            // of the whole font.
+            let adjustment = head.offset + HEAD_CHECKSUM_ADJUSTMENT;
+            data[adjustment .. adjustment + 4].fill(0);
+
+            let table_checksum = checksum(&data[head.offset .. head.offset + head.length]);
+            data[head.record + 4 .. head.record + 8].copy_from_slice(&table_checksum.to_be_bytes());
+
+            let font_checksum = CHECKSUM_MAGIC.wrapping_sub(checksum(&data));
+            data[adjustment .. adjustment + 4].copy_from_slice(&font_checksum.to_be_bytes());
+
+            io.open_output()?;
+            io.output.as_mut().unwrap().write_all(&data)?;
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"\x00\x00\x00\x01\x00\x00\x00\x02"), 3);
+        // Padded with zeros
+        assert_eq!(checksum(b"\x00\x00\x00\x01\x01"), 0x01000001);
+        // Overflow wraps around
+        assert_eq!(checksum(b"\xff\xff\xff\xff\x00\x00\x00\x02"), 1);
+    }
+
+    #[test]
+    fn test_read_tables() {
+        let mut data = b"OTTO\x00\x01\x00\x10\x00\x00\x00\x00".to_vec();
+        data.extend(b"head\x12\x34\x56\x78\x00\x00\x00\x1c\x00\x00\x00\x04");
+        data.extend(b"abcd");
+
+        let tables = read_tables(&data).unwrap();
+        assert_eq!(tables, vec![
+            Table { tag: *b"head", checksum: 0x12345678, offset: 0x1c, length: 4, record: 12 },
+        ]);
+
+        // Table outside of the file
+        data[27] = 5;
+        assert!(read_tables(&data).is_err());
+
+        // Truncated directory
+        assert!(read_tables(&data[..20]).is_err());
+
+        // Font collections are not supported
+        assert!(read_tables(b"ttcf\x00\x01\x00\x00").is_err());
+    }
+
+    #[test]
+    fn filter_font() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Font::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/DejaVuSans.ttf")).unwrap());
+        assert!( h.filter(Path::new("/some/path/font.otf")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/font.woff2")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/ttf")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+}
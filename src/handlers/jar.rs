@@ -34,7 +34,7 @@ impl super::Processor for Jar {
 
     fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
         let mut have_mod = false;
-        let (mut io, input) = InputOutputHelper::open(input_path, self.config.check)?;
+        let (mut io, input) = InputOutputHelper::open(input_path, &self.config)?;
         let mut input = zip::ZipArchive::new(input)?;
 
         io.open_output()?;
@@ -87,10 +87,12 @@ impl super::Processor for Jar {
                                       e);
                             }
                             Ok(mtime) => {
+                                let clamp = self.config.clamp_mtime(mtime.unix_timestamp())
+                                    != mtime.unix_timestamp();
                                 debug!("File {}: {}\n  {:?} {:?} {}", i, file.name(), mtime, epoch,
-                                       mtime > epoch);
+                                       clamp);
 
-                                if mtime > epoch {
+                                if clamp {
                                     let header_offset = file.header_start();
 
                                     debug!("{}: {}: seeking to 0x{:08x} (local file header)",
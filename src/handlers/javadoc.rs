@@ -21,53 +21,56 @@ impl Javadoc {
     pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
         Box::new(Self { config: config.clone() })
     }
+}
 
-    fn process_line(&self, line: &str) -> Result<Option<String>> {
-        // javadoc files have the date in two places in the header:
-        //   <!-- Generated by javadoc (21) on Sat Mar 02 16:07:41 UTC 2024 -->
-        //   <meta name="dc.created" content="2024-03-02">
-        //
-        // We strip the javadoc version and date in the first line, based on the
-        // assumption that this is just a freeform comment and the date is not
-        // parsed by anything. The information that this was generated by javadoc is
-        // retained to that is useful information (and because people sometimes
-        // modify generated files by hand, wasting their time).
-        //
-        // In the second line, we parse the date as %Y-%m-%d, compare is with
-        // $SOURCE_DATE_EPOCH, and replace if newer. This means that we'll not
-        // rewrite this line in pages that were generated a long time ago.
-
-        let re = Regex::new(r"(.*<!-- Generated by javadoc) .+ (-->.*)")?;
-        if let Some(caps) = re.captures(line) {
-            return Ok(Some(format!("{} {}", &caps[1], &caps[2])));
-        }
+/// Return the normalized version of line, or None if nothing needs
+/// to change. This is also used for HTML in other files, see zip.rs.
+pub fn process_line(config: &options::Config, line: &str) -> Result<Option<String>> {
+    // javadoc files have the date in two places in the header:
+    //   <!-- Generated by javadoc (21) on Sat Mar 02 16:07:41 UTC 2024 -->
+    //   <meta name="dc.created" content="2024-03-02">
+    //
+    // We strip the javadoc version and date in the first line, based on the
+    // assumption that this is just a freeform comment and the date is not
+    // parsed by anything. The information that this was generated by javadoc is
+    // retained to that is useful information (and because people sometimes
+    // modify generated files by hand, wasting their time).
+    //
+    // In the second line, we parse the date as %Y-%m-%d, compare is with
+    // $SOURCE_DATE_EPOCH, and replace if newer. This means that we'll not
+    // rewrite this line in pages that were generated a long time ago.
+
+    let re = Regex::new(r"(.*<!-- Generated by javadoc) .+ (-->.*)")?;
+    if let Some(caps) = re.captures(line) {
+        return Ok(Some(format!("{} {}", &caps[1], &caps[2])));
+    }
 
-        let epoch = self.config.source_date_epoch
-            .map(|v| chrono::DateTime::from_timestamp(v, 0).unwrap());
+    let epoch = config.source_date_epoch
+        .map(|v| chrono::DateTime::from_timestamp(v, 0).unwrap());
 
-        if let Some(epoch) = epoch {
-            let re = RegexBuilder::new(r#"<(meta name="(date|dc\.created)" content=)"([^"]+)">"#)
-                .case_insensitive(true)
-                .build()?;
+    if let Some(epoch) = epoch {
+        let re = RegexBuilder::new(r#"<(meta name="(date|dc\.created)" content=)"([^"]+)">"#)
+            .case_insensitive(true)
+            .build()?;
 
-            if let Some(caps) = re.captures(line) {
-                match chrono::NaiveDate::parse_from_str(&caps[3], "%Y-%m-%d") {
-                    Err(_) => {
-                        debug!("Failed to parse naive date: {:?}", &caps[3]);
-                    }
-                    Ok(date) => {
-                        debug!("Matched meta {} date {} → {:?}", &caps[2], &caps[3], date);
-                        if epoch.date_naive() < date {
-                            let ts = epoch.format("%Y-%m-%d");
-                            return Ok(Some(format!("<{}\"{}\">", &caps[1], ts)));
-                        }
+        if let Some(caps) = re.captures(line) {
+            match chrono::NaiveDate::parse_from_str(&caps[3], "%Y-%m-%d") {
+                Err(_) => {
+                    debug!("Failed to parse naive date: {:?}", &caps[3]);
+                }
+                Ok(date) => {
+                    debug!("Matched meta {} date {} → {:?}", &caps[2], &caps[3], date);
+                    let ts = date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp();
+                    if config.clamp_mtime(ts) != ts {
+                        let ts = epoch.format("%Y-%m-%d");
+                        return Ok(Some(format!("<{}\"{}\">", &caps[1], ts)));
                     }
                 }
             }
         }
-
-        Ok(None)
     }
+
+    Ok(None)
 }
 
 impl super::Processor for Javadoc {
@@ -76,7 +79,7 @@ impl super::Processor for Javadoc {
     }
 
     fn filter(&self, path: &Path) -> Result<bool> {
-        Ok(path.extension().is_some_and(|x| x == "html")
+        Ok(path.extension().is_some_and(|x| x == "html" || x == "htm")
            // && path.to_str().is_some_and(|x| x.contains("/usr/share/javadoc/"))
         )
     }
@@ -85,7 +88,7 @@ impl super::Processor for Javadoc {
         let mut have_mod = false;
         let mut after_header = false;
 
-        let (mut io, input) = InputOutputHelper::open(input_path, self.config.check)?;
+        let (mut io, input) = InputOutputHelper::open(input_path, &self.config)?;
 
         io.open_output()?;
         let mut output = BufWriter::new(io.output.as_mut().unwrap());
@@ -110,7 +113,7 @@ impl super::Processor for Javadoc {
 
             num += 1;
 
-            let line2 = if !after_header { self.process_line(&line)? } else { None };
+            let line2 = if !after_header { process_line(&self.config, &line)? } else { None };
 
             if line2.is_some() && !have_mod {
                 debug!("{}:{}: found first line to replace: {:?}", input_path.display(), num, line);
@@ -152,6 +155,7 @@ mod tests {
         let h = Javadoc::boxed(&cfg);
 
         assert!( h.filter(Path::new("/some/path/page.html")).unwrap());
+        assert!( h.filter(Path::new("/some/path/page.htm")).unwrap());
         assert!(!h.filter(Path::new("/some/path/page.htmll")).unwrap());
         assert!(!h.filter(Path::new("/some/path/page.html.jpg")).unwrap());
         assert!(!h.filter(Path::new("/some/path/page")).unwrap());
@@ -162,9 +166,8 @@ mod tests {
 
     #[test]
     fn test_process_line() {
-        let config = Rc::new(options::Config::empty(1704106800, false));
-        let h = Javadoc { config };
-        let plu = |s| h.process_line(s).unwrap();
+        let config = options::Config::empty(1704106800, false);
+        let plu = |s| process_line(&config, s).unwrap();
 
         assert_eq!(plu("<!-- Generated by javadoc (21) on Sat Mar 02 16:07:41 UTC 2024 -->").unwrap(),
                    "<!-- Generated by javadoc -->");
@@ -172,6 +175,11 @@ mod tests {
         // If we're running on an already processed file, don't report this as a match
         assert!(plu("<!-- Generated by javadoc -->").is_none());
 
+        // Other generators are not touched
+        assert!(plu("<!-- Generated by javadocx 1.0 on Sat Mar 02 16:07:41 UTC 2024 -->").is_none());
+        assert!(plu("<!-- Generated by Doxygen 1.10.0 on Sat Mar 02 2024 -->").is_none());
+        assert!(plu("<p>Generated by javadoc (21) on Sat Mar 02 16:07:41 UTC 2024</p>").is_none());
+
         assert_eq!(plu(r#"<meta name="dc.created" content="2024-03-02">"#).unwrap(),
                    r#"<meta name="dc.created" content="2024-01-01">"#);
 
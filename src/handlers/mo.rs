@@ -0,0 +1,299 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+
+// https://www.gnu.org/software/gettext/manual/html_node/MO-Files.html
+//
+//         byte
+//              +------------------------------------------+
+//           0  | magic number = 0x950412de                |
+//           4  | file format revision = 0                 |
+//           8  | number of strings                        |  == N
+//          12  | offset of table with original strings    |  == O
+//          16  | offset of table with translation strings |  == T
+//          20  | size of hashing table                    |  == S
+//          24  | offset of hashing table                  |  == H
+//              .                                          .
+//              .    (possibly more entries later)         .
+//
+// Each string table has N entries of (length, offset) pairs.
+// The metadata is stored as the translation of the empty msgid.
+
+const MAGIC: u32 = 0x950412de;
+const MAGIC_SWAPPED: u32 = 0xde120495;
+const MAGIC_BYTES: &[u8] = &[0xde, 0x12, 0x04, 0x95];
+
+const DATE_FIELDS: &[&str] = &["POT-Creation-Date: ", "PO-Revision-Date: "];
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M%z";
+
+pub struct Mo {
+    config: Rc<options::Config>,
+}
+
+impl Mo {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+
+    fn process_header(&self, header: &str) -> Option<String> {
+        let epoch = chrono::DateTime::from_timestamp(self.config.source_date_epoch?, 0)?;
+
+        let mut have_mod = false;
+        let mut lines = vec![];
+
+        for line in header.split_inclusive('\n') {
+            let (content, newline) = match line.strip_suffix('\n') {
+                Some(content) => (content, "\n"),
+                None => (line, ""),
+            };
+
+            let field = DATE_FIELDS.iter().find(|f| content.starts_with(*f));
+            if let Some(field) = field {
+                let value = &content[field.len()..];
+
+                match chrono::DateTime::parse_from_str(value, DATE_FORMAT) {
+                    Err(_) => {
+                        debug!("Failed to parse date: {:?}", value);
+                    }
+                    Ok(date) => {
+                        debug!("Matched {}{:?} → {:?}", field, value, date);
+                        if self.config.clamp_mtime(date.timestamp()) != date.timestamp() {
+                            lines.push(format!("{}{}{}", field, epoch.format("%Y-%m-%d %H:%M+0000"), newline));
+                            have_mod = true;
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            lines.push(line.to_string());
+        }
+
+        if have_mod { Some(lines.concat()) } else { None }
+    }
+}
+
+struct Catalog {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Catalog {
+    fn read_u32(&self, offset: usize) -> Result<u32> {
+        let bytes = self.data.get(offset .. offset + 4)
+            .ok_or(super::Error::UnexpectedEOF(offset as u64, 4))?;
+        let bytes = bytes.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        self.data[offset .. offset + 4].copy_from_slice(&bytes);
+    }
+
+    /// Return the list of offsets at which (length, offset) pairs are stored.
+    fn string_entries(&self) -> Result<Vec<usize>> {
+        let count = self.read_u32(8)? as usize;
+        let mut entries = vec![];
+
+        for table in [self.read_u32(12)?, self.read_u32(16)?] {
+            for i in 0..count {
+                let entry = table as usize + 8 * i;
+                // Verify that the entry and the string are within the file
+                let length = self.read_u32(entry)? as usize;
+                let offset = self.read_u32(entry + 4)? as usize;
+                if offset + length >= self.data.len() {
+                    return Err(super::Error::UnexpectedEOF(offset as u64, length + 1).into());
+                }
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn string(&self, entry: usize) -> Result<&[u8]> {
+        let length = self.read_u32(entry)? as usize;
+        let offset = self.read_u32(entry + 4)? as usize;
+        Ok(&self.data[offset .. offset + length])
+    }
+
+    /// Replace the string at given entry, moving all data after it and
+    /// adjusting any offsets that point at that data.
+    fn replace_string(&mut self, entry: usize, new: &[u8]) -> Result<()> {
+        let entries = self.string_entries()?;
+
+        let length = self.read_u32(entry)? as usize;
+        let offset = self.read_u32(entry + 4)? as usize;
+
+        let delta = new.len() as i64 - length as i64;
+        // Keep the data after the string aligned to 4 bytes,
+        // the tables might be there and they should stay aligned.
+        let shift = (delta + 3).div_euclid(4) * 4;
+        let padding = (shift - delta) as usize;
+
+        if shift != 0 {
+            let revision = self.read_u32(4)?;
+            if revision & 0xFFFF > 0 && self.read_u32(36)? > 0 {
+                return Err(super::Error::Other(
+                    "cannot resize strings in catalog with system-dependent strings".to_string()
+                ).into());
+            }
+        }
+
+        let mut data = Vec::with_capacity(self.data.len() + padding + new.len());
+        data.extend_from_slice(&self.data[..offset]);
+        data.extend_from_slice(new);
+        data.push(0);
+        data.resize(data.len() + padding, 0);
+        data.extend_from_slice(&self.data[offset + length + 1..]);
+        self.data = data;
+
+        let moved = |x: u32| x as usize > offset;
+        let adjust = |x: u32| (x as i64 + shift) as u32;
+
+        if shift != 0 {
+            for header_field in [12, 16, 24] {
+                let val = self.read_u32(header_field)?;
+                if moved(val) {
+                    self.write_u32(header_field, adjust(val));
+                }
+            }
+        }
+
+        // Table positions might have moved too
+        let entries: Vec<usize> = entries
+            .into_iter()
+            .map(|e| if moved(e as u32) { adjust(e as u32) as usize } else { e })
+            .collect();
+        let entry = if moved(entry as u32) { adjust(entry as u32) as usize } else { entry };
+
+        if shift != 0 {
+            for e in entries {
+                let val = self.read_u32(e + 4)?;
+                if moved(val) {
+                    self.write_u32(e + 4, adjust(val));
+                }
+            }
+        }
+
+        self.write_u32(entry, new.len() as u32);
+        Ok(())
+    }
+}
+
+impl super::Processor for Mo {
+    fn name(&self) -> &str {
+        "mo"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "mo"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+
+        let mut catalog = Catalog { data, big_endian: false };
+        let magic = catalog.read_u32(0)?;
+        if magic == MAGIC_SWAPPED {
+            catalog.big_endian = true;
+        } else if magic != MAGIC {
+            return Err(super::Error::BadMagic(0, catalog.data[..4].to_vec(), MAGIC_BYTES).into());
+        }
+
+        let revision = catalog.read_u32(4)?;
+        if revision >> 16 > 1 {
+            return Err(super::Error::Other(
+                format!("unsupported file format revision 0x{revision:08x}")
+            ).into());
+        }
+
+        let count = catalog.read_u32(8)? as usize;
+        debug!("{}: {}-endian catalog with {} strings",
+               input_path.display(), if catalog.big_endian { "big" } else { "little" }, count);
+
+        let entries = catalog.string_entries()?;
+        let (originals, translations) = entries.split_at(count);
+
+        // Find the metadata entry, i.e. the translation of the empty msgid.
+        let mut header_entry = None;
+        for (orig, trans) in originals.iter().zip(translations) {
+            if catalog.string(*orig)?.is_empty() {
+                header_entry = Some(*trans);
+                break;
+            }
+        }
+
+        let Some(header_entry) = header_entry else {
+            debug!("{}: no metadata entry", input_path.display());
+            return Ok(super::ProcessResult::Noop);
+        };
+
+        let header = catalog.string(header_entry)?;
+        let Ok(header) = std::str::from_utf8(header) else {
+            debug!("{}: metadata entry is not valid UTF-8, ignoring", input_path.display());
+            return Ok(super::ProcessResult::Noop);
+        };
+
+        let Some(new_header) = self.process_header(header) else {
+            return Ok(super::ProcessResult::Noop);
+        };
+
+        catalog.replace_string(header_entry, new_header.as_bytes())?;
+
+        io.open_output()?;
+        io.output.as_mut().unwrap().write_all(&catalog.data)?;
+
+        io.finalize(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_mo() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Mo::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/usr/share/locale/pl/LC_MESSAGES/foo.mo")).unwrap());
+        assert!(!h.filter(Path::new("/usr/share/locale/pl/LC_MESSAGES/foo.po")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/foo.mo.gz")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/mo")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+
+    #[test]
+    fn test_process_header() {
+        let config = Rc::new(options::Config::empty(1704106800, false));
+        let h = Mo { config };
+
+        assert_eq!(h.process_header("Project-Id-Version: foo\nPO-Revision-Date: 2024-06-09 08:15+0200\n").unwrap(),
+                   "Project-Id-Version: foo\nPO-Revision-Date: 2024-01-01 11:00+0000\n");
+        assert_eq!(h.process_header("POT-Creation-Date: 2024-6-9 8:15+0000").unwrap(),
+                   "POT-Creation-Date: 2024-01-01 11:00+0000");
+
+        // Too old
+        assert!(h.process_header("PO-Revision-Date: 2023-06-09 08:15+0200\n").is_none());
+        // Template placeholder
+        assert!(h.process_header("PO-Revision-Date: YEAR-MO-DA HO:MI+ZONE\n").is_none());
+        assert!(h.process_header("Project-Id-Version: foo\n").is_none());
+        assert!(h.process_header("").is_none());
+
+        let config = Rc::new(options::Config { source_date_epoch: None, ..options::Config::empty(0, false) });
+        let h = Mo { config };
+        assert!(h.process_header("PO-Revision-Date: 2024-06-09 08:15+0200\n").is_none());
+    }
+}
@@ -1,28 +1,51 @@
 /* SPDX-License-Identifier: GPL-3.0-or-later */
 
 pub mod ar;
+pub mod classfile;
+pub mod cpio;
+pub mod elf;
+pub mod font;
 pub mod jar;
 pub mod javadoc;
+pub mod mo;
+pub mod pe;
+pub mod png;
 pub mod pyc;
+pub mod rlib;
+pub mod squashfs;
+pub mod sqlite;
+pub mod zip;
 
 use anyhow::{bail, Context, Result};
-use log::{debug, info, warn};
+use log::{debug, info, trace, warn};
+use nix::errno;
 use serde::{Serialize, Deserialize};
 use std::ascii::escape_default;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
 use std::fs;
 use std::fs::{File, Metadata};
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, Read, Write};
 use std::io::Seek;
 use std::os::linux::fs::MetadataExt as _;
 use std::os::unix::fs as unix_fs;
 use std::os::unix::fs::MetadataExt as _;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 use thiserror::Error;
+use xattr::FileExt as _;
 
+use crate::diff;
 use crate::options;
+use crate::progress;
+use crate::trace;
+
+/// Extended attributes that are copied from the original file when it is replaced.
+const XATTR_PREFIXES: &[&str] = &["security.", "user."];
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -62,6 +85,7 @@ impl ProcessResult {
         match result {
             Err(err) => {
                 warn!("{}: failed to process: {}", input_path.display(), err);
+                trace::event(input_path, "failed", format_args!("{}", err));
 
                 if err.downcast_ref::<Error>().is_some() {
                     ProcessResult::BadFormat
@@ -133,6 +157,21 @@ pub struct Stats {
 
     /// Various errors other than bad format above.
     pub errors: u64,
+
+    /// Regular files that were not matched by any handler.
+    pub unhandled: u64,
+
+    /// Counts of the above by file extension. Only filled in
+    /// with --report-unhandled.
+    pub unhandled_extensions: BTreeMap<String, u64>,
+
+    /// Files that were matched by a handler, but skipped because
+    /// they are larger than --max-file-size.
+    pub too_large: u64,
+
+    /// Files that were not looked at: symlinks that are not followed
+    /// and special files.
+    pub ignored: u64,
 }
 
 impl Stats {
@@ -159,42 +198,191 @@ impl Stats {
         self.inodes_rewritten += other.inodes_rewritten;
         self.misunderstood += other.misunderstood;
         self.errors += other.errors;
+        self.unhandled += other.unhandled;
+        for (ext, count) in &other.unhandled_extensions {
+            *self.unhandled_extensions.entry(ext.clone()).or_default() += count;
+        }
+        self.too_large += other.too_large;
+        self.ignored += other.ignored;
+    }
+
+    /// Files which were seen, but not normalized, without that being an
+    /// error. With --check, files in an unsupported format are an
+    /// error, so they are not counted here.
+    pub fn skipped(&self, check: bool) -> u64 {
+        self.ignored + self.unhandled + self.too_large + if check { 0 } else { self.misunderstood }
+    }
+
+    /// The summary printed at the end of the run
+    pub fn summary_line(&self, check: bool) -> String {
+        format!("{} files, {} {}, {} errors, {} skipped",
+                self.files,
+                self.inodes_replaced + self.inodes_rewritten,
+                if check { "not normalized" } else { "modified" },
+                self.errors + if check { self.misunderstood } else { 0 },
+                self.skipped(check))
     }
 
     pub fn summarize(&self) {
-        info!("Scanned {} directories and {} files,
+        debug!("Scanned {} directories and {} files,
                processed {} inodes,
                {} modified ({} replaced + {} rewritten),
-               {} unsupported format, {} errors",
+               {} unsupported format, {} errors,
+               {} not matched by any handler, {} skipped as too large",
               self.directories, self.files,
               self.inodes_processed,
               self.inodes_replaced + self.inodes_rewritten,
               self.inodes_replaced, self.inodes_rewritten,
-              self.misunderstood, self.errors);
+              self.misunderstood, self.errors,
+              self.unhandled, self.too_large);
+    }
+
+    pub fn report_unhandled(&self) {
+        if self.unhandled_extensions.is_empty() {
+            info!("All files were matched by some handler");
+            return;
+        }
+
+        info!("Files not matched by any handler, by extension:");
+        for (ext, count) in &self.unhandled_extensions {
+            info!("  {ext}: {count}");
+        }
     }
 }
 
 pub type HandlerBoxed = fn(&Rc<options::Config>) -> Box<dyn Processor>;
 
-pub const HANDLERS: &[(&str, HandlerBoxed)] = &[
-    ("ar",      ar::Ar::boxed),
-    ("jar",     jar::Jar::boxed),
-    ("javadoc", javadoc::Javadoc::boxed),
-    ("pyc",     pyc::Pyc::boxed),
+/// Return true if the beginning of a file, up to SNIFF_LENGTH bytes,
+/// looks like something the handler can process.
+pub type HandlerSniff = fn(&[u8]) -> bool;
+
+/// The number of bytes passed to HandlerSniff functions
+pub const SNIFF_LENGTH: usize = 16;
+
+#[derive(Clone, Copy)]
+pub struct Handler {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub boxed: HandlerBoxed,
+    /// Used with --content-detect for files not accepted by any filter
+    pub sniff: Option<HandlerSniff>,
+}
+
+const HANDLERS: &[Handler] = &[
+    Handler {
+        name: "ar",
+        description: "Static libraries and other ar archives (.a)",
+        boxed: ar::Ar::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "classfile",
+        description: "Java class files (.class)",
+        boxed: classfile::Classfile::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "cpio",
+        description: "cpio archives in the \"newc\" format (.cpio)",
+        boxed: cpio::Cpio::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "elf",
+        description: "ELF object files and shared libraries (.o, .so, .so.N)",
+        boxed: elf::Elf::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "font",
+        description: "TrueType and OpenType fonts (.ttf, .otf)",
+        boxed: font::Font::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "jar",
+        description: "Java archives (.jar)",
+        boxed: jar::Jar::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "javadoc",
+        description: "HTML documentation generated by Javadoc (.html)",
+        boxed: javadoc::Javadoc::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "mo",
+        description: "Gettext message catalogs (.mo)",
+        boxed: mo::Mo::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "pe",
+        description: "Windows PE executables (.exe, .dll, .efi)",
+        boxed: pe::Pe::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "png",
+        description: "PNG images (.png)",
+        boxed: png::Png::boxed,
+        sniff: Some(png::sniff),
+    },
+    Handler {
+        name: "pyc",
+        description: "Python compiled bytecode (.pyc)",
+        boxed: pyc::Pyc::boxed,
+        sniff: Some(pyc::sniff),
+    },
+    Handler {
+        name: "rlib",
+        description: "Rust static libraries (.rlib)",
+        boxed: rlib::Rlib::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "squashfs",
+        description: "SquashFS images (.squashfs, .sqfs, .sqsh)",
+        boxed: squashfs::Squashfs::boxed,
+        sniff: None,
+    },
+    Handler {
+        name: "sqlite",
+        description: "SQLite databases (.sqlite, .sqlite3)",
+        boxed: sqlite::Sqlite::boxed,
+        sniff: Some(sqlite::sniff),
+    },
+    Handler {
+        name: "zip",
+        description: "Zip archives and Python wheels (.zip, .whl)",
+        boxed: zip::Zip::boxed,
+        sniff: Some(zip::sniff),
+    },
 ];
 
+// Sets of handlers are passed around as bitmasks
+const _: () = assert!(HANDLERS.len() <= u32::BITS as usize);
+
+/// Return all known handlers, in the order in which they are tried.
+/// A file is processed by the first handler whose filter matches.
+pub fn registry() -> Vec<Handler> {
+    HANDLERS.to_vec()
+}
+
 pub fn handler_names() -> Vec<&'static str> {
-    HANDLERS.iter()
-        .map(|(name, _)| *name)
+    registry()
+        .iter()
+        .map(|h| h.name)
         .collect()
 }
 
 pub fn make_handlers(config: &Rc<options::Config>) -> Result<Vec<Box<dyn Processor>>> {
     let mut handlers: Vec<Box<dyn Processor>> = vec![];
 
-    for (name, func) in HANDLERS {
-        if config.handler_names.contains(name) {
-            let mut handler = func(config);
+    for h in registry() {
+        if config.handler_names.contains(&h.name) {
+            let mut handler = (h.boxed)(config);
             match handler.initialize() {
                 Err(e) => {
                     if config.strict_handlers {
@@ -213,7 +401,7 @@ pub fn make_handlers(config: &Rc<options::Config>) -> Result<Vec<Box<dyn Process
     Ok(handlers)
 }
 
-pub fn inodes_seen() -> HashMap<u64, u8> {
+pub fn inodes_seen() -> HashMap<u64, u32> {
     HashMap::new()
 }
 
@@ -223,77 +411,281 @@ pub fn do_normal_work(config: &Rc<options::Config>) -> Result<Stats> {
     let mut total = Stats::new();
 
     for input_path in &config.inputs {
-        let stats = process_file_or_dir(&handlers, &mut inodes_seen, input_path, None)?;
+        let stats = process_file_or_dir(config, &handlers, &mut inodes_seen, input_path, None)?;
         total.add(&stats);
     }
 
     Ok(total)
 }
 
-pub type ProcessWrapper<'a> = Option<&'a dyn Fn(u8, &Path) -> Result<()>>;
+/// With --content-detect, return the index of the first handler whose
+/// sniff function recognizes the beginning of the file, if any.
+fn match_content(
+    config: &options::Config,
+    handlers: &[Box<dyn Processor>],
+    already_seen: u32,
+    input_path: &Path,
+) -> Result<Option<usize>> {
+
+    if !config.content_detect {
+        return Ok(None);
+    }
 
-fn process_file(
+    let mut buf = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(input_path)?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut buf)?;
+
+    for h in HANDLERS {
+        let Some(sniff) = h.sniff else {
+            continue;
+        };
+        let Some(n_processor) = handlers.iter().position(|p| p.name() == h.name) else {
+            continue;
+        };
+        if already_seen & (1 << n_processor) > 0 {
+            continue;
+        }
+
+        if sniff(&buf) {
+            debug!("{}: matched by contents for handler {}", input_path.display(), h.name);
+            trace::event(input_path, "matched", format_args!("handler {} by contents", h.name));
+            return Ok(Some(n_processor));
+        }
+    }
+
+    Ok(None)
+}
+
+pub type ProcessWrapper<'a> = Option<&'a dyn Fn(u32, &Path) -> Result<()>>;
+
+/// Return the index of the handler selected by a --magic-rule, if any.
+fn match_magic_rules(
+    config: &options::Config,
     handlers: &[Box<dyn Processor>],
-    already_seen: &mut u8,
+    already_seen: u32,
     input_path: &Path,
-    process_wrapper: ProcessWrapper,
-) -> Result<ProcessResult> {
+) -> Result<Option<usize>> {
 
-    // When processing locally, this says whether modifications have
-    // been made. When processing remotely, we will send the result
-    // separately after asynchronous processing is finished.
-    let mut entry_mod = ProcessResult::Ignored;
+    if config.magic_rules.is_empty() {
+        return Ok(None);
+    }
 
-    let mut selected_handlers = 0;
+    let mut file = File::open(input_path)?;
+
+    for rule in &config.magic_rules {
+        let Some(n_processor) = handlers.iter().position(|h| h.name() == rule.handler) else {
+            continue;
+        };
+        if already_seen & (1 << n_processor) > 0 {
+            continue;
+        }
+
+        let mut buf = vec![0; rule.magic.len()];
+        file.seek(io::SeekFrom::Start(rule.offset))?;
+        match file.read_exact(&mut buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => { continue; }
+            Err(e) => { return Err(e.into()); }
+            Ok(()) => {}
+        }
+
+        if buf == rule.magic {
+            debug!("{}: matched by magic rule {}", input_path.display(), rule);
+            trace::event(input_path, "matched", format_args!("handler {} by magic rule {}", rule.handler, rule));
+            return Ok(Some(n_processor));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Return the index of the handler which should process input_path:
+/// the first handler that accepts the file name, the handler selected
+/// by a --magic-rule, or with --content-detect, the first handler that
+/// recognizes the contents. Handlers in already_seen are not considered.
+pub fn select_handler(
+    config: &options::Config,
+    handlers: &[Box<dyn Processor>],
+    already_seen: u32,
+    input_path: &Path,
+) -> Result<Option<usize>> {
 
     for (n_processor, processor) in handlers.iter().enumerate() {
         // The same inode can be linked under multiple names
         // with different extensions. Thus, we check if the
         // given processor already handled this file.
-        if *already_seen & (1 << n_processor) > 0 {
+        if already_seen & (1 << n_processor) > 0 {
             debug!("{}: already seen by {} handler",
                    input_path.display(), processor.name());
+            trace::event(input_path, "skipped", format_args!("already seen by handler {}", processor.name()));
             continue;
         }
 
-        let cond = processor.filter(input_path)?;
-        if cond {
+        if processor.filter(input_path)? {
             debug!("{}: matched by handler {}", input_path.display(), processor.name());
+            trace::event(input_path, "matched", format_args!("handler {}", processor.name()));
 
-            selected_handlers |= 1 << n_processor;
+            // Handlers are tried in order and the first one that matches wins.
+            return Ok(Some(n_processor));
+        }
+    }
 
-            if process_wrapper.is_none() {
-                let res = processor.process(input_path);
-                entry_mod.extend_and_warn(input_path, res);
-            }
+    if let Some(n_processor) = match_magic_rules(config, handlers, already_seen, input_path)? {
+        return Ok(Some(n_processor));
+    }
+
+    match_content(config, handlers, already_seen, input_path)
+}
+
+/// Run the handler on a separate thread, and give up if it does not finish
+/// within the timeout. Handlers are not Send, so a new instance is created
+/// in the thread. A thread cannot be cancelled, so after the timeout, it is
+/// left running and the file is reported as failed. The thread is told
+/// that it was abandoned, and InputOutputHelper::finalize() refuses to
+/// replace or rewrite the file after that.
+///
+/// This means that, unlike with -j, where each worker is a separate process,
+/// an abandoned thread can run concurrently with the handlers for the next
+/// files. Handlers must not share mutable state through statics or the
+/// file system, other than through the trace module, which is locked.
+pub fn process_with_timeout(
+    config: &options::Config,
+    boxed: HandlerBoxed,
+    input_path: &Path,
+    timeout: Duration,
+) -> Result<ProcessResult> {
+
+    let abandoned = Arc::new(AtomicBool::new(false));
+    let config = options::Config {
+        abandoned: Some(abandoned.clone()),
+        ..config.clone()
+    };
+    let path = input_path.to_path_buf();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name(format!("handler {}", input_path.display()))
+        .spawn(move || {
+            let config = Rc::new(config);
+            let mut handler = boxed(&config);
+            let res = handler.initialize().and_then(|_| handler.process(&path));
+            // The receiver is gone if we gave up already
+            let _ = sender.send(res);
+        })?;
+
+    match receiver.recv_timeout(timeout) {
+        Ok(res) => res,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            abandoned.store(true, Ordering::SeqCst);
+            bail!("handler did not finish within --handler-timeout={}, abandoned",
+                  timeout.as_secs_f64())
         }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            bail!("handler thread panicked")
+        }
+    }
+}
+
+/// Run the handler on the file, with --handler-timeout if it was given
+pub fn run_handler(
+    config: &options::Config,
+    handler: &dyn Processor,
+    input_path: &Path,
+) -> Result<ProcessResult> {
+
+    let timeout = config.handler_timeout;
+    let entry = HANDLERS.iter().find(|h| h.name == handler.name());
 
-        *already_seen |= selected_handlers;
+    match (timeout, entry) {
+        (Some(timeout), Some(entry)) => process_with_timeout(config, entry.boxed, input_path, timeout),
+        _ => handler.process(input_path),
     }
+}
+
+fn process_file(
+    config: &options::Config,
+    handlers: &[Box<dyn Processor>],
+    already_seen: &mut u32,
+    input_path: &Path,
+    process_wrapper: ProcessWrapper,
+    stats: &mut Stats,
+) -> Result<ProcessResult> {
+
+    // When processing locally, this says whether modifications have
+    // been made. When processing remotely, we will send the result
+    // separately after asynchronous processing is finished.
+    let mut entry_mod = ProcessResult::Ignored;
+
+    let selected = select_handler(config, handlers, *already_seen, input_path)?;
 
-    if selected_handlers > 0 {
-        if let Some(func) = process_wrapper {
-            assert!(entry_mod == ProcessResult::Ignored);
-            func(selected_handlers, input_path)?;
+    let Some(n_processor) = selected else {
+        trace::event(input_path, "skipped", format_args!("no handler matched"));
+        // If some handler was applied to this inode under a different name,
+        // the file is not unhandled.
+        if *already_seen == 0 {
+            stats.unhandled += 1;
+
+            if config.report_unhandled {
+                let ext = match input_path.extension() {
+                    Some(ext) => format!(".{}", ext.to_string_lossy()),
+                    None => "(no extension)".to_string(),
+                };
+                *stats.unhandled_extensions.entry(ext).or_default() += 1;
+            }
         }
+        return Ok(entry_mod);
+    };
+
+    // This is checked after the handler is selected, so that large files
+    // which no handler would look at are not reported.
+    if let Some(max_size) = config.max_file_size {
+        let size = input_path.metadata()?.len();
+        if size > max_size {
+            warn!("{}: file size {} is above --max-file-size={}, skipping",
+                  input_path.display(), size, max_size);
+            trace::event(input_path, "skipped", format_args!("file size {size} above limit {max_size}"));
+            stats.too_large += 1;
+            return Ok(entry_mod);
+        }
+    }
+
+    let selected_handlers = 1 << n_processor;
+    *already_seen |= selected_handlers;
+
+    if let Some(func) = process_wrapper {
+        func(selected_handlers, input_path)?;
+    } else {
+        let res = run_handler(config, handlers[n_processor].as_ref(), input_path);
+        entry_mod.extend_and_warn(input_path, res);
     }
 
     Ok(entry_mod)
 }
 
 fn process_entry(
+    config: &options::Config,
     handlers: &[Box<dyn Processor>],
-    inodes_seen: &mut HashMap<u64, u8>,
+    inodes_seen: &mut HashMap<u64, u32>,
     process_wrapper: ProcessWrapper,
     stats: &mut Stats,
     entry: &walkdir::DirEntry,
 ) -> Result<ProcessResult> {
 
-    debug!("Looking at {}…", entry.path().display());
+    trace!("Looking at {}…", entry.path().display());
 
     let name = unwrap_os_string(entry.file_name())?;
     if name.starts_with(".#.") && name.ends_with(".tmp") {
         // This is our own temporary file. Ignore it.
+        trace::event(entry.path(), "skipped", format_args!("temporary file"));
+        return Ok(ProcessResult::Ignored);
+    }
+
+    if entry.path_is_symlink() && !config.follow_symlinks {
+        debug!("{}: symlink, not following", entry.path().display());
+        trace::event(entry.path(), "skipped", format_args!("symlink, not following"));
+        stats.files += 1;
+        stats.ignored += 1;
+        progress::file(entry.path());
         return Ok(ProcessResult::Ignored);
     }
 
@@ -304,8 +696,11 @@ fn process_entry(
     }
 
     stats.files += 1;
+    progress::file(entry.path());
     if !metadata.is_file() {
         debug!("{}: not a file", entry.path().display());
+        trace::event(entry.path(), "skipped", format_args!("not a regular file"));
+        stats.ignored += 1;
         return Ok(ProcessResult::Ignored);
     }
 
@@ -313,10 +708,12 @@ fn process_entry(
     let mut already_seen = *inodes_seen.get(&inode).unwrap_or(&0);
 
     let entry_mod = process_file(
+        config,
         handlers,
         &mut already_seen,
         entry.path(),
-        process_wrapper)?;
+        process_wrapper,
+        stats)?;
 
     inodes_seen.insert(inode, already_seen); // This is the orig inode
     if entry_mod != ProcessResult::Noop {
@@ -334,9 +731,47 @@ fn process_entry(
     Ok(entry_mod)
 }
 
+/// Check the path of an entry, relative to the argument it was found under,
+/// against the --exclude and --include patterns. Excluded directories are
+/// not descended into. The --include patterns only apply to files.
+fn entry_is_selected(
+    config: &options::Config,
+    input_path: &Path,
+    entry: &walkdir::DirEntry,
+) -> bool {
+
+    if config.exclude.is_empty() && config.include.is_empty() {
+        return true;
+    }
+
+    // If the argument is a file, match on the file name
+    let rel = match entry.path().strip_prefix(input_path) {
+        Ok(rel) if rel.as_os_str().is_empty() => Path::new(entry.file_name()),
+        Ok(rel) => rel,
+        Err(_) => entry.path(),
+    };
+
+    if let Some(pattern) = config.exclude.iter().find(|p| p.matches_path(rel)) {
+        debug!("{}: excluded by pattern {}", entry.path().display(), pattern);
+        trace::event(entry.path(), "skipped", format_args!("excluded by pattern {}", pattern));
+        return false;
+    }
+
+    if !config.include.is_empty()
+        && !entry.file_type().is_dir()
+        && !config.include.iter().any(|p| p.matches_path(rel)) {
+        debug!("{}: not included by any pattern", entry.path().display());
+        trace::event(entry.path(), "skipped", format_args!("not included by any pattern"));
+        return false;
+    }
+
+    true
+}
+
 pub fn process_file_or_dir(
+    config: &options::Config,
     handlers: &[Box<dyn Processor>],
-    inodes_seen: &mut HashMap<u64, u8>,
+    inodes_seen: &mut HashMap<u64, u32>,
     input_path: &Path,
     process_wrapper: ProcessWrapper,
 ) -> Result<Stats> {
@@ -349,8 +784,10 @@ pub fn process_file_or_dir(
     let mut stats = Stats::new();
 
     for entry in walkdir::WalkDir::new(input_path)
-        .follow_links(false)
-        .into_iter() {
+        .follow_links(config.follow_symlinks)
+        .follow_root_links(config.follow_symlinks)
+        .into_iter()
+        .filter_entry(|entry| entry_is_selected(config, input_path, entry)) {
             let entry = match entry {
                 Err(e) if first => {
                     return Err(e.into());
@@ -364,21 +801,46 @@ pub fn process_file_or_dir(
             };
             first = false;
 
-            let res = process_entry(handlers, inodes_seen, process_wrapper, &mut stats, &entry);
+            let res = process_entry(config, handlers, inodes_seen, process_wrapper, &mut stats, &entry);
             stats.add_one(ProcessResult::convert_and_warn(entry.path(), res));
         }
 
     Ok(stats)
 }
 
+/// Count the files that process_file_or_dir() will look at, for --progress.
+pub fn count_files(config: &options::Config, input_path: &Path) -> u64 {
+    walkdir::WalkDir::new(input_path)
+        .follow_links(config.follow_symlinks)
+        .follow_root_links(config.follow_symlinks)
+        .into_iter()
+        .filter_entry(|entry| entry_is_selected(config, input_path, entry))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            !(name.starts_with(".#.") && name.ends_with(".tmp"))
+        })
+        .count() as u64
+}
+
 pub struct InputOutputHelper<'a> {
     pub input_path: &'a Path,
+    /// The file that is modified: input_path with symlinks resolved
+    pub target_path: PathBuf,
     pub input_metadata: Metadata,
 
     pub output_path: Option<PathBuf>,
     pub output: Option<File>,
 
     pub check: bool,
+    /// With --diff, the changed byte ranges are listed
+    pub diff: bool,
+    /// With --backup, the original is kept under target_path + suffix
+    pub backup_suffix: Option<String>,
+    pub force_backup: bool,
+    /// Set if the handler was abandoned after --handler-timeout
+    pub abandoned: Option<Arc<AtomicBool>>,
 }
 
 impl<'a> Drop for InputOutputHelper<'a> {
@@ -403,8 +865,64 @@ fn unwrap_os_string(filename: &OsStr) -> Result<&str> {
     }
 }
 
+/// The parts of InputOutputHelper needed to make a backup. Those are
+/// borrowed separately, because the output file is borrowed mutably.
+struct BackupSource<'a> {
+    input_path: &'a Path,
+    target_path: &'a Path,
+    input_metadata: &'a Metadata,
+}
+
+/// Keep the original contents under target_path + suffix. When the file
+/// is going to be replaced, the original inode is linked under the new
+/// name, so the backup also keeps the original metadata. When the file
+/// is going to be rewritten in place, the contents are copied.
+fn make_backup(source: BackupSource, suffix: &str, force: bool, link: bool) -> Result<()> {
+    let mut backup_path = source.target_path.as_os_str().to_owned();
+    backup_path.push(suffix);
+    let backup_path = PathBuf::from(backup_path);
+
+    if force {
+        if let Err(e) = fs::remove_file(&backup_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                bail!("{}: cannot remove old backup: {}", backup_path.display(), e);
+            }
+        }
+    }
+
+    let res = if link {
+        fs::hard_link(source.target_path, &backup_path)
+    } else {
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(&backup_path)
+            .and_then(|mut backup| {
+                io::copy(&mut File::open(source.target_path)?, &mut backup)?;
+                backup.set_permissions(source.input_metadata.permissions())?;
+                backup.set_modified(source.input_metadata.modified()?)
+            })
+    };
+
+    if let Err(e) = res {
+        if e.kind() == io::ErrorKind::AlreadyExists {
+            bail!("{}: backup already exists, refusing to overwrite it without --force-backup",
+                  backup_path.display());
+        }
+        bail!("{}: cannot create backup: {}", backup_path.display(), e);
+    }
+
+    debug!("{}: original saved as {}", source.input_path.display(), backup_path.display());
+    trace::event(source.input_path, "backup", format_args!("{}", backup_path.display()));
+    Ok(())
+}
+
 impl<'a> InputOutputHelper<'a> {
-    pub fn open(input_path: &'a Path, check: bool) -> Result<(Self, BufReader<File>)> {
+    pub fn open(
+        input_path: &'a Path,
+        config: &options::Config,
+    ) -> Result<(Self, BufReader<File>)> {
+
 
         let input = File::open(input_path)
             .with_context(|| format!("Cannot open {:?}", input_path))?;
@@ -412,12 +930,30 @@ impl<'a> InputOutputHelper<'a> {
         let input_metadata = input.metadata()?;
         let input = BufReader::new(input);
 
+        // If we were given a symlink, the file it points to is replaced,
+        // and the temporary file must be created next to it.
+        let target_path = if input_path.symlink_metadata()?.is_symlink() {
+            let target = fs::canonicalize(input_path)
+                .with_context(|| format!("Cannot resolve symlink {:?}", input_path))?;
+            debug!("{}: symlink to {}", input_path.display(), target.display());
+            target
+        } else {
+            input_path.to_path_buf()
+        };
+
+        trace::event(input_path, "opened", format_args!("size {}", input_metadata.len()));
+
         let io = InputOutputHelper {
             input_path,
+            target_path,
             input_metadata,
             output_path: None,
             output: None,
-            check,
+            check: config.check,
+            diff: config.diff,
+            backup_suffix: config.backup.clone(),
+            force_backup: config.force_backup,
+            abandoned: config.abandoned.clone(),
         };
 
         Ok((io, input))
@@ -430,8 +966,8 @@ impl<'a> InputOutputHelper<'a> {
         // TODO: if .check, open a null sink here?
         // We'd avoid some IO and also not require the output path to be writable.
 
-        let input_file_name = unwrap_os_string(self.input_path.file_name().unwrap())?;
-        let output_path = self.input_path.with_file_name(format!(".#.{}.tmp", input_file_name));
+        let input_file_name = unwrap_os_string(self.target_path.file_name().unwrap())?;
+        let output_path = self.target_path.with_file_name(format!(".#.{}.tmp", input_file_name));
 
         let mut openopts = File::options();
         openopts.read(true).write(true).create_new(true);
@@ -454,6 +990,48 @@ impl<'a> InputOutputHelper<'a> {
         Ok(())
     }
 
+    fn copy_xattrs(input_path: &Path, output: &File) -> Result<()> {
+        let names = match xattr::list(input_path) {
+            Ok(names) => names,
+            Err(e) if e.raw_os_error() == Some(errno::Errno::ENOTSUP as i32) => {
+                debug!("{}: extended attributes are not supported, not copying",
+                       input_path.display());
+                return Ok(());
+            }
+            Err(e) => {
+                bail!("{}: cannot list extended attributes: {}", input_path.display(), e);
+            }
+        };
+
+        for name in names {
+            let name_str = name.to_string_lossy();
+            if !XATTR_PREFIXES.iter().any(|p| name_str.starts_with(p)) {
+                debug!("{}: not copying extended attribute {}", input_path.display(), name_str);
+                continue;
+            }
+
+            let Some(value) = xattr::get(input_path, &name)? else {
+                continue; // The attribute was removed in the meantime
+            };
+
+            if let Err(e) = output.set_xattr(&name, &value) {
+                warn!("{}: cannot copy extended attribute {}, ignoring: {}",
+                      input_path.display(), name_str, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// After --handler-timeout, the file may already be processed again,
+    /// so the abandoned handler must not touch it anymore.
+    fn check_abandoned(input_path: &Path, abandoned: &Option<Arc<AtomicBool>>) -> Result<()> {
+        if abandoned.as_ref().is_some_and(|a| a.load(Ordering::SeqCst)) {
+            bail!("{}: handler was abandoned, not modifying the file", input_path.display());
+        }
+        Ok(())
+    }
+
     pub fn finalize(&mut self, have_mod: bool) -> Result<ProcessResult> {
         let meta = &self.input_metadata;
 
@@ -478,44 +1056,94 @@ impl<'a> InputOutputHelper<'a> {
             }
             let output = output.unwrap();
 
+            if self.check && self.diff {
+                output.seek(io::SeekFrom::Start(0))?;
+                let input = BufReader::new(File::open(&self.target_path)?);
+                let diff = diff::compare(input, BufReader::new(&*output))?;
+
+                // With -j, the workers share stdout, so the lines for each
+                // file are written in one go, and not mixed with other output
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(diff.format(self.input_path).as_bytes())?;
+                stdout.flush()?;
+            }
+
             // If the original file has nlinks == 1, we atomically replace it.
             // If it has multiple links, we reopen the orignal file and rewrite it.
             // This way the inode number is retained and hard links are not broken.
             if meta.nlink() == 1 {
-                info!("{}: replacing with normalized version", self.input_path.display());
-
-                if !self.check {
-                    output.set_permissions(meta.permissions())?;
-                    output.set_modified(meta.modified()?)?;
+                if self.check {
+                    info!("{}: not normalized, would be replaced", self.input_path.display());
+                    trace::event(self.input_path, "checked", format_args!("not normalized"));
+                } else {
+                    Self::check_abandoned(self.input_path, &self.abandoned)?;
+                    info!("{}: replacing with normalized version", self.input_path.display());
+
+                    if let Some(suffix) = &self.backup_suffix {
+                        let source = BackupSource {
+                            input_path: self.input_path,
+                            target_path: &self.target_path,
+                            input_metadata: &self.input_metadata,
+                        };
+                        make_backup(source, suffix, self.force_backup, true)?;
+                    }
 
-                    if let Err(e) = unix_fs::lchown(output_path, Some(meta.st_uid()), Some(meta.st_gid())) {
-                        if e.kind() == io::ErrorKind::PermissionDenied {
-                            warn!("{}: cannot change file ownership, ignoring", self.input_path.display());
-                        } else {
-                            bail!("{}: cannot change file ownership: {}", self.input_path.display(), e);
+                    // The ownership must be changed before the mode is set,
+                    // because chown clears the setuid and setgid bits.
+                    let output_meta = output.metadata()?;
+                    if output_meta.st_uid() != meta.st_uid() || output_meta.st_gid() != meta.st_gid() {
+                        if let Err(e) = unix_fs::fchown(&*output, Some(meta.st_uid()), Some(meta.st_gid())) {
+                            if e.kind() == io::ErrorKind::PermissionDenied {
+                                warn!("{}: cannot change file ownership, ignoring", self.input_path.display());
+                            } else {
+                                bail!("{}: cannot change file ownership: {}", self.input_path.display(), e);
+                            }
                         }
                     }
 
-                    fs::rename(output_path, self.input_path)?;
+                    output.set_permissions(meta.permissions())?;
+                    Self::copy_xattrs(&self.target_path, output)?;
+                    output.set_modified(meta.modified()?)?;
+
+                    fs::rename(output_path, &self.target_path)
+                        .with_context(|| format!("Cannot replace {:?} with {:?}",
+                                                 self.target_path, output_path))?;
                     self.output_path = None; /* The path is now invalid */
+                    trace::event(self.input_path, "wrote", format_args!("replaced"));
                 }
 
                 Ok(ProcessResult::Replaced)
 
             } else {
-                info!("{}: rewriting with normalized contents", self.input_path.display());
+                if self.check {
+                    info!("{}: not normalized, would be rewritten", self.input_path.display());
+                    trace::event(self.input_path, "checked", format_args!("not normalized"));
+                } else {
+                    Self::check_abandoned(self.input_path, &self.abandoned)?;
+                    info!("{}: rewriting with normalized contents", self.input_path.display());
+
+                    if let Some(suffix) = &self.backup_suffix {
+                        let source = BackupSource {
+                            input_path: self.input_path,
+                            target_path: &self.target_path,
+                            input_metadata: &self.input_metadata,
+                        };
+                        make_backup(source, suffix, self.force_backup, false)?;
+                    }
 
-                if !self.check {
                     output.seek(io::SeekFrom::Start(0))?;
 
-                    let mut input_writer = File::options().write(true).open(self.input_path)?;
+                    // The normalized contents may be shorter than the original
+                    let mut input_writer = File::options().write(true).truncate(true).open(&self.target_path)?;
                     io::copy(output, &mut input_writer)?;
                     input_writer.set_modified(meta.modified()?)?;
+                    trace::event(self.input_path, "wrote", format_args!("rewritten"));
                 }
 
                 Ok(ProcessResult::Rewritten)
             }
         } else {
+            trace::event(self.input_path, "unchanged", format_args!(""));
             Ok(ProcessResult::Noop)
         }
     }
@@ -0,0 +1,345 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC: &[u8] = b"MZ";
+const PE_SIGNATURE: &[u8] = b"PE\0\0";
+
+// https://learn.microsoft.com/en-us/windows/win32/debug/pe-format
+const E_LFANEW_OFFSET: usize = 0x3c;
+
+const COFF_HEADER_LENGTH: usize = 20;
+const COFF_TIME_DATE_STAMP: usize = 4;
+
+const OPTIONAL_MAGIC_PE32: u16 = 0x10b;
+const OPTIONAL_MAGIC_PE32_PLUS: u16 = 0x20b;
+const OPTIONAL_CHECKSUM: usize = 64;
+
+const SECTION_HEADER_LENGTH: usize = 40;
+
+const DIRECTORY_EXPORT: u32 = 0;
+const DIRECTORY_SECURITY: u32 = 4;
+const DIRECTORY_DEBUG: u32 = 6;
+const DIRECTORY_CLR_RUNTIME_HEADER: u32 = 14;
+const DEBUG_DIRECTORY_LENGTH: usize = 28;
+
+/// The offset of TimeDateStamp in the export directory and in the
+/// debug directory entries
+const DIRECTORY_TIME_DATE_STAMP: usize = 4;
+
+fn le(data: &[u8], offset: usize, size: usize) -> Result<u64> {
+    let bytes = offset.checked_add(size).and_then(|end| data.get(offset .. end))
+        .ok_or(super::Error::UnexpectedEOF(offset as u64, size))?;
+    Ok(bytes.iter().rev().fold(0, |acc, b| acc << 8 | *b as u64))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(le(data, offset, 2)? as u16)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(le(data, offset, 4)? as u32)
+}
+
+/// The checksum in the optional header, as calculated by
+/// CheckSumMappedFile: the sum of little-endian 16-bit words with the
+/// carry folded back in, plus the file size. The checksum field itself
+/// must be zero.
+pub fn checksum(data: &[u8]) -> u32 {
+    let sum = data.chunks(2)
+        .map(|chunk| chunk[0] as u32 | (chunk.get(1).copied().unwrap_or(0) as u32) << 8)
+        .fold(0_u32, |acc, word| {
+            let acc = acc + word;
+            (acc & 0xffff) + (acc >> 16)
+        });
+    sum.wrapping_add(data.len() as u32)
+}
+
+#[derive(Debug)]
+struct Section {
+    virtual_address: u32,
+    raw_size: u32,
+    raw_offset: u32,
+}
+
+/// The position in the file of the data at a relative virtual address
+fn rva_to_offset(sections: &[Section], rva: u32) -> Option<usize> {
+    sections.iter()
+        .find(|s| rva >= s.virtual_address && rva - s.virtual_address < s.raw_size)
+        .map(|s| s.raw_offset as usize + (rva - s.virtual_address) as usize)
+}
+
+/// The headers of an image, with the positions of the fields to modify
+#[derive(Debug)]
+struct Image {
+    coff_header: usize,
+    checksum: Option<usize>,
+    export_directory: Option<usize>,
+    debug_directory: Option<(usize, usize)>,
+    /// The size of the attribute certificate table
+    certificate_table: usize,
+    /// The image is a .NET assembly
+    clr_header: bool,
+}
+
+impl Image {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let magic = data.get(..MAGIC.len())
+            .ok_or(super::Error::UnexpectedEOF(0, MAGIC.len()))?;
+        if magic != MAGIC {
+            return Err(super::Error::BadMagic(0, magic.to_vec(), MAGIC).into());
+        }
+
+        let pe_header = u32_at(data, E_LFANEW_OFFSET)? as usize;
+        let signature = pe_header.checked_add(PE_SIGNATURE.len())
+            .and_then(|end| data.get(pe_header .. end))
+            .ok_or(super::Error::UnexpectedEOF(pe_header as u64, PE_SIGNATURE.len()))?;
+        if signature != PE_SIGNATURE {
+            return Err(super::Error::BadMagic(pe_header as u64, signature.to_vec(), PE_SIGNATURE).into());
+        }
+
+        let coff_header = pe_header + PE_SIGNATURE.len();
+        let section_count = u16_at(data, coff_header + 2)? as usize;
+        let optional_size = u16_at(data, coff_header + 16)? as usize;
+        let optional_header = coff_header + COFF_HEADER_LENGTH;
+
+        let mut image = Image {
+            coff_header,
+            checksum: None,
+            export_directory: None,
+            debug_directory: None,
+            certificate_table: 0,
+            clr_header: false,
+        };
+
+        // Object files have no optional header
+        if optional_size == 0 {
+            return Ok(image);
+        }
+
+        let (count_offset, directories_offset) = match u16_at(data, optional_header)? {
+            OPTIONAL_MAGIC_PE32 => (92, 96),
+            OPTIONAL_MAGIC_PE32_PLUS => (108, 112),
+            magic => {
+                return Err(super::Error::Other(
+                    format!("optional header has unknown magic 0x{magic:x}")
+                ).into());
+            }
+        };
+        if optional_size < directories_offset {
+            return Err(super::Error::UnexpectedEOF(optional_header as u64, directories_offset).into());
+        }
+        image.checksum = Some(optional_header + OPTIONAL_CHECKSUM);
+
+        let sections_start = optional_header + optional_size;
+        let sections = (0..section_count)
+            .map(|n| -> Result<Section> {
+                let header = sections_start + n * SECTION_HEADER_LENGTH;
+                Ok(Section {
+                    virtual_address: u32_at(data, header + 12)?,
+                    raw_size: u32_at(data, header + 16)?,
+                    raw_offset: u32_at(data, header + 20)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The data directories which are present and not empty
+        let directory_count = u32_at(data, optional_header + count_offset)?;
+        let directory_entry = |index: u32| -> Result<Option<(u32, u32)>> {
+            let pos = optional_header + directories_offset + 8 * index as usize;
+            if index >= directory_count || pos + 8 > sections_start {
+                return Ok(None);
+            }
+            Ok(Some((u32_at(data, pos)?, u32_at(data, pos + 4)?)))
+        };
+        let directory = |index: u32| -> Result<Option<(usize, usize)>> {
+            let Some((rva, size)) = directory_entry(index)? else {
+                return Ok(None);
+            };
+            if rva == 0 || size == 0 {
+                return Ok(None);
+            }
+
+            match rva_to_offset(&sections, rva) {
+                Some(offset) => Ok(Some((offset, size as usize))),
+                None => Err(super::Error::Other(
+                    format!("data directory {index} at RVA 0x{rva:x} is not in any section")
+                ).into()),
+            }
+        };
+
+        image.export_directory = directory(DIRECTORY_EXPORT)?.map(|(offset, _)| offset);
+        // The address of the certificate table is a file offset, not an RVA,
+        // because the table is not loaded into memory
+        image.certificate_table = directory_entry(DIRECTORY_SECURITY)?
+            .map_or(0, |(_, size)| size as usize);
+        image.debug_directory = directory(DIRECTORY_DEBUG)?;
+        image.clr_header = directory_entry(DIRECTORY_CLR_RUNTIME_HEADER)?
+            .is_some_and(|(rva, size)| rva != 0 && size != 0);
+
+        Ok(image)
+    }
+}
+
+pub struct Pe {
+    config: Rc<options::Config>,
+}
+
+impl Pe {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+impl super::Processor for Pe {
+    fn name(&self) -> &str {
+        "pe"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "exe" || x == "dll" || x == "efi"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+
+        let image = Image::parse(&data)?;
+        debug!("{}: {:?}", input_path.display(), image);
+
+        // An Authenticode signature covers the fields that we would modify
+        if image.certificate_table > 0 {
+            debug!("{}: image is signed, not modifying it", input_path.display());
+            return Ok(super::ProcessResult::Noop);
+        }
+
+        // The strong name signature of an assembly covers them too.
+        // Whether the assembly is signed is not checked, just skip it.
+        if image.clr_header {
+            debug!("{}: image is a .NET assembly, not modifying it", input_path.display());
+            return Ok(super::ProcessResult::Noop);
+        }
+
+        let mut have_mod = false;
+
+        // The timestamps in the headers are clamped, like file modification
+        // times. The timestamps of the debug directory entries are zeroed.
+        let mut fields = vec![
+            ("TimeDateStamp".to_string(), image.coff_header + COFF_TIME_DATE_STAMP, true),
+        ];
+        if let Some(offset) = image.export_directory {
+            fields.push(("export directory TimeDateStamp".to_string(),
+                         offset + DIRECTORY_TIME_DATE_STAMP, true));
+        }
+        if let Some((offset, size)) = image.debug_directory {
+            for n in 0..size / DEBUG_DIRECTORY_LENGTH {
+                fields.push((format!("debug directory entry {n} TimeDateStamp"),
+                             offset + n * DEBUG_DIRECTORY_LENGTH + DIRECTORY_TIME_DATE_STAMP, false));
+            }
+        }
+
+        for (field, pos, clamp) in fields {
+            let value = u32_at(&data, pos)?;
+            let new_value = if clamp {
+                self.config.clamp_mtime(value as i64).clamp(0, u32::MAX as i64) as u32
+            } else {
+                0
+            };
+
+            if new_value != value {
+                trace::event(input_path, "field",
+                             format_args!("{}: {} → {}", field, value, new_value));
+                data[pos .. pos + 4].copy_from_slice(&new_value.to_le_bytes());
+                have_mod = true;
+            }
+        }
+
+        if let Some(pos) = image.checksum.filter(|_| have_mod) {
+            // The checksum is optional, and most images only have a zero
+            let old = u32_at(&data, pos)?;
+            if old != 0 {
+                data[pos .. pos + 4].fill(0);
+                let new = checksum(&data);
+                trace::event(input_path, "field",
+                             format_args!("CheckSum: 0x{:08x} → 0x{:08x}", old, new));
+                data[pos .. pos + 4].copy_from_slice(&new.to_le_bytes());
+            }
+        }
+
+        if have_mod {
+            io.open_output()?;
+            io.output.as_mut().unwrap().write_all(&data)?;
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"\x01\x00\x02\x00"), 3 + 4);
+        // An odd byte at the end is padded
+        assert_eq!(checksum(b"\x01\x00\x02"), 3 + 3);
+        // The carry is folded back in
+        assert_eq!(checksum(b"\xff\xff\x02\x00"), 2 + 4);
+    }
+
+    #[test]
+    fn test_rva_to_offset() {
+        let sections = [
+            Section { virtual_address: 0x1000, raw_size: 0x200, raw_offset: 0x400 },
+            Section { virtual_address: 0x2000, raw_size: 0, raw_offset: 0 },
+        ];
+
+        assert_eq!(rva_to_offset(&sections, 0x1000), Some(0x400));
+        assert_eq!(rva_to_offset(&sections, 0x1100), Some(0x500));
+        assert_eq!(rva_to_offset(&sections, 0x1200), None);
+        // Uninitialized data is not in the file
+        assert_eq!(rva_to_offset(&sections, 0x2000), None);
+        assert_eq!(rva_to_offset(&sections, 0x10), None);
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert!(Image::parse(b"").is_err());
+        assert!(Image::parse(b"ELF\0").is_err());
+
+        // e_lfanew points outside of the file
+        let mut data = vec![0; 0x40];
+        data[..2].copy_from_slice(MAGIC);
+        data[E_LFANEW_OFFSET] = 0x80;
+        assert!(Image::parse(&data).is_err());
+
+        // A DOS executable without the PE header
+        data.resize(0x100, 0);
+        assert!(Image::parse(&data).is_err());
+    }
+
+    #[test]
+    fn filter_pe() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Pe::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/foo.exe")).unwrap());
+        assert!( h.filter(Path::new("/some/path/foo.dll")).unwrap());
+        assert!( h.filter(Path::new("/usr/lib/systemd/boot/efi/systemd-bootx64.efi")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/foo.exe.config")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/exe")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+}
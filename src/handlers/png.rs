@@ -0,0 +1,182 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// https://www.w3.org/TR/png-3/#5Chunk-layout
+const CHUNK_LENGTH_MAX: u32 = (1 << 31) - 1;
+
+/// Chunks that are always dropped.
+const TIME_CHUNKS: &[&[u8; 4]] = &[b"tIME"];
+
+/// Chunks that are dropped if --png-strip-text is used.
+const TEXT_CHUNKS: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt", b"eXIf"];
+
+/// Recognize PNG images by the signature, for --content-detect.
+pub fn sniff(buf: &[u8]) -> bool {
+    buf.starts_with(MAGIC)
+}
+
+pub struct Png {
+    config: Rc<options::Config>,
+}
+
+impl Png {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+
+    fn should_drop(&self, typ: &[u8; 4]) -> bool {
+        TIME_CHUNKS.contains(&typ) ||
+            self.config.png_strip_text && TEXT_CHUNKS.contains(&typ)
+    }
+}
+
+impl super::Processor for Png {
+    fn name(&self) -> &str {
+        "png"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "png"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let mut have_mod = false;
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        // The filter only looks at the extension, so the signature must be checked
+        let mut buf = [0; MAGIC.len()];
+        if let Err(e) = input.read_exact(&mut buf) {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                return Err(super::Error::UnexpectedEOF(0, buf.len()).into());
+            }
+            return Err(e.into());
+        }
+        if buf != MAGIC {
+            return Err(super::Error::BadMagic(0, buf.to_vec(), MAGIC).into());
+        }
+
+        io.open_output()?;
+        let mut output = BufWriter::new(io.output.as_mut().unwrap());
+
+        output.write_all(&buf)?;
+
+        let mut offset = MAGIC.len() as u64;
+
+        loop {
+            // Each chunk is: length (4 bytes, big-endian), type (4 bytes),
+            // data (length bytes), and CRC (4 bytes). The CRC only covers
+            // the type and data, so chunks can be dropped without touching
+            // the rest of the file.
+
+            let mut header = [0; 8];
+            if let Err(e) = input.read_exact(&mut header) {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    return Err(super::Error::UnexpectedEOF(offset, header.len()).into());
+                }
+                return Err(e.into());
+            }
+
+            let length = u32::from_be_bytes(header[..4].try_into().unwrap());
+            let typ: [u8; 4] = header[4..].try_into().unwrap();
+
+            if length > CHUNK_LENGTH_MAX {
+                return Err(super::Error::Other(
+                    format!("chunk at offset 0x{offset:x} has invalid length {length}")
+                ).into());
+            }
+
+            let mut data = vec![0; length as usize + 4];
+            if let Err(e) = input.read_exact(&mut data) {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    return Err(super::Error::UnexpectedEOF(offset + 8, data.len()).into());
+                }
+                return Err(e.into());
+            }
+
+            if self.should_drop(&typ) {
+                debug!("{}: dropping chunk {} at offset 0x{:x}, length {}",
+                       io.input_path.display(), super::asciify(typ), offset, length);
+                trace::event(io.input_path, "field",
+                             format_args!("chunk {} at offset 0x{:x}: dropped", super::asciify(typ), offset));
+                have_mod = true;
+            } else {
+                debug!("{}: chunk {} at offset 0x{:x}, length {}",
+                       io.input_path.display(), super::asciify(typ), offset, length);
+                output.write_all(&header)?;
+                output.write_all(&data)?;
+            }
+
+            offset += 8 + data.len() as u64;
+
+            if &typ == b"IEND" {
+                break;
+            }
+        }
+
+        // Anything after IEND is not part of the image, but we don't want
+        // to lose data, so copy it unmodified.
+        let trailing = std::io::copy(&mut input, &mut output)?;
+        if trailing > 0 {
+            debug!("{}: {} bytes after IEND chunk", io.input_path.display(), trailing);
+        }
+
+        output.flush()?;
+        drop(output);
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_png() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Png::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/image.png")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/image.pngg")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/image.png.jpg")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/image")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/png")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+
+    #[test]
+    fn should_drop() {
+        let cfg = options::Config::empty(0, false);
+        let h = Png { config: Rc::new(cfg) };
+
+        assert!( h.should_drop(b"tIME"));
+        assert!(!h.should_drop(b"tEXt"));
+        assert!(!h.should_drop(b"iTXt"));
+        assert!(!h.should_drop(b"IHDR"));
+        assert!(!h.should_drop(b"IEND"));
+
+        let mut cfg = options::Config::empty(0, false);
+        cfg.png_strip_text = true;
+        let h = Png { config: Rc::new(cfg) };
+
+        assert!( h.should_drop(b"tIME"));
+        assert!( h.should_drop(b"tEXt"));
+        assert!( h.should_drop(b"zTXt"));
+        assert!( h.should_drop(b"iTXt"));
+        assert!( h.should_drop(b"eXIf"));
+        assert!(!h.should_drop(b"IHDR"));
+        assert!(!h.should_drop(b"PLTE"));
+        assert!(!h.should_drop(b"IDAT"));
+        assert!(!h.should_drop(b"IEND"));
+    }
+}
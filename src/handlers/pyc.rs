@@ -13,6 +13,7 @@ use num_bigint_dig::{BigInt, ToBigInt};
 
 use crate::handlers::InputOutputHelper;
 use crate::options;
+use crate::trace;
 
 const PYC_MAGIC: &[u8] = &[0x0D, 0x0A];
 const PYLONG_MARSHAL_SHIFT: i32 = 15;
@@ -260,8 +261,9 @@ pub fn pyc_python_version(buf: &[u8; 4]) -> Result<((u32, u32), usize)> {
 
     let val = ((buf[1] as u32) << 8) + (buf[0] as u32);
 
-    #[allow(overlapping_range_endpoints)]
-    #[allow(clippy::match_overlapping_arm)]
+    // Each range starts just above the last magic of the previous release,
+    // so that the arms don't overlap and development versions are
+    // attributed to the release they led up to.
     match val {
         20121 => Ok(((1, 5), 8)),
         50428 => Ok(((1, 6), 8)),
@@ -274,20 +276,19 @@ pub fn pyc_python_version(buf: &[u8; 4]) -> Result<((u32, u32), usize)> {
         62151 | 62161 => Ok(((2, 6), 8)),
         62171 | 62181 | 62191 | 62201 | 62211 => Ok(((2, 7), 8)),
         3000..=3131 => Ok(((3, 0), 8)),
-        3000..=3151 => Ok(((3, 1), 8)),
-        3000..=3160 => Ok(((3, 1), 8)),
-        3000..=3180 => Ok(((3, 2), 8)),
-        3000..=3230 => Ok(((3, 3), 12)),
-        3000..=3310 => Ok(((3, 4), 12)),
-        3000..=3351 => Ok(((3, 5), 12)),
-        3360 | 3361 | 3370..=3379 => Ok(((3, 6), 12)),
-        3390..=3394 => Ok(((3, 7), 16)),
-        3400 | 3401 | 3410..=3413 => Ok(((3, 8), 16)),
-        3400..=3425 => Ok(((3, 9), 16)),
-        3430..=3439 => Ok(((3, 10), 16)),
-        3450..=3495 => Ok(((3, 11), 16)),
-        3500..=3531 => Ok(((3, 12), 16)),
-        3550..=3599 => Ok(((3, 13), 16)),
+        3132..=3151 => Ok(((3, 1), 8)),
+        3152..=3180 => Ok(((3, 2), 8)),
+        3181..=3230 => Ok(((3, 3), 12)),
+        3231..=3310 => Ok(((3, 4), 12)),
+        3311..=3351 => Ok(((3, 5), 12)),
+        3352..=3379 => Ok(((3, 6), 12)),
+        3380..=3394 => Ok(((3, 7), 16)),
+        3395..=3413 => Ok(((3, 8), 16)),
+        3414..=3425 => Ok(((3, 9), 16)),
+        3426..=3439 => Ok(((3, 10), 16)),
+        3440..=3495 => Ok(((3, 11), 16)),
+        3496..=3531 => Ok(((3, 12), 16)),
+        3532..=3599 => Ok(((3, 13), 16)),
         3600..=3699 => Ok(((3, 14), 16)),
         3700..=4000 => Ok(((3, 15), 16)),
         _ => Err(super::Error::Other(
@@ -296,6 +297,13 @@ pub fn pyc_python_version(buf: &[u8; 4]) -> Result<((u32, u32), usize)> {
     }
 }
 
+/// Recognize compiled bytecode by the magic number, for --content-detect.
+pub fn sniff(buf: &[u8]) -> bool {
+    buf.get(..4)
+        .and_then(|magic| <&[u8; 4]>::try_from(magic).ok())
+        .is_some_and(|magic| pyc_python_version(magic).is_ok())
+}
+
 pub struct Pyc {
     config: Rc<options::Config>,
 }
@@ -366,6 +374,9 @@ pub struct PycParser {
 
     irefs: Vec<Ref>,
     flag_refs: Vec<Ref>,
+
+    // offsets of string objects used as co_filename
+    filenames: Vec<usize>,
 }
 
 impl PycParser {
@@ -389,6 +400,7 @@ impl PycParser {
             read_offset: header_length,
             irefs: Vec::new(),
             flag_refs: Vec::new(),
+            filenames: Vec::new(),
         })
     }
 
@@ -521,7 +533,7 @@ impl PycParser {
             cellvars: self.maybe_read_object(self.version < (3, 11))?,
             localsplusnames: self.maybe_read_object(self.version >= (3, 11))?,
             localspluskinds: self.maybe_read_object(self.version >= (3, 11))?,
-            filename: Box::new(self.read_object()?),
+            filename: self.read_filename()?,
             name: Box::new(self.read_object()?),
             qualname: self.maybe_read_object(self.version >= (3, 11))?,
             firstlineno: self._read_long()?,
@@ -530,6 +542,31 @@ impl PycParser {
         })
     }
 
+    fn read_filename(&mut self) -> Result<Box<Object>> {
+        let offset = self.read_offset;
+        let obj = self.read_object()?;
+
+        // The filename is often a reference to the same string
+        // in an outer code object. Remember the original string.
+        let target = match obj {
+            Object::String(_) => Some(offset),
+            Object::Ref(_) => {
+                let index = u32::from_le_bytes(self.data[offset + 1 .. offset + 5].try_into().unwrap());
+                let target = self.flag_refs[index as usize].offset;
+                string_at(&self.data, target).map(|_| target)
+            }
+            _ => None,
+        };
+
+        if let Some(target) = target {
+            if let Err(pos) = self.filenames.binary_search(&target) {
+                self.filenames.insert(pos, target);
+            }
+        }
+
+        Ok(Box::new(obj))
+    }
+
     fn _read_long(&mut self) -> Result<u32> {
         let offset = self.take(4)?;
         let bytes = &self.data[offset .. offset + 4];
@@ -702,6 +739,76 @@ impl PycParser {
     }
 }
 
+/// Return the type byte (without the flag bit) and the range of contents
+/// of the string object at offset, or None if there is no string there.
+fn string_at(data: &[u8], offset: usize) -> Option<(u8, std::ops::Range<usize>)> {
+    let typ = *data.get(offset)? & !(1 << 7);
+
+    let (start, size) = match typ {
+        b'z' | b'Z' => (offset + 2, *data.get(offset + 1)? as usize),
+        b's' | b't' | b'u' | b'a' | b'A' => {
+            let bytes = data.get(offset + 1 .. offset + 5)?;
+            (offset + 5, u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        }
+        _ => { return None; }
+    };
+
+    if start + size > data.len() {
+        return None;
+    }
+    Some((typ, start .. start + size))
+}
+
+impl PycParser {
+    /// Apply --replace-path-prefix to the co_filename strings. This must be
+    /// called after the whole file has been parsed, with data that has
+    /// the same layout as the original. The length of the strings can change,
+    /// which is OK because marshal refs are indices, not offsets.
+    fn replace_filename_prefixes(&self, config: &options::Config, data: &[u8]) -> Option<Vec<u8>> {
+        let mut new_data = Vec::with_capacity(data.len());
+        let mut copied = 0;
+
+        for offset in &self.filenames {
+            let (typ, range) = string_at(data, *offset).unwrap();
+            let Some(new) = config.replace_path_prefix(&data[range.clone()]) else {
+                continue;
+            };
+
+            debug!("{}: co_filename {:?} → {:?}", self.input_path.display(),
+                   String::from_utf8_lossy(&data[range.clone()]), String::from_utf8_lossy(&new));
+            trace::event(&self.input_path, "field",
+                         format_args!("co_filename {:?} → {:?}",
+                                      String::from_utf8_lossy(&data[range.clone()]),
+                                      String::from_utf8_lossy(&new)));
+
+            let interned = matches!(typ, b'Z' | b'A' | b't');
+            let new_typ = match typ {
+                b'z' | b'Z' if new.len() < 256 && new.is_ascii() => typ,
+                b'z' | b'Z' | b'a' | b'A' if new.is_ascii() => if interned { b'A' } else { b'a' },
+                b'z' | b'Z' | b'a' | b'A' => if interned { b't' } else { b'u' },
+                _ => typ,
+            };
+
+            new_data.extend_from_slice(&data[copied .. *offset]);
+            new_data.push(new_typ | (data[*offset] & (1 << 7)));
+            if matches!(new_typ, b'z' | b'Z') {
+                new_data.push(new.len() as u8);
+            } else {
+                new_data.extend_from_slice(&(new.len() as u32).to_le_bytes());
+            }
+            new_data.extend_from_slice(&new);
+            copied = range.end;
+        }
+
+        if copied == 0 {
+            return None;
+        }
+
+        new_data.extend_from_slice(&data[copied..]);
+        if new_data != data { Some(new_data) } else { None }
+    }
+}
+
 impl super::Processor for Pyc {
     fn name(&self) -> &str {
         "pyc"
@@ -712,7 +819,7 @@ impl super::Processor for Pyc {
     }
 
     fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
-        let (mut io, input) = InputOutputHelper::open(input_path, self.config.check)?;
+        let (mut io, input) = InputOutputHelper::open(input_path, &self.config)?;
 
         let mut parser = PycParser::from_file(input_path, input)?;
         if parser.version < (3, 0) {
@@ -721,7 +828,12 @@ impl super::Processor for Pyc {
 
         parser.read_object()?;
 
-        let (have_mod, data) = parser.clear_unused_flag_refs()?;
+        let (mut have_mod, mut data) = parser.clear_unused_flag_refs()?;
+
+        if let Some(new) = parser.replace_filename_prefixes(&self.config, &data) {
+            data = new;
+            have_mod = true;
+        }
         if have_mod {
             io.open_output()?;
             io.output.as_mut().unwrap().write_all(&data)?;
@@ -735,6 +847,56 @@ impl super::Processor for Pyc {
 mod tests {
     use super::*;
 
+    fn magic(val: u16) -> [u8; 4] {
+        let [a, b] = val.to_le_bytes();
+        [a, b, b'\r', b'\n']
+    }
+
+    #[test]
+    fn test_pyc_python_version() {
+        for (val, version, header_length) in [
+            (62211, (2, 7), 8),
+            (3000, (3, 0), 8),
+            (3131, (3, 0), 8),
+            (3151, (3, 1), 8),
+            (3180, (3, 2), 8),
+            (3230, (3, 3), 12),
+            (3310, (3, 4), 12),
+            (3351, (3, 5), 12),
+            (3379, (3, 6), 12),
+            (3394, (3, 7), 16),
+            (3413, (3, 8), 16),
+            (3425, (3, 9), 16),
+            (3439, (3, 10), 16),
+            (3495, (3, 11), 16),
+            (3500, (3, 12), 16),
+            (3531, (3, 12), 16),
+            (3550, (3, 13), 16),
+            (3569, (3, 13), 16),
+            (3600, (3, 14), 16),
+            (3700, (3, 15), 16),
+        ] {
+            assert_eq!(pyc_python_version(&magic(val)).unwrap(), (version, header_length),
+                       "magic {val}");
+        }
+
+        assert!(pyc_python_version(&magic(2999)).is_err());
+        assert!(pyc_python_version(&magic(4001)).is_err());
+        assert!(pyc_python_version(&magic(62212)).is_err());
+        assert!(pyc_python_version(&[0x63, 0x0d, b'\n', b'\r']).is_err());
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert!( sniff(b"\xcb\x0d\x0d\x0a\x00\x00\x00\x00"));
+        assert!( sniff(b"\xcb\x0d\x0d\x0a"));
+        assert!(!sniff(b"\xcb\x0d\x0d"));
+        assert!(!sniff(b"\xcb\x0d\x0a\x0d"));
+        // Unknown version
+        assert!(!sniff(b"\xff\xff\x0d\x0a"));
+        assert!(!sniff(b"\x89PNG\r\n\x1a\n"));
+    }
+
     #[test]
     fn filter_a() {
         let cfg = Rc::new(options::Config::empty(0, false));
@@ -742,7 +904,9 @@ mod tests {
 
         assert!( h.filter(Path::new("/some/path/foobar.pyc")).unwrap());
         assert!(!h.filter(Path::new("/some/path/foobar.apyc")).unwrap());
+        assert!( h.filter(Path::new("/some/path/foobar.opt-1.pyc")).unwrap());
         assert!( h.filter(Path::new("/some/path/foobar.opt-2.pyc")).unwrap());
+        assert!( h.filter(Path::new("/some/path/foobar.cpython-312.opt-2.pyc")).unwrap());
         assert!(!h.filter(Path::new("/some/path/foobar")).unwrap());
         assert!(!h.filter(Path::new("/some/path/pyc")).unwrap());
         assert!(!h.filter(Path::new("/some/path/pyc_pyc")).unwrap());
@@ -0,0 +1,78 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::Cursor;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::{ar, elf};
+use crate::options;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// The crate metadata, which rustc reads when linking against the library
+const METADATA_MEMBER: &str = "lib.rmeta";
+
+pub struct Rlib {
+    config: Rc<options::Config>,
+}
+
+impl Rlib {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+
+    /// Apply the ELF normalizations to an object file in the archive.
+    /// The metadata is wrapped in an ELF file too, but it is left alone.
+    fn process_member(&self, input_path: &Path, name: &str, data: &mut [u8]) -> Result<bool> {
+        if name == METADATA_MEMBER || !data.starts_with(ELF_MAGIC) {
+            debug!("{}: member {:?} is not an object file, skipping", input_path.display(), name);
+            return Ok(false);
+        }
+
+        let member_path = input_path.join(name);
+        let patches = elf::find_patches(&self.config, &member_path,
+                                        &mut Cursor::new(&*data), data.len() as u64)?;
+
+        let have_mod = !patches.is_empty();
+        for (offset, patch) in patches {
+            let offset = offset as usize;
+            data[offset .. offset + patch.len()].copy_from_slice(&patch);
+        }
+
+        Ok(have_mod)
+    }
+}
+
+impl super::Processor for Rlib {
+    fn name(&self) -> &str {
+        "rlib"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "rlib"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        ar::process_archive(&self.config, input_path,
+                            &mut |name, data| self.process_member(input_path, name, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_rlib() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Rlib::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/libfoo-0123456789abcdef.rlib")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/libfoo.rmeta")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/libfoo.a")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/rlib")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+}
@@ -0,0 +1,151 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC: &[u8] = b"SQLite format 3\0";
+
+// https://www.sqlite.org/fileformat.html#the_database_header
+const HEADER_LENGTH: usize = 100;
+const CHANGE_COUNTER: usize = 24;
+const DATABASE_SIZE: usize = 28;
+const VERSION_VALID_FOR: usize = 92;
+
+fn be32(header: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(header[offset .. offset + 4].try_into().unwrap())
+}
+
+/// Recognize SQLite databases by the header string, for --content-detect.
+pub fn sniff(buf: &[u8]) -> bool {
+    buf.starts_with(MAGIC)
+}
+
+/// Append suffix to the file name of path
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+pub struct Sqlite {
+    config: Rc<options::Config>,
+}
+
+impl Sqlite {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+impl super::Processor for Sqlite {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "sqlite" || x == "sqlite3"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        // If there is a journal or a write-ahead log, the database was not
+        // closed properly, and the header might be rewritten when they are
+        // replayed.
+        for suffix in ["-journal", "-wal"] {
+            let path = with_suffix(input_path, suffix);
+            if path.exists() {
+                return Err(super::Error::Other(
+                    format!("{} exists, refusing to modify the database", path.display())
+                ).into());
+            }
+        }
+
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        let mut header = [0; HEADER_LENGTH];
+        if let Err(e) = input.read_exact(&mut header) {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Err(super::Error::UnexpectedEOF(0, HEADER_LENGTH).into());
+            }
+            return Err(e.into());
+        }
+
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(super::Error::BadMagic(0, header[..MAGIC.len()].to_vec(), MAGIC).into());
+        }
+
+        let change_counter = be32(&header, CHANGE_COUNTER);
+        let version_valid_for = be32(&header, VERSION_VALID_FOR);
+        debug!("{}: change counter {}, version-valid-for {}",
+               input_path.display(), change_counter, version_valid_for);
+
+        let mut have_mod = false;
+
+        // The database size in the header is only used if the version-valid-for
+        // number matches the change counter. Writers which don't update the size
+        // leave them different, in which case the size is computed from the file
+        // size. Once both numbers are zero, that can't be distinguished, so the
+        // size is reset too, which makes it invalid.
+        if change_counter != version_valid_for {
+            let size = be32(&header, DATABASE_SIZE);
+            if size != 0 {
+                trace::event(input_path, "field",
+                             format_args!("database size {} → 0", size));
+                header[DATABASE_SIZE .. DATABASE_SIZE + 4].fill(0);
+                have_mod = true;
+            }
+        }
+
+        for (field, offset, value) in [("change counter", CHANGE_COUNTER, change_counter),
+                                       ("version-valid-for", VERSION_VALID_FOR, version_valid_for)] {
+            if value != 0 {
+                trace::event(input_path, "field",
+                             format_args!("{} {} → 0", field, value));
+                header[offset .. offset + 4].fill(0);
+                have_mod = true;
+            }
+        }
+
+        if have_mod {
+            io.open_output()?;
+            let output = io.output.as_mut().unwrap();
+
+            output.write_all(&header)?;
+            io::copy(&mut input, output)?;
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert!( sniff(b"SQLite format 3\0\x10\x00"));
+        assert!(!sniff(b"SQLite format 3"));
+        assert!(!sniff(b"SQLite format 2\0"));
+        assert!(!sniff(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn filter_sqlite() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Sqlite::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/searchindex.sqlite")).unwrap());
+        assert!( h.filter(Path::new("/some/path/docs.sqlite3")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/docs.sqlite-journal")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/packages.db")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/sqlite")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+}
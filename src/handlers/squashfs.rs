@@ -0,0 +1,330 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use log::debug;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::options;
+use crate::trace;
+
+const MAGIC: &[u8] = b"hsqs";
+
+const SUPERBLOCK_LENGTH: usize = 96;
+
+// https://dr-emann.github.io/squashfs/squashfs.html
+const MKFS_TIME_OFFSET: usize = 8;
+
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+
+const INODE_MTIME_OFFSET: usize = 8;
+const NO_FRAGMENT: u32 = 0xffffffff;
+
+fn le(data: &[u8], offset: usize, size: usize) -> Result<u64> {
+    let bytes = data.get(offset .. offset + size)
+        .ok_or(super::Error::UnexpectedEOF(offset as u64, size))?;
+    Ok(bytes.iter().rev().fold(0, |acc, b| acc << 8 | *b as u64))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(le(data, offset, 2)? as u16)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(le(data, offset, 4)? as u32)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64> {
+    le(data, offset, 8)
+}
+
+#[derive(Debug)]
+struct Superblock {
+    inode_count: u32,
+    mkfs_time: u32,
+    block_size: u32,
+    inode_table_start: u64,
+    directory_table_start: u64,
+}
+
+impl Superblock {
+    fn parse(buf: &[u8], file_size: u64) -> Result<Self> {
+        if &buf[..MAGIC.len()] != MAGIC {
+            return Err(super::Error::BadMagic(0, buf[..MAGIC.len()].to_vec(), MAGIC).into());
+        }
+
+        let version = (u16_at(buf, 28)?, u16_at(buf, 30)?);
+        if version != (4, 0) {
+            return Err(super::Error::Other(
+                format!("unsupported squashfs version {}.{}", version.0, version.1)
+            ).into());
+        }
+
+        let block_size = u32_at(buf, 12)?;
+        let block_log = u16_at(buf, 22)?;
+        if block_log >= 32 || block_size != 1 << block_log {
+            return Err(super::Error::Other(
+                format!("block size {block_size} does not match block log {block_log}")
+            ).into());
+        }
+
+        let sb = Superblock {
+            inode_count: u32_at(buf, 4)?,
+            mkfs_time: u32_at(buf, MKFS_TIME_OFFSET)?,
+            block_size,
+            inode_table_start: u64_at(buf, 64)?,
+            directory_table_start: u64_at(buf, 72)?,
+        };
+
+        let bytes_used = u64_at(buf, 40)?;
+        if bytes_used > file_size
+            || sb.inode_table_start < SUPERBLOCK_LENGTH as u64
+            || sb.inode_table_start > sb.directory_table_start
+            || sb.directory_table_start > bytes_used {
+            return Err(super::Error::Other(
+                format!("inode table at 0x{:x}..0x{:x} is outside of the image",
+                        sb.inode_table_start, sb.directory_table_start)
+            ).into());
+        }
+
+        Ok(sb)
+    }
+}
+
+/// The contents of a metadata table, with the headers of the
+/// metadata blocks removed. Returns None if any of the blocks are
+/// compressed.
+fn read_metadata(table: &[u8]) -> Result<Option<(Vec<u8>, Vec<usize>)>> {
+    let mut data = vec![];
+    let mut block_offsets = vec![];
+
+    let mut pos = 0;
+    while pos < table.len() {
+        let header = u16_at(table, pos)?;
+        if header & METADATA_UNCOMPRESSED == 0 {
+            return Ok(None);
+        }
+
+        let size = (header & !METADATA_UNCOMPRESSED) as usize;
+        if size > METADATA_SIZE || (size < METADATA_SIZE && pos + 2 + size < table.len()) {
+            return Err(super::Error::Other(
+                format!("metadata block at 0x{pos:x} has unexpected size {size}")
+            ).into());
+        }
+
+        let block = table.get(pos + 2 .. pos + 2 + size)
+            .ok_or(super::Error::UnexpectedEOF(pos as u64 + 2, size))?;
+        block_offsets.push(pos + 2);
+        data.extend_from_slice(block);
+        pos += 2 + size;
+    }
+
+    Ok(Some((data, block_offsets)))
+}
+
+/// The size of the inode at offset in the inode table
+fn inode_size(data: &[u8], offset: usize, block_size: u32) -> Result<usize> {
+    let inode_type = u16_at(data, offset)?;
+
+    // The number of blocks of file data, excluding the tail end in a fragment
+    let blocks = |file_size: u64, fragment: u32| {
+        if fragment == NO_FRAGMENT {
+            file_size.div_ceil(block_size as u64) as usize
+        } else {
+            (file_size / block_size as u64) as usize
+        }
+    };
+
+    let size = match inode_type {
+        1 => 32,
+        2 => {
+            let fragment = u32_at(data, offset + 20)?;
+            let file_size = u32_at(data, offset + 28)? as u64;
+            32 + 4 * blocks(file_size, fragment)
+        }
+        3 => 24 + u32_at(data, offset + 20)? as usize,
+        4 | 5 => 24,
+        6 | 7 => 20,
+        8 => {
+            let index_count = u16_at(data, offset + 32)?;
+            let mut size = 40;
+            for _ in 0..index_count {
+                size += 12 + u32_at(data, offset + size + 8)? as usize + 1;
+            }
+            size
+        }
+        9 => {
+            let file_size = u64_at(data, offset + 24)?;
+            let fragment = u32_at(data, offset + 44)?;
+            56 + 4 * blocks(file_size, fragment)
+        }
+        10 => 28 + u32_at(data, offset + 20)? as usize,
+        11 | 12 => 28,
+        13 | 14 => 24,
+        _ => {
+            return Err(super::Error::Other(
+                format!("inode at 0x{offset:x} has unknown type {inode_type}")
+            ).into());
+        }
+    };
+
+    Ok(size)
+}
+
+pub struct Squashfs {
+    config: Rc<options::Config>,
+}
+
+impl Squashfs {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+impl super::Processor for Squashfs {
+    fn name(&self) -> &str {
+        "squashfs"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "squashfs" || x == "sqfs" || x == "sqsh"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+        let file_size = io.input_metadata.len();
+
+        let mut superblock = [0; SUPERBLOCK_LENGTH];
+        if let Err(e) = input.read_exact(&mut superblock) {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Err(super::Error::UnexpectedEOF(0, SUPERBLOCK_LENGTH).into());
+            }
+            return Err(e.into());
+        }
+
+        let sb = Superblock::parse(&superblock, file_size)?;
+        debug!("{}: {:?}", input_path.display(), sb);
+
+        // Everything is overwritten in place, so all offsets stay valid.
+        // The compression id and the flags are not touched.
+        let mut patches = vec![];
+
+        let mkfs_time = self.config.clamp_mtime(sb.mkfs_time as i64).max(0) as u32;
+        if mkfs_time != sb.mkfs_time {
+            trace::event(input_path, "field",
+                         format_args!("mkfs_time {} → {}", sb.mkfs_time, mkfs_time));
+            patches.push((MKFS_TIME_OFFSET as u64, mkfs_time.to_le_bytes().to_vec()));
+        }
+
+        let mut table = vec![0; (sb.directory_table_start - sb.inode_table_start) as usize];
+        input.seek(SeekFrom::Start(sb.inode_table_start))?;
+        input.read_exact(&mut table)?;
+
+        if let Some((mut data, block_offsets)) = read_metadata(&table)? {
+            let mut have_mod = false;
+
+            let mut offset = 0;
+            for _ in 0..sb.inode_count {
+                let mtime = u32_at(&data, offset + INODE_MTIME_OFFSET)?;
+                let new_mtime = self.config.clamp_mtime(mtime as i64).max(0) as u32;
+                if new_mtime != mtime {
+                    let number = u32_at(&data, offset + 12)?;
+                    trace::event(input_path, "field",
+                                 format_args!("inode {}: mtime {} → {}", number, mtime, new_mtime));
+                    data[offset + INODE_MTIME_OFFSET .. offset + INODE_MTIME_OFFSET + 4]
+                        .copy_from_slice(&new_mtime.to_le_bytes());
+                    have_mod = true;
+                }
+
+                offset += inode_size(&data, offset, sb.block_size)?;
+            }
+
+            if have_mod {
+                for (n, block) in data.chunks(METADATA_SIZE).enumerate() {
+                    patches.push((sb.inode_table_start + block_offsets[n] as u64, block.to_vec()));
+                }
+            }
+        } else {
+            // Without a decompressor, we cannot modify the inodes.
+            debug!("{}: inode table is compressed, not clamping inode mtimes",
+                   input_path.display());
+        }
+
+        let have_mod = !patches.is_empty();
+
+        if have_mod {
+            io.open_output()?;
+            let output = io.output.as_mut().unwrap();
+
+            input.seek(SeekFrom::Start(0))?;
+            io::copy(&mut input, output)?;
+
+            for (offset, data) in patches {
+                output.seek(SeekFrom::Start(offset))?;
+                output.write_all(&data)?;
+            }
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_metadata() {
+        let table = b"\x03\x80abc";
+        let (data, offsets) = read_metadata(table).unwrap().unwrap();
+        assert_eq!(data, b"abc");
+        assert_eq!(offsets, vec![2]);
+
+        // Compressed
+        assert!(read_metadata(b"\x03\x00abc").unwrap().is_none());
+
+        // Truncated
+        assert!(read_metadata(b"\x04\x80abc").is_err());
+
+        // A short block can only be the last one
+        assert!(read_metadata(b"\x01\x80a\x01\x80b").is_err());
+    }
+
+    #[test]
+    fn test_inode_size() {
+        let mut inode = vec![0; 64];
+
+        // Basic file with 10000 bytes in 4096-byte blocks
+        inode[0] = 2;
+        inode[20..24].copy_from_slice(&NO_FRAGMENT.to_le_bytes());
+        inode[28..32].copy_from_slice(&10000_u32.to_le_bytes());
+        assert_eq!(inode_size(&inode, 0, 4096).unwrap(), 32 + 3 * 4);
+
+        // The tail end is in a fragment
+        inode[20..24].copy_from_slice(&0_u32.to_le_bytes());
+        assert_eq!(inode_size(&inode, 0, 4096).unwrap(), 32 + 2 * 4);
+
+        // Basic symlink with a 5-byte target
+        inode[0] = 3;
+        inode[20..24].copy_from_slice(&5_u32.to_le_bytes());
+        assert_eq!(inode_size(&inode, 0, 4096).unwrap(), 29);
+
+        inode[0] = 15;
+        assert!(inode_size(&inode, 0, 4096).is_err());
+    }
+
+    #[test]
+    fn filter_squashfs() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Squashfs::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/image.squashfs")).unwrap());
+        assert!( h.filter(Path::new("/some/path/image.sqfs")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/image.squashfs.xz")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/squashfs")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+}
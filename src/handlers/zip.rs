@@ -0,0 +1,1449 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use anyhow::Result;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use log::{debug, warn};
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::handlers::InputOutputHelper;
+use crate::handlers::javadoc;
+use crate::options;
+use crate::trace;
+
+// https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+const LOCAL_HEADER_MAGIC: &[u8] = &[0x50, 0x4b, 0x03, 0x04];
+const CENTRAL_HEADER_MAGIC: &[u8] = &[0x50, 0x4b, 0x01, 0x02];
+const END_OF_CENTRAL_DIR_MAGIC: &[u8] = &[0x50, 0x4b, 0x05, 0x06];
+const ZIP64_END_OF_CENTRAL_DIR_LOCATOR_MAGIC: &[u8] = &[0x50, 0x4b, 0x06, 0x07];
+const DATA_DESCRIPTOR_MAGIC: &[u8] = &[0x50, 0x4b, 0x07, 0x08];
+
+const END_OF_CENTRAL_DIR_LENGTH: usize = 22;
+
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// The "version made by" host byte for UNIX.
+const HOST_UNIX: u8 = 3;
+
+/// Extended timestamp extra field ("UT").
+const EXTRA_EXTENDED_TIMESTAMP: u16 = 0x5455;
+
+/// Info-ZIP "new Unix" extra field ("ux") with the uid and gid.
+const EXTRA_UNIX_UID_GID: u16 = 0x7875;
+
+/// Recognize zip archives by the first local file header, for --content-detect.
+/// Empty archives, which only have the end of central directory record,
+/// are not worth processing.
+pub fn sniff(buf: &[u8]) -> bool {
+    buf.starts_with(LOCAL_HEADER_MAGIC)
+}
+
+pub struct Zip {
+    config: Rc<options::Config>,
+}
+
+impl Zip {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn super::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        Parser { data, offset }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.offset + count <= self.data.len() {
+            let slice = &self.data[self.offset .. self.offset + count];
+            self.offset += count;
+            Ok(slice)
+        } else {
+            Err(super::Error::UnexpectedEOF(self.offset as u64, count).into())
+        }
+    }
+
+    fn magic(&mut self, magic: &'static [u8]) -> Result<()> {
+        let offset = self.offset;
+        let buf = self.take(magic.len())?;
+        if buf != magic {
+            return Err(super::Error::BadMagic(offset as u64, buf.to_vec(), magic).into());
+        }
+        Ok(())
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+struct LocalHeader {
+    version_needed: u16,
+    flags: u16,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc32: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    name: Vec<u8>,
+    extra: Vec<u8>,
+}
+
+struct CentralHeader {
+    version_made_by: u16,
+    version_needed: u16,
+    flags: u16,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc32: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    disk_start: u16,
+    internal_attr: u16,
+    external_attr: u32,
+    local_header_offset: u32,
+    name: Vec<u8>,
+    extra: Vec<u8>,
+    comment: Vec<u8>,
+}
+
+struct Entry {
+    local: LocalHeader,
+    central: CentralHeader,
+    data: Vec<u8>,       // the compressed data
+    descriptor: Vec<u8>, // the data descriptor, if present, unparsed
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        String::from_utf8_lossy(&self.central.name).into_owned()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.central.name.ends_with(b"/")
+    }
+
+    /// Return the uncompressed contents of the entry.
+    fn read_content(&self) -> Result<Vec<u8>> {
+        let content = match self.central.method {
+            METHOD_STORED => self.data.clone(),
+            METHOD_DEFLATED => {
+                let mut content = vec![];
+                DeflateDecoder::new(&self.data[..]).read_to_end(&mut content)?;
+                content
+            }
+            method => {
+                return Err(super::Error::Other(
+                    format!("{}: unsupported compression method {}", self.name(), method)
+                ).into());
+            }
+        };
+
+        if crc32fast::hash(&content) != self.central.crc32 {
+            return Err(super::Error::Other(
+                format!("{}: CRC mismatch", self.name())
+            ).into());
+        }
+
+        Ok(content)
+    }
+
+    /// Replace the contents of the entry, compressing with the same method.
+    /// The sizes and checksum are stored in the local header, so the data
+    /// descriptor is not needed anymore.
+    fn set_content(&mut self, content: &[u8]) -> Result<()> {
+        self.data = match self.central.method {
+            METHOD_STORED => content.to_vec(),
+            METHOD_DEFLATED => {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+                encoder.write_all(content)?;
+                encoder.finish()?
+            }
+            _ => unreachable!(),  // read_content() must have been called first
+        };
+
+        let crc32 = crc32fast::hash(content);
+        let compressed_size = self.data.len() as u32;
+        let uncompressed_size = content.len() as u32;
+
+        self.local.flags &= !FLAG_DATA_DESCRIPTOR;
+        self.local.crc32 = crc32;
+        self.local.compressed_size = compressed_size;
+        self.local.uncompressed_size = uncompressed_size;
+        self.descriptor.clear();
+
+        self.central.flags &= !FLAG_DATA_DESCRIPTOR;
+        self.central.crc32 = crc32;
+        self.central.compressed_size = compressed_size;
+        self.central.uncompressed_size = uncompressed_size;
+
+        Ok(())
+    }
+}
+
+struct Archive {
+    prefix: Vec<u8>, // anything before the first entry, e.g. a self-extractor
+    entries: Vec<Entry>,
+    comment: Vec<u8>,
+}
+
+impl Archive {
+    fn find_end_of_central_dir(data: &[u8]) -> Result<usize> {
+        // The end of central directory record is at the end of the file,
+        // followed only by the archive comment (up to 64k).
+        let min = data.len().saturating_sub(END_OF_CENTRAL_DIR_LENGTH + 0xFFFF);
+        let max = data.len().checked_sub(END_OF_CENTRAL_DIR_LENGTH)
+            .ok_or(super::Error::UnexpectedEOF(0, END_OF_CENTRAL_DIR_LENGTH))?;
+
+        for offset in (min..=max).rev() {
+            if &data[offset .. offset + 4] == END_OF_CENTRAL_DIR_MAGIC {
+                let comment_len = u16::from_le_bytes(data[offset + 20 .. offset + 22].try_into().unwrap());
+                if offset + END_OF_CENTRAL_DIR_LENGTH + comment_len as usize == data.len() {
+                    return Ok(offset);
+                }
+            }
+        }
+
+        Err(super::Error::Other("end of central directory record not found".to_string()).into())
+    }
+
+    fn parse(data: &[u8]) -> Result<Self> {
+        let eocd = Self::find_end_of_central_dir(data)?;
+
+        if eocd >= 20 && &data[eocd - 20 .. eocd - 16] == ZIP64_END_OF_CENTRAL_DIR_LOCATOR_MAGIC {
+            return Err(super::Error::Other("zip64 archives are not supported".to_string()).into());
+        }
+
+        let mut p = Parser::new(data, eocd);
+        p.magic(END_OF_CENTRAL_DIR_MAGIC)?;
+        let disk = p.u16()?;
+        let cd_disk = p.u16()?;
+        let disk_entries = p.u16()?;
+        let entries = p.u16()?;
+        let _cd_size = p.u32()?;
+        let cd_offset = p.u32()?;
+        let comment_len = p.u16()?;
+        let comment = p.take(comment_len as usize)?.to_vec();
+
+        if disk != 0 || cd_disk != 0 || disk_entries != entries {
+            return Err(super::Error::Other("multi-disk archives are not supported".to_string()).into());
+        }
+
+        let mut p = Parser::new(data, cd_offset as usize);
+        let mut centrals = vec![];
+
+        for _ in 0..entries {
+            p.magic(CENTRAL_HEADER_MAGIC)?;
+
+            let version_made_by = p.u16()?;
+            let version_needed = p.u16()?;
+            let flags = p.u16()?;
+            let method = p.u16()?;
+            let time = p.u16()?;
+            let date = p.u16()?;
+            let crc32 = p.u32()?;
+            let compressed_size = p.u32()?;
+            let uncompressed_size = p.u32()?;
+            let name_len = p.u16()?;
+            let extra_len = p.u16()?;
+            let comment_len = p.u16()?;
+            let disk_start = p.u16()?;
+            let internal_attr = p.u16()?;
+            let external_attr = p.u32()?;
+            let local_header_offset = p.u32()?;
+
+            centrals.push(CentralHeader {
+                version_made_by,
+                version_needed,
+                flags,
+                method,
+                time,
+                date,
+                crc32,
+                compressed_size,
+                uncompressed_size,
+                disk_start,
+                internal_attr,
+                external_attr,
+                local_header_offset,
+                name: p.take(name_len as usize)?.to_vec(),
+                extra: p.take(extra_len as usize)?.to_vec(),
+                comment: p.take(comment_len as usize)?.to_vec(),
+            });
+        }
+
+        let first_offset = centrals.iter()
+            .map(|c| c.local_header_offset as usize)
+            .min()
+            .unwrap_or(cd_offset as usize);
+        let prefix = data[..first_offset.min(data.len())].to_vec();
+
+        let mut entries = vec![];
+
+        for central in centrals {
+            let mut p = Parser::new(data, central.local_header_offset as usize);
+            p.magic(LOCAL_HEADER_MAGIC)?;
+
+            let version_needed = p.u16()?;
+            let flags = p.u16()?;
+            let method = p.u16()?;
+            let time = p.u16()?;
+            let date = p.u16()?;
+            let crc32 = p.u32()?;
+            let compressed_size = p.u32()?;
+            let uncompressed_size = p.u32()?;
+            let name_len = p.u16()?;
+            let extra_len = p.u16()?;
+
+            let local = LocalHeader {
+                version_needed,
+                flags,
+                method,
+                time,
+                date,
+                crc32,
+                compressed_size,
+                uncompressed_size,
+                name: p.take(name_len as usize)?.to_vec(),
+                extra: p.take(extra_len as usize)?.to_vec(),
+            };
+
+            // If a data descriptor is used, the sizes in the local header are
+            // not set, so always use the sizes from the central directory.
+            let data = p.take(central.compressed_size as usize)?.to_vec();
+
+            let descriptor = if local.flags & FLAG_DATA_DESCRIPTOR > 0 {
+                // The signature is optional
+                let len = if p.data[p.offset..].starts_with(DATA_DESCRIPTOR_MAGIC) { 16 } else { 12 };
+                p.take(len)?.to_vec()
+            } else {
+                vec![]
+            };
+
+            entries.push(Entry { local, central, data, descriptor });
+        }
+
+        Ok(Archive { prefix, entries, comment })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = self.prefix.clone();
+        let mut offsets = vec![];
+
+        for entry in &self.entries {
+            let h = &entry.local;
+            offsets.push(out.len() as u32);
+
+            out.extend_from_slice(LOCAL_HEADER_MAGIC);
+            out.extend_from_slice(&h.version_needed.to_le_bytes());
+            out.extend_from_slice(&h.flags.to_le_bytes());
+            out.extend_from_slice(&h.method.to_le_bytes());
+            out.extend_from_slice(&h.time.to_le_bytes());
+            out.extend_from_slice(&h.date.to_le_bytes());
+            out.extend_from_slice(&h.crc32.to_le_bytes());
+            out.extend_from_slice(&h.compressed_size.to_le_bytes());
+            out.extend_from_slice(&h.uncompressed_size.to_le_bytes());
+            out.extend_from_slice(&(h.name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(h.extra.len() as u16).to_le_bytes());
+            out.extend_from_slice(&h.name);
+            out.extend_from_slice(&h.extra);
+            out.extend_from_slice(&entry.data);
+            out.extend_from_slice(&entry.descriptor);
+        }
+
+        let cd_offset = out.len() as u32;
+
+        for (entry, offset) in self.entries.iter().zip(offsets) {
+            let h = &entry.central;
+
+            out.extend_from_slice(CENTRAL_HEADER_MAGIC);
+            out.extend_from_slice(&h.version_made_by.to_le_bytes());
+            out.extend_from_slice(&h.version_needed.to_le_bytes());
+            out.extend_from_slice(&h.flags.to_le_bytes());
+            out.extend_from_slice(&h.method.to_le_bytes());
+            out.extend_from_slice(&h.time.to_le_bytes());
+            out.extend_from_slice(&h.date.to_le_bytes());
+            out.extend_from_slice(&h.crc32.to_le_bytes());
+            out.extend_from_slice(&h.compressed_size.to_le_bytes());
+            out.extend_from_slice(&h.uncompressed_size.to_le_bytes());
+            out.extend_from_slice(&(h.name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(h.extra.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(h.comment.len() as u16).to_le_bytes());
+            out.extend_from_slice(&h.disk_start.to_le_bytes());
+            out.extend_from_slice(&h.internal_attr.to_le_bytes());
+            out.extend_from_slice(&h.external_attr.to_le_bytes());
+            out.extend_from_slice(&offset.to_le_bytes());
+            out.extend_from_slice(&h.name);
+            out.extend_from_slice(&h.extra);
+            out.extend_from_slice(&h.comment);
+        }
+
+        let cd_size = out.len() as u32 - cd_offset;
+        let count = self.entries.len() as u16;
+
+        out.extend_from_slice(END_OF_CENTRAL_DIR_MAGIC);
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&cd_size.to_le_bytes());
+        out.extend_from_slice(&cd_offset.to_le_bytes());
+        out.extend_from_slice(&(self.comment.len() as u16).to_le_bytes());
+        out.extend_from_slice(&self.comment);
+
+        out
+    }
+}
+
+/// Call func on the data of each extra field with the given id.
+fn for_each_extra_field(extra: &mut [u8], id: u16, mut func: impl FnMut(&mut [u8])) {
+    let mut offset = 0;
+
+    while offset + 4 <= extra.len() {
+        let field_id = u16::from_le_bytes(extra[offset .. offset + 2].try_into().unwrap());
+        let len = u16::from_le_bytes(extra[offset + 2 .. offset + 4].try_into().unwrap()) as usize;
+        if offset + 4 + len > extra.len() {
+            break; // Malformed, ignore the rest
+        }
+
+        if field_id == id {
+            func(&mut extra[offset + 4 .. offset + 4 + len]);
+        }
+
+        offset += 4 + len;
+    }
+}
+
+/// Replace the data of each extra field with the given id by the value
+/// returned by func, if any, and adjust the length of the field. Returns
+/// None if nothing was replaced. Any trailing bytes that do not form a
+/// complete field are kept at the end.
+fn replace_extra_fields(
+    extra: &[u8],
+    id: u16,
+    mut func: impl FnMut(&[u8]) -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+
+    let mut out = vec![];
+    let mut have_mod = false;
+    let mut offset = 0;
+
+    while offset + 4 <= extra.len() {
+        let field_id = u16::from_le_bytes(extra[offset .. offset + 2].try_into().unwrap());
+        let len = u16::from_le_bytes(extra[offset + 2 .. offset + 4].try_into().unwrap()) as usize;
+        if offset + 4 + len > extra.len() {
+            break;
+        }
+
+        let field = &extra[offset + 4 .. offset + 4 + len];
+        match (field_id == id).then(|| func(field)).flatten() {
+            Some(new) => {
+                out.extend_from_slice(&field_id.to_le_bytes());
+                out.extend_from_slice(&(new.len() as u16).to_le_bytes());
+                out.extend_from_slice(&new);
+                have_mod = true;
+            }
+            None => {
+                out.extend_from_slice(&extra[offset .. offset + 4 + len]);
+            }
+        }
+
+        offset += 4 + len;
+    }
+
+    out.extend_from_slice(&extra[offset..]);
+
+    // The length of all extra fields is stored in 16 bits
+    (have_mod && out.len() <= u16::MAX as usize).then_some(out)
+}
+
+/// Return the data of a "ux" extra field with the uid and gid set to 0,
+/// stored in 4 bytes each like Info-ZIP does, or None if the field is
+/// already like that, empty (as in the central header of some
+/// archives), or not in the known format.
+fn zero_uid_gid(field: &[u8]) -> Option<Vec<u8>> {
+    const ZEROED: &[u8] = &[1, 4, 0, 0, 0, 0, 4, 0, 0, 0, 0];
+
+    // version, uid size, uid, gid size, gid
+    let [1, uid_size, rest @ ..] = field else {
+        return None;
+    };
+    let gid_size = *rest.get(*uid_size as usize)?;
+    if rest.len() != *uid_size as usize + 1 + gid_size as usize {
+        return None;
+    }
+
+    (field != ZEROED).then(|| ZEROED.to_vec())
+}
+
+/// Return the extra fields sorted by header id, or None if they are already
+/// sorted. The order of fields with the same id is kept. Any trailing bytes
+/// that do not form a complete field are kept at the end.
+fn sort_extra_fields(extra: &[u8]) -> Option<Vec<u8>> {
+    let mut fields = vec![];
+    let mut offset = 0;
+
+    while offset + 4 <= extra.len() {
+        let id = u16::from_le_bytes(extra[offset .. offset + 2].try_into().unwrap());
+        let len = u16::from_le_bytes(extra[offset + 2 .. offset + 4].try_into().unwrap()) as usize;
+        if offset + 4 + len > extra.len() {
+            break;
+        }
+
+        fields.push((id, &extra[offset .. offset + 4 + len]));
+        offset += 4 + len;
+    }
+
+    if fields.windows(2).all(|w| w[0].0 <= w[1].0) {
+        return None;
+    }
+
+    fields.sort_by_key(|(id, _)| *id);
+
+    let mut sorted: Vec<u8> = fields.iter().flat_map(|(_, field)| field.iter()).copied().collect();
+    sorted.extend_from_slice(&extra[offset..]);
+
+    assert_eq!(sorted.len(), extra.len());
+    Some(sorted)
+}
+
+fn clamp_extended_timestamp(field: &mut [u8], config: &options::Config) {
+    // The first byte has flags that say which of mtime, atime, ctime are
+    // present. The central header only has mtime, but the flags are the
+    // same as in the local header, so we stop when we run out of data.
+    if field.is_empty() {
+        return;
+    }
+    let flags = field[0];
+
+    let mut offset = 1;
+    for bit in 0..3 {
+        if flags & (1 << bit) == 0 {
+            continue;
+        }
+        if offset + 4 > field.len() {
+            break;
+        }
+
+        let ts = i32::from_le_bytes(field[offset .. offset + 4].try_into().unwrap());
+        let new_ts = config.clamp_mtime(ts as i64) as i32;
+        if new_ts != ts {
+            field[offset .. offset + 4].copy_from_slice(&new_ts.to_le_bytes());
+        }
+        offset += 4;
+    }
+}
+
+impl Zip {
+    fn normalize_wheel_metadata(&self, input_path: &Path, archive: &mut Archive) -> Result<()> {
+        // Entries which were modified and need to be updated in RECORD
+        let mut modified = vec![];
+
+        for entry in &mut archive.entries {
+            let is_wheel = entry.central.name.ends_with(b".dist-info/WHEEL");
+            let is_metadata = entry.central.name.ends_with(b".dist-info/METADATA");
+            if !is_wheel && !is_metadata {
+                continue;
+            }
+
+            let content = entry.read_content()?;
+            let Ok(text) = std::str::from_utf8(&content) else {
+                warn!("{}: {}: not valid UTF-8, ignoring", input_path.display(), entry.name());
+                continue;
+            };
+
+            let new = if is_wheel {
+                let new = normalize_wheel_tags(text);
+                if new.is_some() {
+                    debug!("{}: {}: sorting tags", input_path.display(), entry.name());
+                    trace::event(input_path, "field", format_args!("{}: tags sorted", entry.name()));
+                }
+                new
+            } else {
+                let mut new = normalize_metadata_folding(text);
+                if new.is_some() {
+                    debug!("{}: {}: normalizing folding", input_path.display(), entry.name());
+                    trace::event(input_path, "field", format_args!("{}: folding normalized", entry.name()));
+                }
+
+                if self.config.wheel_scrub_description {
+                    let text = new.as_deref().unwrap_or(text);
+                    if let Some(scrubbed) = scrub_description(&self.config, text)? {
+                        debug!("{}: {}: scrubbing description", input_path.display(), entry.name());
+                        trace::event(input_path, "field", format_args!("{}: description scrubbed", entry.name()));
+                        new = Some(scrubbed);
+                    }
+                }
+                new
+            };
+
+            if let Some(new) = new {
+                entry.set_content(new.as_bytes())?;
+                modified.push((entry.name(), new.into_bytes()));
+            }
+        }
+
+        self.update_records(input_path, archive, &modified)
+    }
+
+    fn update_records(
+        &self,
+        input_path: &Path,
+        archive: &mut Archive,
+        modified: &[(String, Vec<u8>)],
+    ) -> Result<()> {
+
+        if modified.is_empty() {
+            return Ok(());
+        }
+
+        for entry in &mut archive.entries {
+            if !entry.central.name.ends_with(b".dist-info/RECORD") {
+                continue;
+            }
+
+            let content = entry.read_content()?;
+            let Ok(record) = std::str::from_utf8(&content) else {
+                warn!("{}: {}: not valid UTF-8, ignoring", input_path.display(), entry.name());
+                continue;
+            };
+
+            let mut record = record.to_string();
+            let mut have_mod = false;
+
+            for (path, content) in modified {
+                if let Some(new) = update_record_entry(&record, path, content) {
+                    debug!("{}: {}: updating entry for {}", input_path.display(), entry.name(), path);
+                    trace::event(input_path, "field", format_args!("{}: entry for {} updated", entry.name(), path));
+                    record = new;
+                    have_mod = true;
+                }
+            }
+
+            if have_mod {
+                entry.set_content(record.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn normalize_records(&self, input_path: &Path, archive: &mut Archive) -> Result<()> {
+        for entry in &mut archive.entries {
+            if !entry.central.name.ends_with(b".dist-info/RECORD") {
+                continue;
+            }
+
+            let content = entry.read_content()?;
+            let Ok(record) = std::str::from_utf8(&content) else {
+                warn!("{}: {}: not valid UTF-8, ignoring", input_path.display(), entry.name());
+                continue;
+            };
+
+            if let Some(new) = normalize_record(record) {
+                debug!("{}: {}: normalizing", input_path.display(), entry.name());
+                trace::event(input_path, "field", format_args!("{}: lines sorted", entry.name()));
+                entry.set_content(new.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clamp_mtimes(&self, input_path: &Path, archive: &mut Archive) {
+        let Some(epoch) = self.config.source_date_epoch else {
+            return;
+        };
+
+        let dos_epoch = time::OffsetDateTime::from_unix_timestamp(epoch)
+            .map_err(|e| e.to_string())
+            .and_then(|t| zip::DateTime::try_from(t).map_err(|e| e.to_string()));
+        let dos_epoch = match dos_epoch {
+            Err(e) => {
+                warn!("Cannot convert epoch {} to zip::DateTime: {}", epoch, e);
+                None
+            }
+            Ok(dos_epoch) => Some(dos_epoch),
+        };
+
+        for entry in &mut archive.entries {
+            if let Some(dos_epoch) = dos_epoch {
+                let central = zip::DateTime::from_msdos(entry.central.date, entry.central.time);
+                let local = zip::DateTime::from_msdos(entry.local.date, entry.local.time);
+
+                for mtime in [central, local] {
+                    let mtime = match mtime.to_time() {
+                        Err(e) => {
+                            warn!("{}: component {}: {}", input_path.display(), entry.name(), e);
+                            continue;
+                        }
+                        Ok(mtime) => mtime.unix_timestamp(),
+                    };
+
+                    if self.config.clamp_mtime(mtime) != mtime {
+                        debug!("{}: {}: clamping mtime {}", input_path.display(), entry.name(), mtime);
+                        trace::event(input_path, "field", format_args!("{}: mtime {} clamped", entry.name(), mtime));
+
+                        entry.central.date = dos_epoch.datepart();
+                        entry.central.time = dos_epoch.timepart();
+                        entry.local.date = dos_epoch.datepart();
+                        entry.local.time = dos_epoch.timepart();
+                        break;
+                    }
+                }
+            }
+
+            for extra in [&mut entry.local.extra, &mut entry.central.extra] {
+                for_each_extra_field(extra, EXTRA_EXTENDED_TIMESTAMP,
+                                     |field| clamp_extended_timestamp(field, &self.config));
+            }
+        }
+    }
+
+    fn strip_uid_gid(&self, input_path: &Path, archive: &mut Archive) {
+        for entry in &mut archive.entries {
+            let name = entry.name();
+
+            for extra in [&mut entry.local.extra, &mut entry.central.extra] {
+                if let Some(new) = replace_extra_fields(extra, EXTRA_UNIX_UID_GID, zero_uid_gid) {
+                    debug!("{}: {}: resetting uid and gid", input_path.display(), name);
+                    trace::event(input_path, "field", format_args!("{}: uid and gid reset", name));
+                    *extra = new;
+                }
+            }
+        }
+    }
+
+    fn sort_extra_fields(&self, input_path: &Path, archive: &mut Archive) {
+        for entry in &mut archive.entries {
+            let name = entry.name();
+
+            for extra in [&mut entry.local.extra, &mut entry.central.extra] {
+                if let Some(sorted) = sort_extra_fields(extra) {
+                    debug!("{}: {}: sorting extra fields", input_path.display(), name);
+                    trace::event(input_path, "field", format_args!("{}: extra fields sorted", name));
+                    *extra = sorted;
+                }
+            }
+        }
+    }
+
+    fn normalize_version_made_by(&self, input_path: &Path, archive: &mut Archive) {
+        // The "version made by" field is two bytes: the lower byte is the
+        // version of the specification that the creator supports, the upper
+        // byte is the host system. Different tools set both differently.
+        // We set the host to UNIX and the spec version to the version needed
+        // to extract the entry, i.e. based on the features that are actually
+        // used.
+        for entry in &mut archive.entries {
+            let is_dir = entry.is_dir();
+            let c = &mut entry.central;
+
+            let host = (c.version_made_by >> 8) as u8;
+            let version = (HOST_UNIX as u16) << 8 | (c.version_needed & 0xFF);
+
+            if host != HOST_UNIX {
+                // The upper two bytes of the external attributes are only
+                // meaningful for UNIX, i.e. they hold the file mode. If we
+                // change the host, we need to set them.
+                let mut mode = if is_dir { 0o40755 } else { 0o100644 };
+                if c.external_attr & 0x01 > 0 {
+                    // MS-DOS read-only attribute
+                    mode &= !0o222;
+                }
+
+                c.external_attr = mode << 16 | c.external_attr & 0xFFFF;
+            }
+
+            if c.version_made_by != version {
+                debug!("{}: {}: changing version made by from 0x{:04x} to 0x{:04x}",
+                       input_path.display(), String::from_utf8_lossy(&c.name),
+                       c.version_made_by, version);
+                trace::event(input_path, "field",
+                             format_args!("{}: version made by 0x{:04x} → 0x{:04x}",
+                                          String::from_utf8_lossy(&c.name), c.version_made_by, version));
+                c.version_made_by = version;
+            }
+        }
+    }
+}
+
+/// Return the path field of a RECORD line, possibly quoted,
+/// and the rest of the line.
+fn split_record_path(line: &str) -> (&str, &str) {
+    if line.starts_with('"') {
+        // A quoted field ends with a quote that is not doubled.
+        let mut chars = line.char_indices().skip(1).peekable();
+        while let Some((i, c)) = chars.next() {
+            if c == '"' {
+                if chars.peek().is_some_and(|(_, c)| *c == '"') {
+                    chars.next();
+                } else {
+                    return line.split_at(i + 1);
+                }
+            }
+        }
+        (line, "")
+    } else {
+        line.split_at(line.find(',').unwrap_or(line.len()))
+    }
+}
+
+/// Return the path in the path field of a RECORD line. A quoted field is
+/// unquoted and doubled quotes are replaced by one, as in CSV, and
+/// backslashes are replaced by forward slashes.
+fn record_path(field: &str) -> String {
+    let path = match field.strip_prefix('"') {
+        Some(quoted) => quoted.strip_suffix('"').unwrap_or(quoted).replace("\"\"", "\""),
+        None => field.to_string(),
+    };
+    path.replace('\\', "/")
+}
+
+/// Sort the lines of a .dist-info/RECORD file by path and use forward
+/// slashes as path separators. Returns None if nothing needs to change.
+fn normalize_record(record: &str) -> Option<String> {
+    let mut lines: Vec<(String, &str)> = record
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (path, rest) = split_record_path(line);
+            (path.replace('\\', "/"), rest)
+        })
+        .collect();
+
+    lines.sort_by_cached_key(|(path, _)| record_path(path));
+
+    let mut new = String::with_capacity(record.len());
+    for (path, rest) in lines {
+        new.push_str(&path);
+        new.push_str(rest);
+        new.push('\n');
+    }
+
+    if new != record { Some(new) } else { None }
+}
+
+/// Encode as base64 with the URL-safe alphabet and without padding,
+/// as used for hashes in RECORD files.
+fn urlsafe_b64encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+        }
+    }
+    out
+}
+
+/// Return the hash field for a RECORD line.
+fn record_hash(content: &[u8]) -> String {
+    format!("sha256={}", urlsafe_b64encode(&Sha256::digest(content)))
+}
+
+/// Update the hash and size for the given path in a RECORD file.
+/// Returns None if the path is not listed or nothing needs to change.
+fn update_record_entry(record: &str, path: &str, content: &[u8]) -> Option<String> {
+    let mut have_mod = false;
+    let mut new = String::with_capacity(record.len());
+
+    for line in record.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        let (text, newline) = match text.strip_suffix('\r') {
+            Some(text) => (text, if newline.is_empty() { "\r" } else { "\r\n" }),
+            None => (text, newline),
+        };
+
+        let (line_path, rest) = split_record_path(text);
+        if record_path(line_path) == path {
+            let rest2 = format!(",{},{}", record_hash(content), content.len());
+            if rest != rest2 {
+                new.push_str(line_path);
+                new.push_str(&rest2);
+                new.push_str(newline);
+                have_mod = true;
+                continue;
+            }
+        }
+
+        new.push_str(line);
+    }
+
+    if have_mod { Some(new) } else { None }
+}
+
+/// Sort the Tag lines in a .dist-info/WHEEL file. The other lines
+/// are not moved. Returns None if nothing needs to change.
+fn normalize_wheel_tags(wheel: &str) -> Option<String> {
+    let mut lines: Vec<(&str, &str)> = wheel
+        .split_inclusive('\n')
+        .map(|line| {
+            let text = line.trim_end_matches(['\r', '\n']);
+            (text, &line[text.len()..])
+        })
+        .collect();
+
+    let mut tags: Vec<&str> = lines.iter()
+        .map(|(text, _)| *text)
+        .filter(|text| text.starts_with("Tag:"))
+        .collect();
+    tags.sort();
+
+    let mut tags = tags.into_iter();
+    for (text, _) in lines.iter_mut() {
+        if text.starts_with("Tag:") {
+            *text = tags.next().unwrap();
+        }
+    }
+
+    let new: String = lines.iter().flat_map(|(text, newline)| [*text, *newline]).collect();
+    if new != wheel { Some(new) } else { None }
+}
+
+/// Canonicalize the folding of the header fields in a .dist-info/METADATA
+/// file. Multi-line values, most importantly Description, are written by
+/// different tools with the continuation lines indented by 8 spaces or by
+/// 7 spaces and "|". We use 8 spaces, like setuptools. The separator after
+/// the field name is set to ": " and CRLF line endings are replaced by LF.
+/// Continuation lines with other indentation and the message body after
+/// the first empty line are not modified. Returns None if nothing needs
+/// to change.
+fn normalize_metadata_folding(metadata: &str) -> Option<String> {
+    let mut new = String::with_capacity(metadata.len());
+    let mut in_headers = true;
+
+    for line in metadata.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+
+        if !in_headers {
+            new.push_str(line);
+            continue;
+        }
+
+        if text.is_empty() {
+            // End of headers, the body follows
+            in_headers = false;
+        } else if let Some(rest) = text.strip_prefix("       |")
+            .or_else(|| text.strip_prefix("        ")) {
+            new.push_str("        ");
+            new.push_str(rest);
+        } else if text.starts_with([' ', '\t']) {
+            new.push_str(text);
+        } else if let Some((name, value)) = text.split_once(':') {
+            let value = value.trim_start_matches([' ', '\t']);
+            new.push_str(name);
+            new.push(':');
+            if !value.is_empty() {
+                new.push(' ');
+                new.push_str(value);
+            }
+        } else {
+            new.push_str(text);
+        }
+
+        if line.ends_with('\n') {
+            new.push('\n');
+        }
+    }
+
+    if new != metadata { Some(new) } else { None }
+}
+
+/// With --wheel-scrub-description, remove the generator version and dates
+/// from a long description in .dist-info/METADATA. This is only done if
+/// Description-Content-Type says that the description is HTML or Markdown.
+/// The description can be either in the Description field or in the body.
+fn scrub_description(config: &options::Config, metadata: &str) -> Result<Option<String>> {
+    let content_type = metadata
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix("Description-Content-Type:"))
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    if !content_type.is_some_and(|ct| ct.starts_with("text/html") || ct.starts_with("text/markdown")) {
+        return Ok(None);
+    }
+
+    //   <meta name="generator" content="Docutils 0.20.1: https://docutils.sourceforge.io/">
+    //   <!-- Generated by pandoc 3.1 on 2024-03-02 -->
+    // The name of the generator is kept, the version and date are dropped.
+    let generator_re = RegexBuilder::new(r#"(<meta name="generator" content="[^" ]+) [^"]*(")"#)
+        .case_insensitive(true)
+        .build()?;
+    let comment_re = Regex::new(r"(<!-- Generated by [^ >]+) .+? (-->)")?;
+
+    let mut new = String::with_capacity(metadata.len());
+    let mut in_headers = true;
+    let mut in_description = false;
+
+    for line in metadata.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+
+        if in_headers {
+            if text.is_empty() {
+                in_headers = false;
+                in_description = false;
+            } else if !text.starts_with([' ', '\t']) {
+                in_description = text.starts_with("Description:");
+            }
+        }
+
+        if in_headers && !in_description {
+            new.push_str(line);
+            continue;
+        }
+
+        // The javadoc handler knows how to clamp dates in <meta> tags
+        let text2 = javadoc::process_line(config, text)?.unwrap_or_else(|| text.to_string());
+        let text2 = generator_re.replace_all(&text2, "$1$2");
+        let text2 = comment_re.replace_all(&text2, "$1 $2");
+
+        new.push_str(&text2);
+        new.push_str(&line[text.len()..]);
+    }
+
+    Ok(if new != metadata { Some(new) } else { None })
+}
+
+/// Return the path of the source file for a pyc file,
+/// or None if the path does not look like a pyc file.
+fn pyc_source_name(name: &str) -> Option<String> {
+    let stem = name.strip_suffix(".pyc")?;
+
+    // PEP 3147: dir/__pycache__/name.cpython-312.pyc or
+    // dir/__pycache__/name.cpython-312.opt-1.pyc → dir/name.py
+    let (dir, base) = match stem.rsplit_once('/') {
+        Some((dir, base)) => (Some(dir), base),
+        None => (None, stem),
+    };
+
+    if let Some(parent) = dir.and_then(|d| d.strip_suffix("__pycache__")) {
+        if parent.is_empty() || parent.ends_with('/') {
+            let module = base.split('.').next().unwrap();
+            return Some(format!("{parent}{module}.py"));
+        }
+    }
+
+    // Legacy location: dir/name.pyc → dir/name.py
+    Some(format!("{stem}.py"))
+}
+
+/// Return the names of pyc entries for which there is no source entry.
+fn orphan_pyc_entries<'a>(names: &[&'a str]) -> Vec<&'a str> {
+    names.iter()
+        .filter(|name| {
+            pyc_source_name(name).is_some_and(|source| !names.contains(&source.as_str()))
+        })
+        .copied()
+        .collect()
+}
+
+impl super::Processor for Zip {
+    fn name(&self) -> &str {
+        "zip"
+    }
+
+    fn filter(&self, path: &Path) -> Result<bool> {
+        Ok(path.extension().is_some_and(|x| x == "zip" || x == "whl"))
+    }
+
+    fn process(&self, input_path: &Path) -> Result<super::ProcessResult> {
+        let (mut io, mut input) = InputOutputHelper::open(input_path, &self.config)?;
+
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+
+        let mut archive = Archive::parse(&data)?;
+        debug!("{}: zip archive with {} entries", input_path.display(), archive.entries.len());
+
+        if self.config.warn_orphan_pyc {
+            let names: Vec<String> = archive.entries.iter().map(|e| e.name()).collect();
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+            for name in orphan_pyc_entries(&names) {
+                warn!("{}: {}: compiled bytecode without source file",
+                      input_path.display(), name);
+            }
+        }
+
+        // The paths in RECORD are normalized first, so that the hashes
+        // of the modified metadata files are updated in the final form
+        self.normalize_records(input_path, &mut archive)?;
+        self.normalize_wheel_metadata(input_path, &mut archive)?;
+        if self.config.zip_clamp_time {
+            self.clamp_mtimes(input_path, &mut archive);
+        }
+        if self.config.zip_strip_uid_gid {
+            self.strip_uid_gid(input_path, &mut archive);
+        }
+        if self.config.zip_sort_extra_fields {
+            self.sort_extra_fields(input_path, &mut archive);
+        }
+        self.normalize_version_made_by(input_path, &mut archive);
+
+        let output = archive.serialize();
+        let have_mod = output != data;
+
+        if have_mod {
+            io.open_output()?;
+            io.output.as_mut().unwrap().write_all(&output)?;
+        }
+
+        io.finalize(have_mod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert!( sniff(b"PK\x03\x04\x14\x00"));
+        assert!( sniff(b"PK\x03\x04"));
+        assert!(!sniff(b"PK\x05\x06"));
+        assert!(!sniff(b"PK\x03"));
+        assert!(!sniff(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn filter_zip() {
+        let cfg = Rc::new(options::Config::empty(0, false));
+        let h = Zip::boxed(&cfg);
+
+        assert!( h.filter(Path::new("/some/path/foo.zip")).unwrap());
+        assert!( h.filter(Path::new("/some/path/foo-1.0-py3-none-any.whl")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/foo.jar")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/foo.zip.gz")).unwrap());
+        assert!(!h.filter(Path::new("/some/path/zip")).unwrap());
+        assert!(!h.filter(Path::new("/")).unwrap());
+    }
+
+    #[test]
+    fn test_clamp_extended_timestamp() {
+        let config = options::Config::empty(0x100, false);
+
+        // mtime and atime
+        let mut field = [0x03, 0x10, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00];
+        clamp_extended_timestamp(&mut field, &config);
+        assert_eq!(field, [0x03, 0x10, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]);
+
+        // central header: flags say mtime, atime, ctime, but only mtime is present
+        let mut field = [0x07, 0x00, 0x20, 0x00, 0x00];
+        clamp_extended_timestamp(&mut field, &config);
+        assert_eq!(field, [0x07, 0x00, 0x01, 0x00, 0x00]);
+
+        let mut field = [];
+        clamp_extended_timestamp(&mut field, &config);
+    }
+
+    #[test]
+    fn test_sort_extra_fields() {
+        let extra = [
+            0x75, 0x78, 0x02, 0x00, 0xBB, 0xBB,
+            0x55, 0x54, 0x01, 0x00, 0xAA,
+            0x0a, 0x00, 0x00, 0x00,
+            0x55, 0x54, 0x01, 0x00, 0xCC,
+            0x55, 0x54, 0x09, 0x00, 0xDD, // truncated
+        ];
+
+        assert_eq!(sort_extra_fields(&extra).unwrap(), [
+            0x0a, 0x00, 0x00, 0x00,
+            0x55, 0x54, 0x01, 0x00, 0xAA,
+            0x55, 0x54, 0x01, 0x00, 0xCC,
+            0x75, 0x78, 0x02, 0x00, 0xBB, 0xBB,
+            0x55, 0x54, 0x09, 0x00, 0xDD,
+        ]);
+
+        assert!(sort_extra_fields(&[0x55, 0x54, 0x01, 0x00, 0xAA, 0x75, 0x78, 0x00, 0x00]).is_none());
+        assert!(sort_extra_fields(&[0x75, 0x78, 0x00]).is_none());
+        assert!(sort_extra_fields(&[]).is_none());
+    }
+
+    #[test]
+    fn test_replace_extra_fields() {
+        let extra = [
+            0x55, 0x54, 0x01, 0x00, 0xAA,
+            0x75, 0x78, 0x02, 0x00, 0xBB, 0xBB,
+            0x75, 0x78, 0x00, 0x00,
+            0x75, 0x78, 0x09, 0x00, 0xDD, // truncated
+        ];
+
+        let new = replace_extra_fields(&extra, 0x7875, |f| (!f.is_empty()).then(|| vec![0xEE; 3]));
+        assert_eq!(new.unwrap(), [
+            0x55, 0x54, 0x01, 0x00, 0xAA,
+            0x75, 0x78, 0x03, 0x00, 0xEE, 0xEE, 0xEE,
+            0x75, 0x78, 0x00, 0x00,
+            0x75, 0x78, 0x09, 0x00, 0xDD,
+        ]);
+
+        assert!(replace_extra_fields(&extra, 0x7875, |_| None).is_none());
+        assert!(replace_extra_fields(&extra, 0x000a, |_| Some(vec![])).is_none());
+    }
+
+    #[test]
+    fn test_zero_uid_gid() {
+        let zeroed = [1, 4, 0, 0, 0, 0, 4, 0, 0, 0, 0];
+
+        assert_eq!(zero_uid_gid(&[1, 4, 0xe8, 0x03, 0, 0, 4, 0xe8, 0x03, 0, 0]).unwrap(), zeroed);
+        // 2-byte ids are converted to the usual size
+        assert_eq!(zero_uid_gid(&[1, 2, 0xe8, 0x03, 2, 0, 0]).unwrap(), zeroed);
+        assert!(zero_uid_gid(&zeroed).is_none());
+
+        // Empty, as in central headers written by some tools
+        assert!(zero_uid_gid(&[]).is_none());
+        // Unknown version
+        assert!(zero_uid_gid(&[2, 4, 1, 0, 0, 0, 4, 1, 0, 0, 0]).is_none());
+        // Sizes do not match the length
+        assert!(zero_uid_gid(&[1, 4, 1, 0, 0, 0, 4, 1, 0]).is_none());
+        assert!(zero_uid_gid(&[1, 8, 1, 0]).is_none());
+    }
+
+    #[test]
+    fn test_for_each_extra_field() {
+        let mut extra = vec![
+            0x55, 0x54, 0x01, 0x00, 0xAA,
+            0x75, 0x78, 0x02, 0x00, 0xBB, 0xBB,
+            0x55, 0x54, 0x01, 0x00, 0xCC,
+            0x55, 0x54, 0x09, 0x00, 0xDD, // truncated
+        ];
+
+        let mut seen = vec![];
+        for_each_extra_field(&mut extra, 0x5455, |f| seen.push(f.to_vec()));
+        assert_eq!(seen, vec![vec![0xAA], vec![0xCC]]);
+    }
+
+    #[test]
+    fn test_urlsafe_b64encode() {
+        assert_eq!(urlsafe_b64encode(b""), "");
+        assert_eq!(urlsafe_b64encode(b"f"), "Zg");
+        assert_eq!(urlsafe_b64encode(b"fo"), "Zm8");
+        assert_eq!(urlsafe_b64encode(b"foo"), "Zm9v");
+        assert_eq!(urlsafe_b64encode(b"foob"), "Zm9vYg");
+        assert_eq!(urlsafe_b64encode(&[0xfb, 0xff, 0xbf]), "-_-_");
+    }
+
+    #[test]
+    fn test_record_hash() {
+        // python3 -c 'import hashlib, base64; print(base64.urlsafe_b64encode(hashlib.sha256(b"").digest()))'
+        assert_eq!(record_hash(b""), "sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU");
+    }
+
+    #[test]
+    fn test_update_record_entry() {
+        let record = concat!(
+            "a/WHEEL,sha256=xxx,3\r\n",
+            "\"a/b,c\",sha256=xxx,3\r\n",
+            "a/RECORD,,\r\n",
+        );
+
+        assert_eq!(update_record_entry(record, "a/WHEEL", b"").unwrap(), concat!(
+            "a/WHEEL,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\r\n",
+            "\"a/b,c\",sha256=xxx,3\r\n",
+            "a/RECORD,,\r\n",
+        ));
+        assert_eq!(update_record_entry(record, "a/b,c", b"").unwrap(), concat!(
+            "a/WHEEL,sha256=xxx,3\r\n",
+            "\"a/b,c\",sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\r\n",
+            "a/RECORD,,\r\n",
+        ));
+
+        // The paths are compared after unquoting and with forward slashes
+        assert_eq!(update_record_entry("\"a\\\"\"b\"\"\",sha256=xxx,3\n", "a/\"b\"", b"").unwrap(),
+                   "\"a\\\"\"b\"\"\",sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\n");
+
+        assert!(update_record_entry(record, "a/other", b"").is_none());
+        assert!(update_record_entry("a,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0", "a", b"").is_none());
+    }
+
+    #[test]
+    fn test_normalize_wheel_tags() {
+        assert_eq!(
+            normalize_wheel_tags(concat!(
+                "Wheel-Version: 1.0\n",
+                "Tag: cp312-cp312-manylinux_2_17_x86_64\n",
+                "Tag: cp312-cp312-manylinux2014_x86_64\n",
+                "Root-Is-Purelib: false\n",
+                "Tag: cp312-abi3-linux_x86_64",
+            )).unwrap(),
+            concat!(
+                "Wheel-Version: 1.0\n",
+                "Tag: cp312-abi3-linux_x86_64\n",
+                "Tag: cp312-cp312-manylinux2014_x86_64\n",
+                "Root-Is-Purelib: false\n",
+                "Tag: cp312-cp312-manylinux_2_17_x86_64",
+            ));
+
+        assert!(normalize_wheel_tags("Wheel-Version: 1.0\nTag: py2-none-any\nTag: py3-none-any\n").is_none());
+        assert!(normalize_wheel_tags("Wheel-Version: 1.0\n").is_none());
+        assert!(normalize_wheel_tags("").is_none());
+    }
+
+    #[test]
+    fn test_normalize_metadata_folding() {
+        assert_eq!(
+            normalize_metadata_folding(concat!(
+                "Metadata-Version:2.1\r\n",
+                "Name:   foo\r\n",
+                "Description: Foo\r\n",
+                "       |\r\n",
+                "       |    indented\r\n",
+                "        eight spaces\r\n",
+                "  other folding\r\n",
+                "Keywords:\r\n",
+                "\r\n",
+                "Body:  not a header\r\n",
+            )).unwrap(),
+            concat!(
+                "Metadata-Version: 2.1\n",
+                "Name: foo\n",
+                "Description: Foo\n",
+                "        \n",
+                "            indented\n",
+                "        eight spaces\n",
+                "  other folding\n",
+                "Keywords:\n",
+                "\n",
+                "Body:  not a header\r\n",
+            ));
+
+        assert!(normalize_metadata_folding("Metadata-Version: 2.1\nName: foo\n\nBody\n").is_none());
+        assert!(normalize_metadata_folding("Description: a\n        b\n        c").is_none());
+        assert!(normalize_metadata_folding("").is_none());
+    }
+
+    #[test]
+    fn test_scrub_description() {
+        let config = options::Config::empty(1704106800, false);
+        let scrub = |s| scrub_description(&config, s).unwrap();
+
+        assert_eq!(
+            scrub(concat!(
+                "Name: foo\n",
+                "Description: <!-- Generated by pandoc 3.1 on 2024-03-02 --> <p>x</p>\n",
+                "        <meta name=\"generator\" content=\"Docutils 0.20.1\">\n",
+                "Keywords: <!-- Generated by pandoc 3.1 on 2024-03-02 -->\n",
+                "Description-Content-Type: text/markdown; charset=UTF-8\n",
+                "\n",
+                "<!-- Generated by mkdocs 1.5 on 2024-03-02 -->\r\n",
+                "# Foo <!-- Generated by x -->\n",
+            )).unwrap(),
+            concat!(
+                "Name: foo\n",
+                "Description: <!-- Generated by pandoc --> <p>x</p>\n",
+                "        <meta name=\"generator\" content=\"Docutils\">\n",
+                "Keywords: <!-- Generated by pandoc 3.1 on 2024-03-02 -->\n",
+                "Description-Content-Type: text/markdown; charset=UTF-8\n",
+                "\n",
+                "<!-- Generated by mkdocs -->\r\n",
+                "# Foo <!-- Generated by x -->\n",
+            ));
+
+        // Only HTML and Markdown are scrubbed
+        let rst = "Description-Content-Type: text/x-rst\n\n<!-- Generated by pandoc 3.1 on 2024-03-02 -->\n";
+        assert!(scrub(rst).is_none());
+        let none = "Name: foo\n\n<!-- Generated by pandoc 3.1 on 2024-03-02 -->\n";
+        assert!(scrub(none).is_none());
+
+        let html = "Description-Content-Type: TEXT/HTML\n\n<!-- Generated by pandoc -->\n";
+        assert!(scrub(html).is_none());
+    }
+
+    #[test]
+    fn test_pyc_source_name() {
+        assert_eq!(pyc_source_name("foo/bar.pyc").unwrap(), "foo/bar.py");
+        assert_eq!(pyc_source_name("bar.pyc").unwrap(), "bar.py");
+        assert_eq!(pyc_source_name("foo/__pycache__/bar.cpython-312.pyc").unwrap(), "foo/bar.py");
+        assert_eq!(pyc_source_name("foo/__pycache__/bar.cpython-312.opt-2.pyc").unwrap(), "foo/bar.py");
+        assert_eq!(pyc_source_name("__pycache__/bar.cpython-36.pyc").unwrap(), "bar.py");
+        assert_eq!(pyc_source_name("foo/not__pycache__/bar.pyc").unwrap(), "foo/not__pycache__/bar.py");
+        assert!(pyc_source_name("foo/bar.py").is_none());
+        assert!(pyc_source_name("foo/__pycache__/").is_none());
+    }
+
+    #[test]
+    fn test_orphan_pyc_entries() {
+        let names = [
+            "foo/",
+            "foo/__init__.py",
+            "foo/__pycache__/__init__.cpython-312.pyc",
+            "foo/__pycache__/gone.cpython-312.opt-1.pyc",
+            "foo/legacy.pyc",
+            "foo-1.0.dist-info/WHEEL",
+        ];
+
+        assert_eq!(orphan_pyc_entries(&names),
+                   vec!["foo/__pycache__/gone.cpython-312.opt-1.pyc", "foo/legacy.pyc"]);
+        assert!(orphan_pyc_entries(&names[..3]).is_empty());
+        assert!(orphan_pyc_entries(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_split_record_path() {
+        assert_eq!(split_record_path("foo/bar.py,sha256=abc,12"), ("foo/bar.py", ",sha256=abc,12"));
+        assert_eq!(split_record_path("foo/RECORD,,"), ("foo/RECORD", ",,"));
+        assert_eq!(split_record_path(r#""foo,bar.py",sha256=abc,12"#), (r#""foo,bar.py""#, ",sha256=abc,12"));
+        assert_eq!(split_record_path(r#""a""b",,"#), (r#""a""b""#, ",,"));
+        assert_eq!(split_record_path("foo"), ("foo", ""));
+        assert_eq!(split_record_path(r#""foo"#), (r#""foo"#, ""));
+    }
+
+    #[test]
+    fn test_record_path() {
+        assert_eq!(record_path("foo/bar.py"), "foo/bar.py");
+        assert_eq!(record_path("foo\\bar.py"), "foo/bar.py");
+        assert_eq!(record_path(r#""foo,bar.py""#), "foo,bar.py");
+        assert_eq!(record_path(r#""a""b""#), r#"a"b"#);
+        assert_eq!(record_path(r#""a""""#), r#"a""#);
+        assert_eq!(record_path(r#""foo"#), "foo");
+    }
+
+    #[test]
+    fn test_normalize_record() {
+        assert_eq!(
+            normalize_record(concat!(
+                "foo-1.0.dist-info\\RECORD,,\r\n",
+                "foo\\__init__.py,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\r\n",
+                "\"foo\\a,b.py\",sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\r\n",
+                "foo-1.0.dist-info\\WHEEL,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\r\n",
+            )).unwrap(),
+            concat!(
+                "foo-1.0.dist-info/RECORD,,\n",
+                "foo-1.0.dist-info/WHEEL,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\n",
+                "foo/__init__.py,sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\n",
+                "\"foo/a,b.py\",sha256=47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU,0\n",
+            ));
+
+        assert!(normalize_record("a,,\nb,,\n").is_none());
+        assert_eq!(normalize_record("b,,\na,,").unwrap(), "a,,\nb,,\n");
+        assert!(normalize_record("").is_none());
+    }
+}
@@ -1,5 +1,112 @@
 /* SPDX-License-Identifier: GPL-3.0-or-later */
 
+pub mod diff;
 pub mod handlers;
 pub mod options;
+pub mod progress;
 pub mod simplelog;
+pub mod trace;
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Normalize a single file in place, using the first handler that accepts
+/// it, in the same way as the `add-determinism` program does.
+///
+/// Only the handlers listed in `config.handler_names` are used,
+/// [`handlers::handler_names()`] returns the names of all handlers.
+/// Returns true if the file was modified, or with `config.check`,
+/// if it would have been modified. Returns false if no handler matched.
+/// With `config.handler_timeout`, the handler is abandoned if it takes
+/// too long, like in the program.
+///
+/// ```
+/// use std::fs;
+/// use std::rc::Rc;
+/// use add_determinism::{handlers, normalize_path, options};
+///
+/// let dir = tempfile::TempDir::new().unwrap();
+/// let path = dir.path().join("libfoo.a");
+/// fs::copy("tests/cases/testrelro.a", &path).unwrap();
+///
+/// let mut config = options::Config::empty(111, false);
+/// config.handler_names = handlers::handler_names();
+/// let config = Rc::new(config);
+///
+/// assert!(normalize_path(&path, &config).unwrap());
+/// assert!(!normalize_path(&path, &config).unwrap());
+/// assert_eq!(fs::read(&path).unwrap(), fs::read("tests/cases/testrelro.fixed.a").unwrap());
+/// ```
+pub fn normalize_path(path: &Path, config: &Rc<options::Config>) -> Result<bool> {
+    let handlers = handlers::make_handlers(config)?;
+
+    let Some(n_processor) = handlers::select_handler(config, &handlers, 0, path)? else {
+        return Ok(false);
+    };
+
+    let res = handlers::run_handler(config, &*handlers[n_processor], path)?;
+    Ok(matches!(res, handlers::ProcessResult::Replaced | handlers::ProcessResult::Rewritten))
+}
+
+/// Normalize the contents of a file given as bytes. The handler is selected
+/// based on the file name of `hint`, which does not need to exist.
+/// Returns the normalized contents, or None if they are unchanged.
+///
+/// The handlers work on files, so this is a wrapper around
+/// [`normalize_path()`]: the data is written to a private temporary directory,
+/// created with [`tempfile::tempdir()`], and read back after processing.
+/// Since only that copy is modified, the normalized contents are returned
+/// even with `config.check`.
+///
+/// ```
+/// use std::fs;
+/// use std::path::Path;
+/// use std::rc::Rc;
+/// use add_determinism::{handlers, normalize_bytes, options};
+///
+/// let mut config = options::Config::empty(111, false);
+/// config.handler_names = handlers::handler_names();
+/// let config = Rc::new(config);
+///
+/// let data = fs::read("tests/cases/testrelro.a").unwrap();
+/// let fixed = normalize_bytes(&data, Path::new("/usr/lib64/libfoo.a"), &config).unwrap();
+/// assert_eq!(fixed.unwrap(), fs::read("tests/cases/testrelro.fixed.a").unwrap());
+///
+/// let data = fs::read("tests/cases/testrelro.fixed.a").unwrap();
+/// assert!(normalize_bytes(&data, Path::new("libfoo.a"), &config).unwrap().is_none());
+///
+/// // The copy is normalized even in check mode
+/// let check = Rc::new(options::Config { check: true, ..(*config).clone() });
+/// let data = fs::read("tests/cases/testrelro.a").unwrap();
+/// assert!(normalize_bytes(&data, Path::new("libfoo.a"), &check).unwrap().is_some());
+///
+/// // No handler for this file name
+/// assert!(normalize_bytes(b"text", Path::new("README"), &config).unwrap().is_none());
+/// ```
+pub fn normalize_bytes(
+    data: &[u8],
+    hint: &Path,
+    config: &Rc<options::Config>,
+) -> Result<Option<Vec<u8>>> {
+
+    let file_name = hint.file_name()
+        .with_context(|| format!("{:?} has no file name", hint))?;
+
+    let dir = tempfile::tempdir()
+        .context("Cannot create temporary directory")?;
+    let path = dir.path().join(file_name);
+    fs::write(&path, data)?;
+
+    let config = Rc::new(options::Config {
+        check: false,
+        diff: false,
+        backup: None,
+        ..(**config).clone()
+    });
+    normalize_path(&path, &config)?;
+
+    let output = fs::read(&path)?;
+    Ok(if output != data { Some(output) } else { None })
+}
@@ -1,16 +1,27 @@
 /* SPDX-License-Identifier: GPL-3.0-or-later */
 
+mod diff;
 mod handlers;
 mod multiprocess;
 mod options;
+mod progress;
 mod simplelog;
+mod trace;
 
 use anyhow::{anyhow, bail, Result};
 use log::debug;
 use std::env;
 use std::path::Path;
+use std::process::ExitCode;
 use std::rc::Rc;
 
+/// Some files could not be processed, or with --check, some files are
+/// not normalized. Fatal errors during processing also use this code.
+const EXIT_FAILURE: u8 = 1;
+
+/// Invalid options or arguments. clap uses the same code.
+const EXIT_USAGE: u8 = 2;
+
 fn brp_check(config: &options::Config) -> Result<()> {
     // env::current_exe() does readlink("/proc/self/exe"), which returns
     // the target binary, so we cannot use that.
@@ -45,31 +56,36 @@ fn brp_check(config: &options::Config) -> Result<()> {
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let config = match options::Config::make()? {
-        None => { return Ok(()); }
-        Some(some) => some
-    };
-    let config = Rc::new(config);
-
-    brp_check(&config)?;
-
+fn run(config: &Rc<options::Config>) -> Result<()> {
     let stats;
 
     if let Some(socket) = config.job_socket {
         debug!("Running as worker on job socket {}", socket);
-        return multiprocess::do_worker_work(&config);
+        return multiprocess::do_worker_work(config);
+    }
+
+    if config.progress {
+        progress::init(config.inputs.iter().map(|p| handlers::count_files(config, p)).sum());
+    }
 
-    } else if let Some(jobs) = config.jobs {
+    if let Some(jobs) = config.jobs {
         debug!("Running as controller with {} workers", jobs);
-        stats = multiprocess::Controller::do_work(&config)?;
+        stats = multiprocess::Controller::do_work(config)?;
 
     } else {
         // We're not the controller
-        stats = handlers::do_normal_work(&config)?;
+        stats = handlers::do_normal_work(config)?;
     }
 
+    progress::finish();
+
     stats.summarize();
+    if !config.quiet {
+        eprintln!("{}", stats.summary_line(config.check));
+    }
+    if config.report_unhandled {
+        stats.report_unhandled();
+    }
 
     if (config.check || !config.brp) && stats.errors > 0 {
         // Make any errors fatal, except when --brp (without --check) is used.
@@ -80,7 +96,29 @@ fn main() -> Result<()> {
     } else if config.check && (stats.inodes_replaced > 0 ||
                                stats.inodes_rewritten > 0) {
         bail!("--check was specified, but some files would have been modified")
-    }  else {
+    } else if config.fail_on_unhandled && stats.unhandled > 0 {
+        bail!("--fail-on-unhandled was specified, but {} files were not matched by any handler",
+              stats.unhandled)
+    } else {
         Ok(())
     }
 }
+
+fn main() -> ExitCode {
+    let config = match options::Config::make() {
+        Ok(None) => { return ExitCode::SUCCESS; }
+        Ok(Some(some)) => Rc::new(some),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match brp_check(&config).and_then(|_| run(&config)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
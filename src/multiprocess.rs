@@ -25,7 +25,7 @@ pub struct Controller {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Job {
-    selected_handlers: u8,
+    selected_handlers: u32,
     input_path: PathBuf,
 }
 
@@ -44,12 +44,60 @@ impl Controller {
         if config.brp {
             cmd.arg("--brp");
         }
-        if config.verbose {
+        if config.follow_symlinks {
+            cmd.arg("--follow-symlinks");
+        }
+        for _ in 0..config.verbose {
             cmd.arg("-v");
         }
+        if config.quiet {
+            cmd.arg("--quiet");
+        }
+        if let Some(path) = &config.trace_file {
+            cmd.arg("--trace-file").arg(path);
+        }
         if config.check {
             cmd.arg("--check");
         }
+        if config.diff {
+            cmd.arg("--diff");
+        }
+        if let Some(timeout) = config.handler_timeout {
+            cmd.arg("--handler-timeout").arg(timeout.as_secs_f64().to_string());
+        }
+        if let Some(suffix) = &config.backup {
+            cmd.arg(format!("--backup={suffix}"));
+        }
+        if config.force_backup {
+            cmd.arg("--force-backup");
+        }
+        if config.classfile_strip_source_info {
+            cmd.arg("--classfile-strip-source-info");
+        }
+        if config.elf_strip_comment {
+            cmd.arg("--elf-strip-comment");
+        }
+        if config.png_strip_text {
+            cmd.arg("--png-strip-text");
+        }
+        if config.warn_orphan_pyc {
+            cmd.arg("--warn-orphan-pyc");
+        }
+        if config.wheel_scrub_description {
+            cmd.arg("--wheel-scrub-description");
+        }
+        if config.zip_sort_extra_fields {
+            cmd.arg("--zip-sort-extra-fields");
+        }
+        if config.zip_strip_uid_gid {
+            cmd.arg("--zip-strip-uid-gid");
+        }
+        if !config.zip_clamp_time {
+            cmd.arg("--no-zip-clamp-time");
+        }
+        for rule in &config.path_prefix_map {
+            cmd.arg("--replace-path-prefix").arg(rule.to_string());
+        }
         cmd.arg("--handler")
             .arg(handlers
                  .iter()
@@ -109,7 +157,7 @@ impl Controller {
 
     pub fn send_job(
         &self,
-        selected_handlers: u8,
+        selected_handlers: u32,
         input_path: &Path,
     ) -> Result<()> {
 
@@ -194,6 +242,7 @@ impl Controller {
 
         for input_path in &config.inputs {
             match handlers::process_file_or_dir(
+                config,
                 &control.handlers,
                 &mut inodes_seen,
                 input_path,
@@ -215,13 +264,14 @@ impl Controller {
 }
 
 fn process_file_with_selected_handlers(
+    config: &options::Config,
     handlers: &[Box<dyn handlers::Processor>],
-    selected_handlers: u8,
+    selected_handlers: u32,
     input_path: &Path,
 ) -> Result<handlers::ProcessResult> {
 
     // check if selected_handlers doesn't have any unexpected entries
-    if u8::BITS - selected_handlers.leading_zeros() > handlers.len().try_into().unwrap() {
+    if u32::BITS - selected_handlers.leading_zeros() > handlers.len().try_into().unwrap() {
         bail!("Bad handler mask 0x{selected_handlers:x}");
     }
 
@@ -232,7 +282,7 @@ fn process_file_with_selected_handlers(
 
         if cond {
             debug!("{}: running handler {}", input_path.display(), processor.name());
-            let res = processor.process(input_path);
+            let res = handlers::run_handler(config, processor.as_ref(), input_path);
             entry_mod.extend_and_warn(input_path, res);
         }
     }
@@ -270,6 +320,7 @@ pub fn do_worker_work(config: &Rc<options::Config>) -> Result<()> {
         assert!(job.selected_handlers > 0);
 
         let res = process_file_with_selected_handlers(
+            config,
             &handlers,
             job.selected_handlers,
             &job.input_path)?;
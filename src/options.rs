@@ -1,16 +1,24 @@
 /* SPDX-License-Identifier: GPL-3.0-or-later */
 
 use anyhow::{anyhow, Result};
-use chrono::{TimeZone, Utc};
+use chrono::DateTime;
 use clap::Parser;
 use log::{debug, info, log, warn, Level, LevelFilter};
 use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
 use std::os::fd::RawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::time;
 
 use crate::handlers;
 use crate::simplelog;
+use crate::trace;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -19,28 +27,191 @@ struct Options {
     #[arg(value_name = "path")]
     pub inputs: Vec<PathBuf>,
 
+    /// Read paths to operate on from this file ("-" for stdin),
+    /// one path per line
+    #[arg(long, value_name = "PATH")]
+    pub files_from: Option<PathBuf>,
+
+    /// Paths in the --files-from list are separated by NUL instead of newline
+    #[arg(long, requires = "files_from")]
+    pub files_from0: bool,
+
+    /// Skip paths matching this pattern (relative to the argument)
+    #[arg(long,
+          value_name = "GLOB",
+          value_parser = glob::Pattern::new)]
+    pub exclude: Vec<glob::Pattern>,
+
+    /// Only process files matching this pattern (relative to the argument)
+    #[arg(long,
+          value_name = "GLOB",
+          value_parser = glob::Pattern::new)]
+    pub include: Vec<glob::Pattern>,
+
+    /// Follow symlinks, and modify the files they point to
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    pub follow_symlinks: bool,
+
+    /// Skip symlinks (the default)
+    #[arg(long, overrides_with = "follow_symlinks")]
+    pub no_follow_symlinks: bool,
+
     /// Handlers to enable/disable;
     /// use --handler=list to list
     #[arg(long = "handler")]
     pub handlers: Vec<String>,
 
+    /// List known handlers and exit
+    #[arg(long)]
+    pub list_handlers: bool,
+
+    /// Only use the specified handlers (comma-separated)
+    #[arg(long,
+          value_name = "NAMES",
+          value_delimiter = ',',
+          conflicts_with_all = ["handlers", "skip"])]
+    pub only: Vec<String>,
+
+    /// Do not use the specified handlers (comma-separated)
+    #[arg(long,
+          value_name = "NAMES",
+          value_delimiter = ',',
+          conflicts_with = "handlers")]
+    pub skip: Vec<String>,
+
     /// Adjust behaviour as appropriate for a build root program
     #[arg(long)]
     pub brp: bool,
 
-    /// Turn on debugging output
-    #[arg(short, long)]
-    pub verbose: bool,
+    /// Turn on debugging output, twice for even more output
+    #[arg(short, long,
+          action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[arg(short, long,
+          conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Append a line describing each action to this file
+    #[arg(long, value_name = "PATH")]
+    pub trace_file: Option<PathBuf>,
 
     /// Fail if any modifications would have been made
     #[arg(long)]
     pub check: bool,
 
+    /// List the byte ranges which would be modified in each file;
+    /// implies --check
+    #[arg(long)]
+    pub diff: bool,
+
+    /// Fail if any files were not matched by any handler
+    #[arg(long)]
+    pub fail_on_unhandled: bool,
+
+    /// List extensions of files which were not matched by any handler
+    #[arg(long)]
+    pub report_unhandled: bool,
+
+    /// Show the number of processed files on stderr
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Skip files larger than this, instead of passing them to a handler
+    #[arg(long, value_name = "BYTES")]
+    pub max_file_size: Option<u64>,
+
+    /// Give up on a file if the handler takes longer than this
+    #[arg(long,
+          value_name = "SECONDS",
+          value_parser = parse_timeout)]
+    pub handler_timeout: Option<time::Duration>,
+
+    /// Keep the original of each modified file as PATH + SUFFIX
+    #[arg(long,
+          value_name = "SUFFIX",
+          num_args = 0..=1,
+          require_equals = true,
+          default_missing_value = ".orig",
+          value_parser = parse_backup_suffix)]
+    pub backup: Option<String>,
+
+    /// Overwrite existing backup files
+    #[arg(long,
+          requires = "backup")]
+    pub force_backup: bool,
+
+    /// Drop SourceDebugExtension attributes from Java class files
+    /// and remove directories from SourceFile attributes
+    #[arg(long)]
+    pub classfile_strip_source_info: bool,
+
+    /// Zero the contents of the .comment section in ELF files
+    #[arg(long)]
+    pub elf_strip_comment: bool,
+
+    /// Also strip textual metadata and EXIF chunks from PNG files
+    #[arg(long)]
+    pub png_strip_text: bool,
+
+    /// Warn about compiled Python files without source in zip archives
+    #[arg(long)]
+    pub warn_orphan_pyc: bool,
+
+    /// Remove generator versions and dates from HTML and Markdown
+    /// descriptions in wheel metadata
+    #[arg(long)]
+    pub wheel_scrub_description: bool,
+
+    /// Sort extra fields of zip archive entries by header id
+    #[arg(long)]
+    pub zip_sort_extra_fields: bool,
+
+    /// Set the uid and gid in Unix extra fields of zip archive entries to 0
+    #[arg(long)]
+    pub zip_strip_uid_gid: bool,
+
+    /// Clamp modification times of zip archive entries (the default)
+    #[arg(long, overrides_with = "no_zip_clamp_time")]
+    pub zip_clamp_time: bool,
+
+    /// Keep modification times of zip archive entries
+    #[arg(long, overrides_with = "zip_clamp_time")]
+    pub no_zip_clamp_time: bool,
+
+    /// Route files with BYTES at OFFSET to HANDLER;
+    /// syntax: OFFSET:HEXBYTES=>HANDLER
+    #[arg(long,
+          value_name = "RULE",
+          value_parser = parse_magic_rule)]
+    pub magic_rule: Vec<MagicRule>,
+
+    /// Look at the contents of files not accepted by any handler
+    /// to find a handler which recognizes them
+    #[arg(long)]
+    pub content_detect: bool,
+
+    /// Replace the prefix OLD by NEW in paths embedded in files;
+    /// syntax: OLD=NEW
+    #[arg(long,
+          value_name = "OLD=NEW",
+          value_parser = parse_path_prefix)]
+    pub replace_path_prefix: Vec<PathPrefix>,
+
+    /// Use this timestamp instead of $SOURCE_DATE_EPOCH;
+    /// either seconds since the epoch or e.g. 2020-01-01T00:00:00Z
+    #[arg(long,
+          value_name = "TIME",
+          value_parser = parse_timestamp)]
+    pub timestamp: Option<i64>,
+
     /// Read paths to process from this socket.
     /// When used, an explicit list of handlers must be given.
     #[arg(long,
           hide = true,
           conflicts_with = "inputs",
+          conflicts_with = "files_from",
           conflicts_with = "jobs",
           requires = "handlers")]
     pub job_socket: Option<RawFd>,
@@ -60,17 +231,170 @@ struct Options {
     pub jobs: Option<u32>,
 }
 
+impl Options {
+    /// By default, a line is printed for each modified file and a summary
+    /// at the end. --quiet leaves only errors, -v adds debug and -vv trace
+    /// messages.
+    fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+}
+
+/// A user-specified rule to process files with a given
+/// byte signature with a given handler.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MagicRule {
+    pub offset: u64,
+    pub magic: Vec<u8>,
+    pub handler: &'static str,
+}
+
+impl fmt::Display for MagicRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.offset)?;
+        for b in &self.magic {
+            write!(f, "{b:02x}")?;
+        }
+        write!(f, "=>{}", self.handler)
+    }
+}
+
+pub fn parse_backup_suffix(suffix: &str) -> Result<String> {
+    if suffix.is_empty() || suffix.contains('/') {
+        return Err(anyhow!("The backup suffix must be non-empty and cannot contain '/'"));
+    }
+    Ok(suffix.to_string())
+}
+
+pub fn parse_timestamp(arg: &str) -> Result<i64> {
+    let ts = match arg.parse::<i64>() {
+        Ok(ts) => ts,
+        Err(_) => DateTime::parse_from_rfc3339(arg)
+            .map_err(|e| anyhow!("Expected seconds since the epoch or an RFC 3339 date: {e}"))?
+            .timestamp(),
+    };
+
+    if ts < 0 {
+        return Err(anyhow!("The timestamp cannot be before 1970-01-01"));
+    }
+    if DateTime::from_timestamp(ts, 0).is_none() {
+        return Err(anyhow!("The timestamp is out of range"));
+    }
+    Ok(ts)
+}
+
+pub fn parse_timeout(arg: &str) -> Result<time::Duration> {
+    let secs = arg.parse::<f64>()
+        .map_err(|e| anyhow!("Expected a number of seconds: {e}"))?;
+
+    match time::Duration::try_from_secs_f64(secs) {
+        Ok(timeout) if !timeout.is_zero() => Ok(timeout),
+        _ => Err(anyhow!("The timeout must be a positive number of seconds")),
+    }
+}
+
+pub fn parse_magic_rule(rule: &str) -> Result<MagicRule> {
+    let (magic, handler) = rule.split_once("=>")
+        .ok_or_else(|| anyhow!("Expected OFFSET:HEXBYTES=>HANDLER"))?;
+    let (offset, magic) = magic.split_once(':')
+        .ok_or_else(|| anyhow!("Expected OFFSET:HEXBYTES=>HANDLER"))?;
+
+    let offset = match offset.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => offset.parse(),
+    }.map_err(|e| anyhow!("Invalid offset {offset:?}: {e}"))?;
+
+    if magic.is_empty() || magic.len() % 2 != 0 {
+        return Err(anyhow!("Invalid byte string {magic:?}: expected an even number of hex digits"));
+    }
+    let magic = (0..magic.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&magic[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|e| anyhow!("Invalid byte string {magic:?}: {e}"))?;
+
+    let handler = handlers::handler_names()
+        .into_iter()
+        .find(|name| *name == handler)
+        .ok_or_else(|| anyhow!("Unknown handler name: {handler:?}"))?;
+
+    Ok(MagicRule { offset, magic, handler })
+}
+
+/// A user-specified replacement of the beginning of paths
+/// embedded in processed files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathPrefix {
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for PathPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.old, self.new)
+    }
+}
+
+pub fn parse_path_prefix(arg: &str) -> Result<PathPrefix> {
+    // The old prefix cannot contain "=", but the new one can
+    let (old, new) = arg.split_once('=')
+        .ok_or_else(|| anyhow!("Expected OLD=NEW"))?;
+
+    if old.is_empty() {
+        return Err(anyhow!("The prefix to replace cannot be empty"));
+    }
+    // Paths in ELF files are replaced in place
+    if new.len() > old.len() {
+        return Err(anyhow!("The new prefix cannot be longer than the prefix to replace"));
+    }
+
+    Ok(PathPrefix { old: old.to_string(), new: new.to_string() })
+}
+
+#[derive(Clone)]
 pub struct Config {
     pub inputs: Vec<PathBuf>,
+    pub exclude: Vec<glob::Pattern>,
+    pub include: Vec<glob::Pattern>,
+    pub follow_symlinks: bool,
     pub brp: bool,
-    pub verbose: bool,
+    pub verbose: u8,
+    pub quiet: bool,
+    pub trace_file: Option<PathBuf>,
     pub job_socket: Option<RawFd>,
     pub result_socket: Option<RawFd>,
     pub check: bool,
+    pub diff: bool,
+    pub fail_on_unhandled: bool,
+    pub report_unhandled: bool,
+    pub progress: bool,
+    pub max_file_size: Option<u64>,
+    pub handler_timeout: Option<time::Duration>,
+    /// Set by handlers::process_with_timeout() when it gives up on the
+    /// handler thread, so that the file is not modified afterwards
+    pub abandoned: Option<Arc<AtomicBool>>,
+    pub backup: Option<String>,
+    pub force_backup: bool,
     pub jobs: Option<u32>,
     pub source_date_epoch: Option<i64>,
     pub handler_names: Vec<&'static str>,
     pub strict_handlers: bool,
+    pub classfile_strip_source_info: bool,
+    pub elf_strip_comment: bool,
+    pub png_strip_text: bool,
+    pub warn_orphan_pyc: bool,
+    pub wheel_scrub_description: bool,
+    pub zip_sort_extra_fields: bool,
+    pub zip_strip_uid_gid: bool,
+    pub zip_clamp_time: bool,
+    pub magic_rules: Vec<MagicRule>,
+    pub content_detect: bool,
+    pub path_prefix_map: Vec<PathPrefix>,
 }
 
 fn filter_by_name(name: &str, filter: &[&str]) -> bool {
@@ -93,6 +417,20 @@ fn filter_by_name(name: &str, filter: &[&str]) -> bool {
     negative_filter
 }
 
+/// Read a list of paths separated by newlines or NULs. Empty entries are ignored.
+fn read_file_list(mut input: impl Read, nul: bool) -> Result<Vec<PathBuf>> {
+    let mut buf = vec![];
+    input.read_to_end(&mut buf)?;
+
+    let sep = if nul { b'\0' } else { b'\n' };
+
+    Ok(buf
+       .split(|c| *c == sep)
+       .filter(|path| !path.is_empty())
+       .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+       .collect())
+}
+
 pub fn requested_handlers(filter: &[&str]) -> Result<(Vec<&'static str>, bool)> {
     if filter.iter().any(|x|  x.starts_with('-')) &&
        filter.iter().any(|x| !x.starts_with('-')) {
@@ -107,10 +445,10 @@ pub fn requested_handlers(filter: &[&str]) -> Result<(Vec<&'static str>, bool)>
         return Err(anyhow!("Unknown handler name: {:?}", name));
     }
 
-    let list: Vec<&'static str> = handlers::HANDLERS
+    let list: Vec<&'static str> = handlers::registry()
         .iter()
-        .filter(|(name, _)| filter_by_name(name, filter))
-        .map(|(name, _)| *name)
+        .filter(|h| filter_by_name(h.name, filter))
+        .map(|h| h.name)
         .collect();
 
     if list.is_empty() {
@@ -126,36 +464,82 @@ impl Config {
     pub fn make() -> Result<Option<Self>> {
         let options = Options::parse();
 
-        // log level
+        simplelog::init_with_level(options.log_level())?;
+
+        let config = Self::from_options(options)?;
 
-        let log_level = if options.verbose { LevelFilter::Debug } else { LevelFilter::Info };
-        simplelog::init_with_level(log_level)?;
+        if let Some(path) = config.as_ref().and_then(|c| c.trace_file.as_ref()) {
+            trace::init(path)?;
+        }
+
+        Ok(config)
+    }
 
+    fn from_options(options: Options) -> Result<Option<Self>> {
         // handlers
 
-        let handlers: Vec<&str> = options.handlers.iter().flat_map(|s| s.split(',')).collect();
+        // --only=X and --skip=X are the same as --handler=X and --handler=-X
+        let handlers: Vec<String> = options.handlers.iter()
+            .flat_map(|s| s.split(','))
+            .map(str::to_string)
+            .chain(options.only.iter().cloned())
+            .chain(options.skip.iter().map(|s| format!("-{s}")))
+            .collect();
+        let handlers: Vec<&str> = handlers.iter().map(String::as_str).collect();
 
         if handlers.contains(&"list") {
             println!("{}", handlers::handler_names().join("\n"));
             return Ok(None);
         }
 
+        if options.list_handlers {
+            for h in handlers::registry() {
+                println!("{:<10}{}", h.name, h.description);
+            }
+            return Ok(None);
+        }
+
         let (handler_names, strict_handlers) = requested_handlers(&handlers)?;
 
+        if let Some(rule) = options.magic_rule.iter().find(|r| !handler_names.contains(&r.handler)) {
+            return Err(anyhow!("Magic rule {} refers to a handler which is not enabled", rule));
+        }
+
         // positional args
 
-        if options.job_socket.is_none() && options.inputs.is_empty() && !options.brp {
+        let mut inputs = options.inputs;
+
+        if let Some(files_from) = &options.files_from {
+            let list = if files_from == Path::new("-") {
+                read_file_list(io::stdin().lock(), options.files_from0)
+            } else {
+                File::open(files_from)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|f| read_file_list(f, options.files_from0))
+            }.map_err(|e| anyhow!("Cannot read paths from {}: {}", files_from.display(), e))?;
+
+            debug!("Read {} paths from {}", list.len(), files_from.display());
+            inputs.extend(list);
+        }
+
+        if options.job_socket.is_none() && inputs.is_empty() && !options.brp {
             info!("No arguments specified, nothing to do. 😎");
         }
 
-        // $SOURCE_DATE_EPOCH
+        // $SOURCE_DATE_EPOCH, --timestamp takes precedence
 
-        let mut source_date_epoch = match env::var("SOURCE_DATE_EPOCH") {
-            Ok(val) => Some(val.parse::<i64>()?),
-            Err(_) => None,
+        let (mut source_date_epoch, source) = match (options.timestamp, env::var("SOURCE_DATE_EPOCH")) {
+            (Some(val), _) => (Some(val), "--timestamp"),
+            (None, Ok(val)) => (Some(val.parse::<i64>()
+                                     .map_err(|e| anyhow!("Invalid SOURCE_DATE_EPOCH={val:?}: {e}"))?),
+                                "$SOURCE_DATE_EPOCH"),
+            (None, Err(_)) => (None, "$SOURCE_DATE_EPOCH"),
         };
 
         if let Some(v) = source_date_epoch {
+            let date = DateTime::from_timestamp(v, 0)
+                .ok_or_else(|| anyhow!("Timestamp from {source} is out of range: {v}"))?;
+
             let now = time::SystemTime::now();
             let now_sec = now.duration_since(time::UNIX_EPOCH).unwrap().as_secs();
 
@@ -163,29 +547,54 @@ impl Config {
             let pos = v > 0 && v as u64 > now_sec;
 
             log!(if neg || pos { Level::Warn } else { Level::Debug },
-                 "SOURCE_DATE_EPOCH timestamp: {v} ({})",
-                 Utc.timestamp_opt(v, 0).unwrap());
+                 "Timestamp from {source}: {v} ({date})");
             if neg {
-                warn!("SOURCE_DATE_EPOCH timestamp is negative, ignoring: {v}");
+                warn!("Timestamp from {source} is negative, ignoring: {v}");
                 source_date_epoch = None;
             } else if pos {
-                warn!("SOURCE_DATE_EPOCH timestamp is in the future: {v} > {now_sec}");
+                warn!("Timestamp from {source} is in the future: {v} > {now_sec}");
             }
         } else {
             debug!("SOURCE_DATE_EPOCH timestamp: {}", "(unset)");
         }
 
         Ok(Some(Self {
-            inputs: options.inputs,
+            inputs,
+            exclude: options.exclude,
+            include: options.include,
+            follow_symlinks: options.follow_symlinks,
             brp: options.brp,
             verbose: options.verbose,
+            quiet: options.quiet,
+            trace_file: options.trace_file,
             job_socket: options.job_socket,
             result_socket: options.result_socket,
-            check: options.check,
+            check: options.check || options.diff,
+            diff: options.diff,
+            fail_on_unhandled: options.fail_on_unhandled,
+            report_unhandled: options.report_unhandled,
+            progress: options.progress,
+            max_file_size: options.max_file_size,
+            handler_timeout: options.handler_timeout,
+            abandoned: None,
+            backup: options.backup,
+            force_backup: options.force_backup,
             jobs: options.jobs,
             source_date_epoch,
             handler_names,
             strict_handlers,
+            classfile_strip_source_info: options.classfile_strip_source_info,
+            elf_strip_comment: options.elf_strip_comment,
+            png_strip_text: options.png_strip_text,
+            warn_orphan_pyc: options.warn_orphan_pyc,
+            wheel_scrub_description: options.wheel_scrub_description,
+            zip_sort_extra_fields: options.zip_sort_extra_fields,
+            zip_strip_uid_gid: options.zip_strip_uid_gid,
+            // If only some of the zip normalizations are requested, the others are not done
+            zip_clamp_time: !options.no_zip_clamp_time,
+            magic_rules: options.magic_rule,
+            content_detect: options.content_detect,
+            path_prefix_map: options.replace_path_prefix,
         }))
     }
 
@@ -194,29 +603,273 @@ impl Config {
     pub const fn empty(source_date_epoch: i64, check: bool) -> Self {
         Self {
             inputs: vec![],
+            exclude: vec![],
+            include: vec![],
+            follow_symlinks: false,
             brp: false,
-            verbose: false,
+            verbose: 0,
+            quiet: false,
+            trace_file: None,
             job_socket: None,
             result_socket: None,
             check,
+            diff: false,
+            fail_on_unhandled: false,
+            report_unhandled: false,
+            progress: false,
+            max_file_size: None,
+            handler_timeout: None,
+            abandoned: None,
+            backup: None,
+            force_backup: false,
             jobs: None,
             source_date_epoch: Some(source_date_epoch),
             handler_names: vec![],
             strict_handlers: false,
+            classfile_strip_source_info: false,
+            elf_strip_comment: false,
+            png_strip_text: false,
+            warn_orphan_pyc: false,
+            wheel_scrub_description: false,
+            zip_sort_extra_fields: false,
+            zip_strip_uid_gid: false,
+            zip_clamp_time: true,
+            magic_rules: vec![],
+            content_detect: false,
+            path_prefix_map: vec![],
+        }
+    }
+
+    /// Return the timestamp to use instead of original: timestamps newer
+    /// than $SOURCE_DATE_EPOCH are clamped to it, older timestamps are kept.
+    pub fn clamp_mtime(&self, original: i64) -> i64 {
+        match self.source_date_epoch {
+            Some(epoch) => original.min(epoch),
+            None => original,
         }
     }
+
+    /// Return the first --replace-path-prefix rule which applies to path.
+    /// All handlers which rewrite embedded paths use the same rules.
+    /// The prefix must end at a path component boundary.
+    pub fn match_path_prefix(&self, path: &[u8]) -> Option<&PathPrefix> {
+        self.path_prefix_map
+            .iter()
+            .find(|rule| {
+                let old = rule.old.as_bytes();
+                path.starts_with(old)
+                    && (old.ends_with(b"/") || matches!(path.get(old.len()), None | Some(b'/')))
+            })
+    }
+
+    /// Return path with the prefix replaced according to the first matching
+    /// --replace-path-prefix rule, or None if no rule applies.
+    pub fn replace_path_prefix(&self, path: &[u8]) -> Option<Vec<u8>> {
+        let rule = self.match_path_prefix(path)?;
+        let mut new = rule.new.as_bytes().to_vec();
+        new.extend_from_slice(&path[rule.old.len()..]);
+        Some(new)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::rc::Rc;
+
+    fn config_from_args(args: &[&str]) -> Result<Config> {
+        let args = ["add-determinism"].iter().chain(args);
+        let options = Options::try_parse_from(args)?;
+        Ok(Config::from_options(options)?.unwrap())
+    }
 
     #[test]
     fn test_filter_by_name() {
-        assert_eq!(filter_by_name("x", &vec!["x", "y"]), true);
-        assert_eq!(filter_by_name("x", &vec!["x"]), true);
-        assert_eq!(filter_by_name("x", &vec![]), true);
-        assert_eq!(filter_by_name("x", &vec!["-x"]), false);
-        assert_eq!(filter_by_name("x", &vec!["-y"]), true);
+        assert!(filter_by_name("x", &["x", "y"]));
+        assert!(filter_by_name("x", &["x"]));
+        assert!(filter_by_name("x", &[]));
+        assert!(!filter_by_name("x", &["-x"]));
+        assert!(filter_by_name("x", &["-y"]));
+    }
+
+    #[test]
+    fn test_log_level() {
+        let log_level = |args: &[&str]| {
+            let args = ["add-determinism"].iter().chain(args);
+            Options::try_parse_from(args).map(|o| o.log_level())
+        };
+
+        assert_eq!(log_level(&[]).unwrap(), LevelFilter::Info);
+        assert_eq!(log_level(&["-v"]).unwrap(), LevelFilter::Debug);
+        assert_eq!(log_level(&["-vv"]).unwrap(), LevelFilter::Trace);
+        assert_eq!(log_level(&["-v", "--verbose", "-v"]).unwrap(), LevelFilter::Trace);
+        assert_eq!(log_level(&["-q"]).unwrap(), LevelFilter::Error);
+        assert_eq!(log_level(&["--quiet"]).unwrap(), LevelFilter::Error);
+        assert!(log_level(&["--quiet", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_follow_symlinks() {
+        assert!(!config_from_args(&[]).unwrap().follow_symlinks);
+        assert!( config_from_args(&["--follow-symlinks"]).unwrap().follow_symlinks);
+        assert!(!config_from_args(&["--follow-symlinks", "--no-follow-symlinks"]).unwrap().follow_symlinks);
+        assert!( config_from_args(&["--no-follow-symlinks", "--follow-symlinks"]).unwrap().follow_symlinks);
+    }
+
+    #[test]
+    fn test_zip_options() {
+        let zip = |args| {
+            let config = config_from_args(args).unwrap();
+            (config.zip_strip_uid_gid, config.zip_clamp_time)
+        };
+
+        assert_eq!(zip(&[]), (false, true));
+        assert_eq!(zip(&["--zip-clamp-time"]), (false, true));
+        assert_eq!(zip(&["--no-zip-clamp-time"]), (false, false));
+        assert_eq!(zip(&["--no-zip-clamp-time", "--zip-clamp-time"]), (false, true));
+        assert_eq!(zip(&["--zip-clamp-time", "--no-zip-clamp-time"]), (false, false));
+
+        // The options are independent
+        assert_eq!(zip(&["--zip-strip-uid-gid"]), (true, true));
+        assert_eq!(zip(&["--zip-strip-uid-gid", "--no-zip-clamp-time"]), (true, false));
+    }
+
+    #[test]
+    fn test_parse_magic_rule() {
+        assert_eq!(parse_magic_rule("0:213c617263683e=>ar").unwrap(),
+                   MagicRule { offset: 0, magic: b"!<arch>".to_vec(), handler: "ar" });
+        assert_eq!(parse_magic_rule("0x10:CAFE=>jar").unwrap(),
+                   MagicRule { offset: 16, magic: vec![0xca, 0xfe], handler: "jar" });
+
+        let rule = parse_magic_rule("8:00ff=>pyc").unwrap();
+        assert_eq!(rule.to_string(), "8:00ff=>pyc");
+        assert_eq!(parse_magic_rule(&rule.to_string()).unwrap(), rule);
+
+        assert!(parse_magic_rule("0:cafe").is_err());
+        assert!(parse_magic_rule("cafe=>ar").is_err());
+        assert!(parse_magic_rule("x:cafe=>ar").is_err());
+        assert!(parse_magic_rule("-1:cafe=>ar").is_err());
+        assert!(parse_magic_rule("0:caf=>ar").is_err());
+        assert!(parse_magic_rule("0:=>ar").is_err());
+        assert!(parse_magic_rule("0:xx=>ar").is_err());
+        assert!(parse_magic_rule("0:cafe=>gzip").is_err());
+
+        let config = config_from_args(&["--magic-rule=0:cafe=>ar", "--magic-rule", "4:00=>pyc"]).unwrap();
+        assert_eq!(config.magic_rules.len(), 2);
+        assert!(config_from_args(&["--magic-rule=0:cafe=>ar", "--skip=ar"]).is_err());
+    }
+
+    #[test]
+    fn test_read_file_list() {
+        assert_eq!(read_file_list(&b"a\nb c\n\n/d/e\n"[..], false).unwrap(),
+                   vec![PathBuf::from("a"), PathBuf::from("b c"), PathBuf::from("/d/e")]);
+        assert_eq!(read_file_list(&b"a\nb\0c d\0"[..], true).unwrap(),
+                   vec![PathBuf::from("a\nb"), PathBuf::from("c d")]);
+        assert_eq!(read_file_list(&b"a"[..], false).unwrap(), vec![PathBuf::from("a")]);
+        assert!(read_file_list(&b""[..], false).unwrap().is_empty());
+        assert!(read_file_list(&b"\0\0"[..], true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replace_path_prefix() {
+        assert_eq!(parse_path_prefix("/build=/usr").unwrap(),
+                   PathPrefix { old: "/build".to_string(), new: "/usr".to_string() });
+        assert_eq!(parse_path_prefix("/build=").unwrap().new, "");
+        assert_eq!(parse_path_prefix("/build=a=b").unwrap().new, "a=b");
+        assert_eq!(parse_path_prefix("/build=/src/x").unwrap().new, "/src/x");
+        assert!(parse_path_prefix("/build").is_err());
+        assert!(parse_path_prefix("=/usr/src").is_err());
+        assert!(parse_path_prefix("/build=/usr/src").is_err());
+        assert!(config_from_args(&["--replace-path-prefix=/build=/usr/src"]).is_err());
+
+        let config = config_from_args(&["--replace-path-prefix=/build/foo=/src",
+                                        "--replace-path-prefix", "/build=/usr"]).unwrap();
+        assert_eq!(config.replace_path_prefix(b"/build/foo/a.c").unwrap(), b"/src/a.c");
+        assert_eq!(config.replace_path_prefix(b"/build/bar").unwrap(), b"/usr/bar");
+        assert_eq!(config.replace_path_prefix(b"/build").unwrap(), b"/usr");
+        assert!(config.replace_path_prefix(b"/other/build").is_none());
+        assert_eq!(config.match_path_prefix(b"/build/foo").unwrap().to_string(), "/build/foo=/src");
+
+        // Only whole path components match
+        assert!(config.replace_path_prefix(b"/buildroot/a.c").is_none());
+        assert_eq!(config.replace_path_prefix(b"/build/foobar").unwrap(), b"/usr/foobar");
+
+        let config = config_from_args(&["--replace-path-prefix=/build/=/src/"]).unwrap();
+        assert_eq!(config.replace_path_prefix(b"/build/a.c").unwrap(), b"/src/a.c");
+        assert!(config.replace_path_prefix(b"/build").is_none());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("0").unwrap(), 0);
+        assert_eq!(parse_timestamp("1704106800").unwrap(), 1704106800);
+        assert_eq!(parse_timestamp("2020-01-01T00:00:00Z").unwrap(), 1577836800);
+        assert_eq!(parse_timestamp("2020-01-01T02:00:00+02:00").unwrap(), 1577836800);
+
+        assert!(parse_timestamp("-1").is_err());
+        assert!(parse_timestamp("99999999999999999").is_err());
+        assert!(parse_timestamp(&i64::MAX.to_string()).is_err());
+        assert!(parse_timestamp("1969-12-31T23:59:59Z").is_err());
+        assert!(parse_timestamp("2020-01-01").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("30").unwrap(), time::Duration::from_secs(30));
+        assert_eq!(parse_timeout("0.5").unwrap(), time::Duration::from_millis(500));
+
+        assert!(parse_timeout("0").is_err());
+        assert!(parse_timeout("-1").is_err());
+        assert!(parse_timeout("inf").is_err());
+        assert!(parse_timeout("1m").is_err());
+        assert!(parse_timeout("").is_err());
+    }
+
+    #[test]
+    fn test_clamp_mtime() {
+        let config = Config::empty(1704106800, false);
+        assert_eq!(config.clamp_mtime(1704106799), 1704106799);  // older
+        assert_eq!(config.clamp_mtime(0), 0);
+        assert_eq!(config.clamp_mtime(-1), -1);
+        assert_eq!(config.clamp_mtime(1704106800), 1704106800);  // same
+        assert_eq!(config.clamp_mtime(1704106801), 1704106800);  // newer
+        assert_eq!(config.clamp_mtime(i64::MAX), 1704106800);
+
+        let config = Config { source_date_epoch: None, ..Config::empty(0, false) };
+        assert_eq!(config.clamp_mtime(0), 0);
+        assert_eq!(config.clamp_mtime(1704106801), 1704106801);
+    }
+
+    #[test]
+    fn test_only_skip() {
+        let config = config_from_args(&["--only=pyc,zip"]).unwrap();
+        assert_eq!(config.handler_names, vec!["pyc", "zip"]);
+        assert!(config.strict_handlers);
+
+        let config = config_from_args(&["--only", "zip", "--only", "ar"]).unwrap();
+        assert_eq!(config.handler_names, vec!["ar", "zip"]);
+
+        let config = config_from_args(&["--skip=pyc,zip"]).unwrap();
+        assert_eq!(config.handler_names, vec!["ar", "classfile", "cpio", "elf", "font", "jar", "javadoc", "mo", "pe", "png", "rlib", "squashfs", "sqlite"]);
+
+        let config = config_from_args(&[]).unwrap();
+        assert_eq!(config.handler_names, handlers::handler_names());
+        assert!(!config.strict_handlers);
+
+        assert!(config_from_args(&["--only=nosuchhandler"]).is_err());
+        assert!(config_from_args(&["--skip=nosuchhandler"]).is_err());
+        assert!(config_from_args(&["--only=pyc", "--skip=zip"]).is_err());
+        assert!(config_from_args(&["--only=pyc", "--handler=zip"]).is_err());
+    }
+
+    #[test]
+    fn test_only_restricts_handlers() {
+        let config = Rc::new(config_from_args(&["--only=pyc,zip"]).unwrap());
+        let handlers = handlers::make_handlers(&config).unwrap();
+
+        let names: Vec<&str> = handlers.iter().map(|h| h.name()).collect();
+        assert_eq!(names, vec!["pyc", "zip"]);
     }
 }
@@ -0,0 +1,111 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+// With --progress, the number of files processed so far, the total, and the
+// current path are shown on stderr. If stderr is a terminal, a single status
+// line is redrawn at most every REDRAW_INTERVAL. Otherwise, a plain line is
+// written every PLAIN_LINE_EVERY files, so that logs are not flooded.
+//
+// With -j, the files are counted when they are handed over to the workers.
+
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const PLAIN_LINE_EVERY: u64 = 1000;
+
+static PROGRESS: Mutex<Option<Progress<io::Stderr>>> = Mutex::new(None);
+
+pub struct Progress<W: Write> {
+    output: W,
+    tty: bool,
+    every: u64,
+    total: u64,
+    done: u64,
+    last_redraw: Option<Instant>,
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(output: W, tty: bool, every: u64, total: u64) -> Self {
+        Progress { output, tty, every, total, done: 0, last_redraw: None }
+    }
+
+    pub fn file(&mut self, path: &Path) {
+        self.done += 1;
+
+        // Progress is a convenience, don't fail the processing if it doesn't work.
+        if self.tty {
+            let now = Instant::now();
+            if self.last_redraw.is_some_and(|t| now.duration_since(t) < REDRAW_INTERVAL) {
+                return;
+            }
+            self.last_redraw = Some(now);
+
+            let _ = write!(self.output, "\r{}/{} {}\x1b[K", self.done, self.total, path.display());
+            let _ = self.output.flush();
+        } else if self.done.is_multiple_of(self.every) {
+            let _ = writeln!(self.output, "{}/{} {}", self.done, self.total, path.display());
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.tty {
+            let _ = writeln!(self.output, "\r{}/{} files\x1b[K", self.done, self.total);
+        }
+    }
+}
+
+pub fn init(total: u64) {
+    let stderr = io::stderr();
+    let tty = stderr.is_terminal();
+    *PROGRESS.lock().unwrap() = Some(Progress::new(stderr, tty, PLAIN_LINE_EVERY, total));
+}
+
+pub fn file(path: &Path) {
+    if let Some(progress) = PROGRESS.lock().unwrap().as_mut() {
+        progress.file(path);
+    }
+}
+
+pub fn finish() {
+    if let Some(mut progress) = PROGRESS.lock().unwrap().take() {
+        progress.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_lines() {
+        let mut buf = vec![];
+        let mut progress = Progress::new(&mut buf, false, 10, 35);
+
+        for i in 0..35 {
+            progress.file(Path::new(&format!("/some/path/{i}")));
+        }
+        progress.finish();
+
+        let text = String::from_utf8(buf).unwrap();
+        assert_eq!(text, "10/35 /some/path/9\n20/35 /some/path/19\n30/35 /some/path/29\n");
+    }
+
+    #[test]
+    fn test_tty_redraw() {
+        let mut buf = vec![];
+        let mut progress = Progress::new(&mut buf, true, 10, 1000);
+
+        // Those are all within REDRAW_INTERVAL, only the first one is shown
+        for i in 0..1000 {
+            progress.file(Path::new(&format!("/some/path/{i}")));
+        }
+        progress.finish();
+
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.starts_with("\r1/1000 /some/path/0\x1b[K"));
+        assert!(text.ends_with("\r1000/1000 files\x1b[K\n"));
+        assert!(text.matches('\r').count() < 100);
+    }
+}
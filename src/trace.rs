@@ -0,0 +1,42 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+// With --trace-file, a line is appended to the trace file for each action:
+//
+//   <pid>␉<action>␉<path>␉<details>
+//
+// This is independent of the log level. The file is opened in append mode
+// and each line is written with a single write, so the worker processes can
+// share the same file.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+
+static TRACE_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+pub fn init(path: &Path) -> Result<()> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Cannot open trace file {:?}", path))?;
+
+    *TRACE_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub fn event(path: &Path, action: &str, details: fmt::Arguments) {
+    let mut guard = TRACE_FILE.lock().unwrap();
+    let Some(file) = guard.as_mut() else {
+        return;
+    };
+
+    let line = format!("{}\t{}\t{}\t{}\n", process::id(), action, path.display(), details);
+
+    // Tracing is a debugging aid, don't fail the processing if it doesn't work.
+    let _ = file.write_all(line.as_bytes());
+}
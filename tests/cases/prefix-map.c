@@ -0,0 +1,3 @@
+int hello(int x) {
+    return x + 1;
+}
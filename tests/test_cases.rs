@@ -1,3 +1,4 @@
+mod test_cli;
 mod test_handlers;
 
 use add_determinism::simplelog;
@@ -0,0 +1,530 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+use add_determinism::handlers;
+
+fn command() -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_add-determinism"));
+    cmd.env_remove("SOURCE_DATE_EPOCH");
+    cmd
+}
+
+fn run(args: &[&str]) -> Output {
+    command()
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_list_handlers() {
+    let output = run(&["--list-handlers"]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), handlers::handler_names().len());
+
+    for (line, name) in lines.iter().zip(handlers::handler_names()) {
+        assert!(line.starts_with(name));
+    }
+    assert!(stdout.contains("Python compiled bytecode (.pyc)"));
+}
+
+#[test]
+fn test_check() {
+    let dir = TempDir::new().unwrap();
+
+    let unnormalized = dir.path().join("adapters.cpython-312.pyc");
+    fs::copy("tests/cases/adapters.cpython-312.pyc", &unnormalized).unwrap();
+    let data = fs::read(&unnormalized).unwrap();
+
+    let output = run(&["--check", unnormalized.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("{}: not normalized", unnormalized.display())));
+
+    // The file is not modified
+    assert_eq!(fs::read(&unnormalized).unwrap(), data);
+
+    let normalized = dir.path().join("adapters.cpython-312~fixed.pyc");
+    fs::copy("tests/cases/adapters.cpython-312~fixed.pyc", &normalized).unwrap();
+
+    let output = run(&["--check", normalized.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_diff() {
+    let dir = TempDir::new().unwrap();
+
+    let path = dir.path().join("sqlite-counter.sqlite");
+    fs::copy("tests/cases/sqlite-counter.sqlite", &path).unwrap();
+    let data = fs::read(&path).unwrap();
+
+    // The change counter and the version-valid-for number are zeroed
+    let output = run(&["--diff", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().filter(|l| l.contains(": 0x")).collect();
+    assert_eq!(lines, [
+        format!("{}: 0x1b..0x1c: 04 → 00", path.display()),
+        format!("{}: 0x5f..0x60: 04 → 00", path.display()),
+    ]);
+
+    // --diff implies --check
+    assert_eq!(fs::read(&path).unwrap(), data);
+
+    let output = run(&["--diff", "tests/cases/sqlite-counter.sqlite.fixed"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!String::from_utf8(output.stdout).unwrap().contains(": 0x"));
+}
+
+/// Copy files from tests/cases to dir, returning paths of the copies
+/// and whether the copy is expected to be modified.
+fn prepare_files(dir: &TempDir) -> Vec<(PathBuf, bool)> {
+    [
+        ("testrelro.a", "testrelro.a", true),
+        ("image-metadata.png", "image with spaces.png", true),
+        ("adapters.cpython-312~fixed.pyc", "adapters.cpython-312.pyc", false),
+    ].iter()
+        .map(|(src, dst, modified)| {
+            let path = dir.path().join(dst);
+            fs::copy(format!("tests/cases/{src}"), &path).unwrap();
+            (path, *modified)
+        })
+        .collect()
+}
+
+fn check_files(files: &[(PathBuf, bool)]) {
+    let cases = ["testrelro.a", "image-metadata.png", "adapters.cpython-312~fixed.pyc"];
+
+    for ((path, modified), src) in files.iter().zip(cases) {
+        let orig = fs::read(format!("tests/cases/{src}")).unwrap();
+        assert_eq!(fs::read(path).unwrap() != orig, *modified, "{}", path.display());
+    }
+}
+
+#[test]
+fn test_files_from() {
+    let dir = TempDir::new().unwrap();
+    let files = prepare_files(&dir);
+
+    let list = dir.path().join("list");
+    let content: Vec<String> = files.iter().map(|(p, _)| format!("{}\n", p.display())).collect();
+    fs::write(&list, content.concat()).unwrap();
+
+    let output = command()
+        .env("SOURCE_DATE_EPOCH", "111")
+        .arg("--files-from").arg(&list)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    check_files(&files);
+}
+
+#[test]
+fn test_files_from0_stdin() {
+    let dir = TempDir::new().unwrap();
+    let files = prepare_files(&dir);
+
+    let content: Vec<String> = files.iter().map(|(p, _)| format!("{}\0", p.display())).collect();
+
+    let mut child = command()
+        .env("SOURCE_DATE_EPOCH", "111")
+        .args(["--files-from", "-", "--files-from0"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(content.concat().as_bytes()).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    check_files(&files);
+}
+
+#[test]
+fn test_files_from_missing() {
+    let output = run(&["--files-from", "/no/such/file"]);
+    assert!(!output.status.success());
+
+    let output = run(&["--files-from0"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_trace_file() {
+    let dir = TempDir::new().unwrap();
+    let files = prepare_files(&dir);
+    let trace = dir.path().join("trace");
+
+    let output = command()
+        .env("SOURCE_DATE_EPOCH", "111")
+        .arg("--trace-file").arg(&trace)
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let trace = fs::read_to_string(&trace).unwrap();
+    let lines: Vec<Vec<&str>> = trace.lines()
+        .map(|line| line.splitn(4, '\t').skip(1).collect())
+        .collect();
+
+    let ar = files[0].0.to_str().unwrap();
+    let ar_lines: Vec<&Vec<&str>> = lines.iter().filter(|l| l[1] == ar).collect();
+    assert_eq!(ar_lines, vec![
+        &vec!["matched", ar, "handler ar"],
+        &vec!["opened", ar, "size 1726"],
+        &vec!["field", ar, "member \"/\": mtime 1710585490 → 111"],
+        &vec!["field", ar, "member \"testrelro.o/\": mtime 1710585336 → 111"],
+        &vec!["field", ar, "member \"testrelro.o/\": owner 1000:1000 → 0:0"],
+        &vec!["field", ar, "member \"testrelro.o/\": mode 100644 → 644"],
+        &vec!["wrote", ar, "replaced"],
+    ]);
+
+    let pyc = files[2].0.to_str().unwrap();
+    assert!(lines.contains(&vec!["unchanged", pyc, ""]));
+
+    // The trace file itself is also seen
+    assert!(lines.iter().any(|l| l[0] == "skipped" && l[2] == "no handler matched"));
+}
+
+#[test]
+fn test_fail_on_unhandled() {
+    let dir = TempDir::new().unwrap();
+    prepare_files(&dir);
+
+    let output = run(&["--fail-on-unhandled", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    fs::write(dir.path().join("firmware.bin"), b"\x7fELF?").unwrap();
+
+    let output = run(&["--fail-on-unhandled", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("1 files were not matched by any handler"));
+
+    // Without the option, unmatched files are not an error
+    let output = run(&[dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn test_report_unhandled() {
+    let dir = TempDir::new().unwrap();
+    prepare_files(&dir);
+
+    let sub = dir.path().join("sub");
+    fs::create_dir(&sub).unwrap();
+    fs::write(sub.join("data.xyz"), b"???").unwrap();
+    fs::write(sub.join("more.xyz"), b"???").unwrap();
+    fs::write(sub.join("README"), b"???").unwrap();
+
+    let output = run(&["--report-unhandled", dir.path().to_str().unwrap()]);
+    // This doesn't change the exit code
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Files not matched by any handler, by extension:"));
+    assert!(stdout.contains("  .xyz: 2\n"));
+    assert!(stdout.contains("  (no extension): 1\n"));
+    assert!(!stdout.contains("  .pyc:"));
+    assert!(!stdout.contains("  .a:"));
+}
+
+#[test]
+fn test_progress() {
+    let dir = TempDir::new().unwrap();
+    prepare_files(&dir);
+
+    let sub = dir.path().join("sub");
+    fs::create_dir(&sub).unwrap();
+    for i in 0..2500 {
+        fs::write(sub.join(format!("data{i}.xyz")), b"").unwrap();
+    }
+
+    let output = run(&["--progress", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // stderr is not a terminal here, so plain lines are printed,
+    // at most one per 1000 files
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr.lines().filter(|l| l.contains("/2503 ")).collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("1000/2503 "));
+    assert!(lines[1].starts_with("2000/2503 "));
+    assert!(!stderr.contains('\r'));
+}
+
+#[test]
+fn test_quiet() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("adapters.cpython-312.pyc");
+    fs::copy("tests/cases/adapters.cpython-312~fixed.pyc", &path).unwrap();
+
+    let output = run(&["--quiet", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+    // By default, there is nothing about the unmodified file, only the summary
+    let output = run(&[path.to_str().unwrap()]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "1 files, 0 modified, 0 errors, 0 skipped\n");
+}
+
+#[test]
+fn test_default_output() {
+    let dir = TempDir::new().unwrap();
+    let files = prepare_files(&dir);
+
+    // One line for each modified file, then the summary
+    let output = run(&[dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    for (path, modified) in &files {
+        let n = stdout.lines().filter(|l| l.starts_with(path.to_str().unwrap())).count();
+        assert_eq!(n, if *modified { 1 } else { 0 }, "{}", path.display());
+    }
+    assert_eq!(stdout.lines().count(), 2);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "3 files, 2 modified, 0 errors, 0 skipped\n");
+}
+
+#[test]
+fn test_max_file_size() {
+    let dir = TempDir::new().unwrap();
+    let files = prepare_files(&dir);
+    let (archive, image) = (&files[0].0, &files[1].0);
+    let data = fs::read(archive).unwrap();
+
+    // testrelro.a has 1726 bytes and the pyc file more, the image 209 bytes
+    let output = run(&["--max-file-size=1000", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("{}: file size 1726 is above --max-file-size=1000, skipping",
+                                     archive.display())));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "3 files, 1 modified, 0 errors, 2 skipped\n");
+    assert_eq!(fs::read(archive).unwrap(), data);
+    assert_ne!(fs::read(image).unwrap(), fs::read("tests/cases/image-metadata.png").unwrap());
+
+    // Without the option, there is no limit
+    let output = run(&[archive.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_ne!(fs::read(archive).unwrap(), data);
+}
+
+#[test]
+fn test_content_detect() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("module.bin");
+    fs::copy("tests/cases/adapters.cpython-312.pyc", &path).unwrap();
+    let data = fs::read(&path).unwrap();
+
+    // By default, only the extension is looked at
+    let output = run(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(&path).unwrap(), data);
+
+    let output = run(&["--content-detect", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(&path).unwrap(),
+               fs::read("tests/cases/adapters.cpython-312~fixed.pyc").unwrap());
+
+    // Zip archives are recognized by the local file header
+    let archive = dir.path().join("archive.egg");
+    fs::copy("tests/cases/orphan-pyc.whl", &archive).unwrap();
+    let output = run(&["--content-detect", "-v", archive.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("matched by contents for handler zip"), "{stdout}");
+
+    // Files which are not recognized are still unhandled
+    fs::write(dir.path().join("data.bin"), b"\x0d\x0a").unwrap();
+    let output = run(&["--content-detect", "--fail-on-unhandled", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = TempDir::new().unwrap();
+    prepare_files(&dir);
+    fs::write(dir.path().join("README"), b"???").unwrap();
+
+    // Some files are modified, others are not matched by any handler
+    let output = run(&[dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "4 files, 2 modified, 0 errors, 1 skipped\n");
+
+    // Everything is normalized now
+    let output = run(&["--check", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // Some files would be modified
+    let unnormalized = dir.path().join("adapters.cpython-312.pyc");
+    fs::copy("tests/cases/adapters.cpython-312.pyc", &unnormalized).unwrap();
+    let output = run(&["--check", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("4 files, 1 not normalized, 0 errors, 1 skipped\n"));
+
+    // One file cannot be processed, because its backup already exists,
+    // the other files are still processed
+    let backup = dir.path().join("adapters.cpython-312.pyc.orig");
+    fs::write(&backup, b"").unwrap();
+    fs::copy("tests/cases/testrelro.a", dir.path().join("testrelro.a")).unwrap();
+    let output = run(&["--backup", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("5 files, 1 modified, 1 errors, 2 skipped\n"));
+    assert_eq!(fs::read(&unnormalized).unwrap(), fs::read("tests/cases/adapters.cpython-312.pyc").unwrap());
+    assert_ne!(fs::read(dir.path().join("testrelro.a")).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+
+    // A file in a format that the handler does not understand is skipped,
+    // and only fails with --check
+    let truncated = dir.path().join("libtruncated.a");
+    fs::write(&truncated, b"!<arch>\nfoo.o/").unwrap();
+    let output = run(&[truncated.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "1 files, 0 modified, 0 errors, 1 skipped\n");
+    let output = run(&["--check", truncated.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("1 files, 0 not normalized, 1 errors, 0 skipped\n"));
+
+    // Usage errors, both from clap and found later
+    let output = run(&["--no-such-option", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run(&["--only=nosuchhandler", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Unknown handler name"));
+
+    // The options are valid, but the environment is not
+    let output = run(&["--brp", dir.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("$RPM_BUILD_ROOT variable is not defined"));
+}
+
+#[test]
+fn test_timestamp() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("testrelro.a");
+    let path = path.to_str().unwrap();
+    let expected = fs::read("tests/cases/testrelro.fixed.a").unwrap();
+
+    // testrelro.fixed.a is normalized with the epoch 111
+    for ts in ["111", "1970-01-01T00:01:51Z"] {
+        fs::copy("tests/cases/testrelro.a", path).unwrap();
+
+        let output = command()
+            .env("SOURCE_DATE_EPOCH", "1704106800")
+            .args([&format!("--timestamp={ts}"), path])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(fs::read(path).unwrap(), expected);
+    }
+
+    // Without the option, $SOURCE_DATE_EPOCH is used
+    fs::copy("tests/cases/testrelro.a", path).unwrap();
+    let output = command()
+        .env("SOURCE_DATE_EPOCH", "111")
+        .arg(path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(path).unwrap(), expected);
+
+    fs::copy("tests/cases/testrelro.a", path).unwrap();
+    for ts in ["yesterday", "2020-13-01T00:00:00Z", "-5", "99999999999999999"] {
+        let output = run(&[&format!("--timestamp={ts}"), path]);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--timestamp"), "{stderr}");
+    }
+    assert_eq!(fs::read(path).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+
+    // An invalid $SOURCE_DATE_EPOCH is a usage error too
+    for ts in ["yesterday", "99999999999999999", "-99999999999999999"] {
+        let output = command()
+            .env("SOURCE_DATE_EPOCH", ts)
+            .arg(path)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+    }
+
+    // The warnings name the source of the timestamp
+    let output = run(&["--check", "--timestamp=4000000000", path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Timestamp from --timestamp is in the future: 4000000000"), "{stdout}");
+
+    let output = command()
+        .env("SOURCE_DATE_EPOCH", "4000000000")
+        .args(["--check", path])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Timestamp from $SOURCE_DATE_EPOCH is in the future: 4000000000"), "{stdout}");
+}
+
+#[test]
+fn test_elf_linker_script() {
+    let dir = TempDir::new().unwrap();
+    let script = dir.path().join("libfoo.so");
+    fs::write(&script, b"/* GNU ld script */\nINPUT(libfoo.so.1)\n").unwrap();
+
+    // Not an ELF file, so there is nothing to check, with or without ELF options
+    for args in [&["--check"][..], &["--check", "--elf-strip-comment"]] {
+        let output = command().args(args).arg(&script).output().unwrap();
+        assert_eq!(output.status.code(), Some(0));
+    }
+}
+
+#[test]
+fn test_warn_orphan_pyc() {
+    let dir = TempDir::new().unwrap();
+    let wheel = dir.path().join("orphan-pyc.whl");
+    fs::copy("tests/cases/orphan-pyc.whl", &wheel).unwrap();
+    let wheel = wheel.to_str().unwrap();
+
+    let output = run(&["--warn-orphan-pyc", wheel]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // Warnings are logged to stdout, one for each pyc file without source.
+    // foo/__init__.py exists, so its pyc file is fine.
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let warnings: Vec<&str> = stdout.lines()
+        .filter(|line| line.ends_with("compiled bytecode without source file"))
+        .collect();
+    assert_eq!(warnings, [
+        format!("{wheel}: foo/__pycache__/gone.cpython-312.opt-1.pyc: compiled bytecode without source file"),
+        format!("{wheel}: foo/legacy.pyc: compiled bytecode without source file"),
+    ]);
+
+    // Not without the option
+    let output = run(&[wheel]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("without source file"), "{stdout}");
+
+    assert_eq!(fs::read(wheel).unwrap(), fs::read("tests/cases/orphan-pyc.whl").unwrap());
+}
@@ -1,12 +1,25 @@
 mod test_ar;
+mod test_classfile;
+mod test_cpio;
+mod test_elf;
+mod test_font;
 mod test_javadoc;
+mod test_mo;
+mod test_pe;
+mod test_png;
 mod test_pyc;
+mod test_rlib;
+mod test_squashfs;
+mod test_sqlite;
+mod test_zip;
 
 use anyhow::Result;
 use std::fs;
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
 use add_determinism::options;
@@ -52,11 +65,101 @@ impl handlers::Processor for Trivial {
     }
 }
 
+/// A handler which takes a second for each file
+struct Sleepy {}
+
+impl Sleepy {
+    pub fn boxed(_config: &Rc<options::Config>) -> Box<dyn handlers::Processor> {
+        Box::new(Self {})
+    }
+}
+impl handlers::Processor for Sleepy {
+    fn name(&self) -> &str {
+        "sleepy"
+    }
+
+    fn filter(&self, _path: &Path) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn process(&self, _input_path: &Path) -> Result<handlers::ProcessResult> {
+        thread::sleep(Duration::from_secs(1));
+        Ok(handlers::ProcessResult::Replaced)
+    }
+}
+
+/// The ar handler, but slower
+struct SlowAr {
+    config: Rc<options::Config>,
+}
+
+impl SlowAr {
+    pub fn boxed(config: &Rc<options::Config>) -> Box<dyn handlers::Processor> {
+        Box::new(Self { config: config.clone() })
+    }
+}
+impl handlers::Processor for SlowAr {
+    fn name(&self) -> &str {
+        "slow-ar"
+    }
+
+    fn filter(&self, _path: &Path) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn process(&self, input_path: &Path) -> Result<handlers::ProcessResult> {
+        thread::sleep(Duration::from_millis(200));
+        handlers::ar::Ar::boxed(&self.config).process(input_path)
+    }
+}
+
+#[test]
+fn test_handler_timeout_abandoned() {
+    let (dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let cfg = options::Config::empty(111, false);
+
+    let res = handlers::process_with_timeout(&cfg, SlowAr::boxed, &input, Duration::from_millis(50));
+    assert!(res.is_err());
+
+    // The thread finishes later, but the file is not replaced
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    let res = handlers::process_with_timeout(&cfg, SlowAr::boxed, &input, Duration::from_secs(10));
+    assert_eq!(res.unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.fixed.a").unwrap());
+}
+
+#[test]
+fn test_handler_timeout() {
+    let (_dir, input) = prepare_dir("tests/cases/libempty.a").unwrap();
+    let cfg = options::Config::empty(0, false);
+
+    let start = Instant::now();
+    let res = handlers::process_with_timeout(&cfg, Sleepy::boxed, &input, Duration::from_millis(50));
+    assert!(res.unwrap_err().to_string().contains("did not finish within --handler-timeout=0.05"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // A failure is counted as an error
+    let res = handlers::ProcessResult::convert_and_warn(
+        &input,
+        handlers::process_with_timeout(&cfg, Sleepy::boxed, &input, Duration::from_millis(50)));
+    assert_eq!(res, handlers::ProcessResult::Error);
+
+    let res = handlers::process_with_timeout(&cfg, Sleepy::boxed, &input, Duration::from_secs(10));
+    assert_eq!(res.unwrap(), handlers::ProcessResult::Replaced);
+
+    // The handler runs normally
+    let res = handlers::process_with_timeout(&cfg, handlers::ar::Ar::boxed, &input, Duration::from_secs(10));
+    assert_eq!(res.unwrap(), handlers::ProcessResult::Noop);
+}
+
 #[test]
 fn test_input_output_helper_drop() {
     let (_dir, input) = prepare_dir("tests/cases/libempty.a").unwrap();
 
-    let (mut helper, _) = handlers::InputOutputHelper::open(&*input, false).unwrap();
+    let (mut helper, _) = handlers::InputOutputHelper::open(&input, &options::Config::empty(0, false)).unwrap();
     helper.open_output().unwrap();
 
     let output_path = helper.output_path.as_ref().unwrap().clone();
@@ -66,6 +169,77 @@ fn test_input_output_helper_drop() {
     assert!(!output_path.exists());
 }
 
+fn make_backup_handler(check: bool, force: bool) -> Box<dyn handlers::Processor> {
+    let mut cfg = options::Config::empty(111, check);
+    cfg.backup = Some(".orig".to_string());
+    cfg.force_backup = force;
+    handlers::ar::Ar::boxed(&Rc::new(cfg))
+}
+
+#[test]
+fn test_backup() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let backup = input.with_extension("a.orig");
+
+    let ar = make_backup_handler(false, false);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    assert_eq!(fs::read(&backup).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.fixed.a").unwrap());
+    assert_eq!(backup.metadata().unwrap().modified().unwrap(),
+               input.metadata().unwrap().modified().unwrap());
+
+    // The file is not modified again, so the backup is not touched
+    fs::write(&backup, b"old backup").unwrap();
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(fs::read(&backup).unwrap(), b"old backup");
+}
+
+#[test]
+fn test_backup_rewritten() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let backup = input.with_extension("a.orig");
+    let link = input.with_extension("b");
+    fs::hard_link(&*input, &link).unwrap();
+
+    let ar = make_backup_handler(false, false);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Rewritten);
+
+    assert_eq!(fs::read(&backup).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.fixed.a").unwrap());
+    assert_eq!(fs::read(&link).unwrap(), fs::read("tests/cases/testrelro.fixed.a").unwrap());
+}
+
+#[test]
+fn test_backup_unchanged_and_check() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.fixed.a").unwrap();
+    let ar = make_backup_handler(false, false);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Noop);
+    assert!(!input.with_extension("a.orig").exists());
+
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let ar = make_backup_handler(true, false);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+    assert!(!input.with_extension("a.orig").exists());
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+}
+
+#[test]
+fn test_backup_exists() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let backup = input.with_extension("a.orig");
+    fs::write(&backup, b"old backup").unwrap();
+
+    let ar = make_backup_handler(false, false);
+    assert!(ar.process(&input).is_err());
+    assert_eq!(fs::read(&backup).unwrap(), b"old backup");
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+
+    let ar = make_backup_handler(false, true);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(fs::read(&backup).unwrap(), fs::read("tests/cases/testrelro.a").unwrap());
+}
+
 fn stats(
     inodes_processed: u64,
     inodes_replaced: u64,
@@ -79,6 +253,10 @@ fn stats(
         inodes_rewritten,
         misunderstood: 0,
         errors: 0,
+        unhandled: 0,
+        unhandled_extensions: Default::default(),
+        too_large: 0,
+        ignored: 0,
     }
 }
 
@@ -86,23 +264,24 @@ fn stats(
 fn test_inode_map() {
     let (dir, _input) = prepare_dir("tests/cases/libempty.a").unwrap();
 
+    let cfg = options::Config::empty(0, false);
     let mut handlers = vec![ Trivial::boxed() ];
     let mut cache = handlers::inodes_seen();
 
-    let mods = handlers::process_file_or_dir(&handlers, &mut cache, dir.path(), None).unwrap();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
     assert_eq!(mods, stats(1, 1, 0));
 
-    let mods = handlers::process_file_or_dir(&handlers, &mut cache, dir.path(), None).unwrap();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
     assert_eq!(mods, stats(0, 0, 0));
 
     assert_eq!(cache.len(), 1);
 
     handlers.push(Trivial::boxed());
 
-    let mods = handlers::process_file_or_dir(&handlers, &mut cache, dir.path(), None).unwrap();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
     assert_eq!(mods, stats(1, 1, 0));
 
-    let mods = handlers::process_file_or_dir(&handlers, &mut cache, dir.path(), None).unwrap();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
     assert_eq!(mods, stats(0, 0, 0));
 
     assert_eq!(cache.len(), 1);
@@ -118,10 +297,10 @@ fn test_inode_map_2() {
     let handlers = vec![ar];
     let mut cache = handlers::inodes_seen();
 
-    let mods = handlers::process_file_or_dir(&handlers, &mut cache, dir.path(), None).unwrap();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
     assert_eq!(mods, stats(1, 1, 0));
 
-    let mods = handlers::process_file_or_dir(&handlers, &mut cache, dir.path(), None).unwrap();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
     // The file was already processed, so no change
     assert_eq!(mods, stats(0, 0, 0));
 
@@ -129,6 +308,194 @@ fn test_inode_map_2() {
     assert_eq!(cache.len(), 2);
 }
 
+#[test]
+fn test_magic_rule() {
+    let (dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    fs::rename(&*input, input.with_extension("bin")).unwrap();
+
+    let cfg = Rc::new(options::Config::empty(111, false));
+    let handlers = vec![ handlers::ar::Ar::boxed(&cfg) ];
+    let mut cache = handlers::inodes_seen();
+
+    // Not matched by the filter
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
+    assert_eq!(mods, handlers::Stats { unhandled: 1, ..stats(0, 0, 0) });
+
+    let rule = |s| options::parse_magic_rule(s).unwrap();
+
+    let mut cfg2 = options::Config::empty(111, false);
+    cfg2.magic_rules = vec![
+        rule("0x1000000:00=>ar"),    // past the end of file
+        rule("0:badc0ffee0=>ar"),    // does not match
+        rule("0:213c61726368=>ar"),  // "!<arch"
+    ];
+    // No matching handler
+    let mods = handlers::process_file_or_dir(&cfg2, &[], &mut cache, dir.path(), None).unwrap();
+    assert_eq!(mods, handlers::Stats { unhandled: 1, ..stats(0, 0, 0) });
+    let mods = handlers::process_file_or_dir(&cfg2, &handlers, &mut cache, dir.path(), None).unwrap();
+    assert_eq!(mods, stats(1, 1, 0));
+
+    let mods = handlers::process_file_or_dir(&cfg2, &handlers, &mut cache, dir.path(), None).unwrap();
+    // The file was already processed, so no change
+    assert_eq!(mods, stats(0, 0, 0));
+}
+
+#[test]
+fn test_symlink_policy() {
+    let (target_dir, target) = prepare_dir("tests/cases/adapters.cpython-312.pyc").unwrap();
+    let orig = fs::read(&*target).unwrap();
+
+    let dir = TempDir::new().unwrap();
+    let link = dir.path().join("link.cpython-312.pyc");
+    std::os::unix::fs::symlink(&*target, &link).unwrap();
+
+    let mut cfg = options::Config::empty(111, false);
+    let handlers = vec![ handlers::pyc::Pyc::boxed(&Rc::new(options::Config::empty(111, false))) ];
+
+    // By default, the symlink is skipped, both in a directory and as an argument
+    for path in [dir.path(), &link] {
+        let mut cache = handlers::inodes_seen();
+        let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, path, None).unwrap();
+        assert_eq!(mods.inodes_processed, 0);
+    }
+    assert!(link.symlink_metadata().unwrap().is_symlink());
+    assert_eq!(fs::read(&*target).unwrap(), orig);
+
+    // With --follow-symlinks, the target is modified and the symlink is kept
+    cfg.follow_symlinks = true;
+    let mut cache = handlers::inodes_seen();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
+    assert_eq!(mods, stats(1, 1, 0));
+
+    assert!(link.symlink_metadata().unwrap().is_symlink());
+    assert_eq!(fs::read_link(&link).unwrap(), *target);
+    assert_ne!(fs::read(&*target).unwrap(), orig);
+
+    // The temporary file was created next to the target and is gone
+    assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_exclude_include() {
+    let dir = TempDir::new().unwrap();
+    let paths = ["libtop.a", "sub/libsub.a", "vendor/fixtures/libvendored.a"]
+        .map(|p| dir.path().join(p));
+    for path in &paths {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy("tests/cases/testrelro.a", path).unwrap();
+    }
+    let orig = fs::read("tests/cases/testrelro.a").unwrap();
+    let modified = |path: &Path| fs::read(path).unwrap() != orig;
+
+    let handlers = vec![ handlers::ar::Ar::boxed(&Rc::new(options::Config::empty(111, false))) ];
+    let pattern = |s| glob::Pattern::new(s).unwrap();
+
+    // The excluded directory is not descended into
+    let mut cfg = options::Config::empty(111, false);
+    cfg.exclude = vec![pattern("vendor")];
+    let mut cache = handlers::inodes_seen();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
+    assert_eq!(mods.directories, 2);
+    assert_eq!(mods.files, 2);
+    assert_eq!(mods.inodes_replaced, 2);
+    assert!(modified(&paths[0]));
+    assert!(modified(&paths[1]));
+    assert!(!modified(&paths[2]));
+
+    // Patterns match paths relative to the argument
+    cfg.exclude = vec![pattern("*/libvendored.a")];
+    let mut cache = handlers::inodes_seen();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, &dir.path().join("vendor"), None).unwrap();
+    assert_eq!(mods.files, 0);
+    assert!(!modified(&paths[2]));
+
+    // With --include, only matching files are processed
+    let mut cfg = options::Config::empty(111, false);
+    cfg.include = vec![pattern("vendor/**/*.a")];
+    cfg.exclude = vec![pattern("sub")];
+    let mut cache = handlers::inodes_seen();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, dir.path(), None).unwrap();
+    assert_eq!(mods.files, 1);
+    assert_eq!(mods.inodes_replaced, 1);
+    assert!(modified(&paths[2]));
+
+    // If the argument is a file, its name is matched
+    fs::copy("tests/cases/testrelro.a", &paths[0]).unwrap();
+    cfg.include = vec![];
+    cfg.exclude = vec![pattern("libtop.*")];
+    let mut cache = handlers::inodes_seen();
+    let mods = handlers::process_file_or_dir(&cfg, &handlers, &mut cache, &paths[0], None).unwrap();
+    assert_eq!(mods.files, 0);
+    assert!(!modified(&paths[0]));
+}
+
+#[test]
+fn test_replace_path_prefix() {
+    // Both files were built in /tmp/build-add-determinism
+    let (_dir, pyc_path) = prepare_dir("tests/cases/prefix-map.cpython-311.pyc").unwrap();
+    let (_dir2, elf_path) = prepare_dir("tests/cases/prefix-map.o").unwrap();
+
+    let mut cfg = options::Config::empty(111, false);
+    cfg.path_prefix_map = vec![
+        options::parse_path_prefix("/tmp/build-add-determinism=/usr/src/debug").unwrap(),
+    ];
+    let cfg = Rc::new(cfg);
+
+    for handler in [handlers::pyc::Pyc::boxed(&cfg), handlers::elf::Elf::boxed(&cfg)] {
+        let path = if handler.name() == "pyc" { &pyc_path } else { &elf_path };
+        assert!(handler.filter(path).unwrap());
+        assert_eq!(handler.process(path).unwrap(), handlers::ProcessResult::Replaced);
+        assert_eq!(handler.process(path).unwrap(), handlers::ProcessResult::Noop);
+    }
+
+    let contains = |data: &[u8], s: &str| data.windows(s.len()).any(|w| w == s.as_bytes());
+
+    // co_filename is rewritten
+    let pyc = fs::read(&*pyc_path).unwrap();
+    assert!(contains(&pyc, "/usr/src/debug/hello.py"));
+    assert!(!contains(&pyc, "/tmp/build-add-determinism"));
+
+    // The size of the ELF file doesn't change, the path is padded with slashes
+    let elf = fs::read(&*elf_path).unwrap();
+    assert_eq!(elf.len(), fs::metadata("tests/cases/prefix-map.o").unwrap().len() as usize);
+    assert!(contains(&elf, "/usr/src/debug////////////\0"));
+    assert!(!contains(&elf, "/tmp/build-add-determinism"));
+
+    // Both point to the same directory
+    let (_, sections) = handlers::elf::read_sections(&mut io::Cursor::new(&elf), elf.len() as u64).unwrap();
+    let line_str = sections.iter().find(|s| s.name == ".debug_line_str").unwrap();
+    let comp_dir = elf[line_str.offset as usize..(line_str.offset + line_str.size) as usize]
+        .split(|b| *b == 0)
+        .map(|s| String::from_utf8(s.to_vec()).unwrap())
+        .find(|s| s.starts_with('/'))
+        .unwrap();
+    assert_eq!(Path::new(&comp_dir).join("hello.py"), Path::new("/usr/src/debug/hello.py"));
+
+    // A longer prefix is refused by --replace-path-prefix, because it does
+    // not fit into the ELF file. It can still be used through the library.
+    let (_dir, pyc_path) = prepare_dir("tests/cases/prefix-map.cpython-311.pyc").unwrap();
+    let (_dir2, elf_path) = prepare_dir("tests/cases/prefix-map.o").unwrap();
+
+    let mut cfg = options::Config::empty(111, false);
+    cfg.path_prefix_map = vec![
+        options::PathPrefix {
+            old: "/tmp/build-add-determinism".to_string(),
+            new: "/usr/src/debug/add-determinism-1.0".to_string(),
+        },
+    ];
+    let cfg = Rc::new(cfg);
+
+    let pyc = handlers::pyc::Pyc::boxed(&cfg);
+    assert_eq!(pyc.process(&pyc_path).unwrap(), handlers::ProcessResult::Replaced);
+    assert!(contains(&fs::read(&*pyc_path).unwrap(), "/usr/src/debug/add-determinism-1.0/hello.py"));
+
+    let elf = handlers::elf::Elf::boxed(&cfg);
+    let err = elf.process(&elf_path).unwrap_err();
+    assert!(err.to_string().contains("by longer"));
+    assert_eq!(fs::read(&*elf_path).unwrap(), fs::read("tests/cases/prefix-map.o").unwrap());
+}
+
 fn test_corpus_file(handler: Box<dyn handlers::Processor>, filename: &str) {
     let filename = Path::new(filename);
     let (_dir, input) = prepare_dir(filename.to_str().unwrap()).unwrap();
@@ -141,8 +508,8 @@ fn test_corpus_file(handler: Box<dyn handlers::Processor>, filename: &str) {
         handlers::ProcessResult::Noop
     };
 
-    assert!(handler.filter(&*input).unwrap());
-    assert_eq!(handler.process(&*input).unwrap(), have_mod);
+    assert!(handler.filter(&input).unwrap());
+    assert_eq!(handler.process(&input).unwrap(), have_mod);
 
     let mut data_expected = vec![];
     fs::File::open(
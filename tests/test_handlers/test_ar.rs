@@ -16,11 +16,11 @@ fn test_libempty() {
 
     let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
 
-    assert!(ar.filter(&*input).unwrap());
+    assert!(ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(ar.process(&*input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Noop);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -35,11 +35,11 @@ fn test_testrelro() {
     let cfg = Rc::new(options::Config::empty(111, false));
     let ar = ar::Ar::boxed(&cfg);
 
-    assert!(ar.filter(&*input).unwrap());
+    assert!(ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(ar.process(&*input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Replaced);
 
     let new = input.metadata().unwrap();
     // because of timestamp granularity, creation ts might be equal
@@ -55,11 +55,11 @@ fn test_testrelro_check() {
     let cfg = Rc::new(options::Config::empty(111, true));
     let ar = ar::Ar::boxed(&cfg);
 
-    assert!(ar.filter(&*input).unwrap());
+    assert!(ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(ar.process(&*input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Replaced);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -73,13 +73,13 @@ fn test_testrelro_hardlinked() {
 
     let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
 
-    assert!(ar.filter(&*input).unwrap());
+    assert!(ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
     fs::hard_link(&*input, (*input).with_extension("b")).unwrap();
 
-    assert_eq!(ar.process(&*input).unwrap(), handlers::ProcessResult::Rewritten);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Rewritten);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -93,13 +93,13 @@ fn test_testrelro_hardlinked_check() {
 
     let ar = make_handler(111, true, ar::Ar::boxed).unwrap();
 
-    assert!(ar.filter(&*input).unwrap());
+    assert!(ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
     fs::hard_link(&*input, (*input).with_extension("b")).unwrap();
 
-    assert_eq!(ar.process(&*input).unwrap(), handlers::ProcessResult::Rewritten);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Rewritten);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -113,11 +113,11 @@ fn test_testrelro_c() {
 
     let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
 
-    assert!(!ar.filter(&*input).unwrap());
+    assert!(!ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert!(ar.process(&*input).is_err());
+    assert!(ar.process(&input).is_err());
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -131,11 +131,11 @@ fn test_testrelro_fixed() {
 
     let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
 
-    assert!(ar.filter(&*input).unwrap());
+    assert!(ar.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(ar.process(&*input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(ar.process(&input).unwrap(), handlers::ProcessResult::Noop);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -154,3 +154,66 @@ fn test_libhsbase_compat_batteries() {
     let ar = make_handler(1717842014, false, ar::Ar::boxed).unwrap();
     test_corpus_file(ar, filename);
 }
+
+#[test]
+fn test_testrelro_sym64() {
+    // Like testrelro.a, but with the 64-bit "/SYM64/" symbol table
+    let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
+    test_corpus_file(ar, "tests/cases/testrelro-sym64.a");
+}
+
+#[test]
+fn test_sym64_bad_offset() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro-sym64.a").unwrap();
+
+    // Point the only symbol past the end of the archive
+    let mut data = fs::read(&*input).unwrap();
+    data[8 + 60 + 8 .. 8 + 60 + 16].copy_from_slice(&0x1_0000_0000_u64.to_be_bytes());
+    fs::write(&*input, &data).unwrap();
+
+    let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
+
+    let err = ar.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
+
+#[test]
+fn test_member_size_too_large() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+
+    // The size of the first member is much larger than the archive
+    let mut data = fs::read(&*input).unwrap();
+    data[8 + 48 .. 8 + 58].copy_from_slice(b"9999999999");
+    fs::write(&*input, &data).unwrap();
+
+    let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
+
+    let err = ar.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+    assert_eq!(fs::read(&*input).unwrap(), data);
+}
+
+#[test]
+fn test_gnu_ar_llvm_ar_converge() {
+    // The same objects archived with "ar rcsU" and with
+    // "SYM64_THRESHOLD=0 llvm-ar rcsU". The mode fields differ
+    // ("100644" vs "644") and llvm-ar uses the 64-bit symbol table.
+    let (_dir1, gnu) = prepare_dir("tests/cases/convergence-gnu-ar.a").unwrap();
+    let (_dir2, llvm) = prepare_dir("tests/cases/convergence-llvm-ar.a").unwrap();
+    assert_ne!(fs::read(&*gnu).unwrap(), fs::read(&*llvm).unwrap());
+
+    let ar = make_handler(111, false, ar::Ar::boxed).unwrap();
+
+    assert_eq!(ar.process(&gnu).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(ar.process(&llvm).unwrap(), handlers::ProcessResult::Replaced);
+
+    let data = fs::read(&*gnu).unwrap();
+    assert_eq!(data, fs::read(&*llvm).unwrap());
+    assert_eq!(&data[8..24], b"/               ");
+    assert_eq!(&data[8 + 60 + 64 + 60 + 26 + 16 .. 8 + 60 + 64 + 60 + 26 + 48],
+               b"111         0     0     644     ");
+
+    // And the result is stable
+    assert_eq!(ar.process(&gnu).unwrap(), handlers::ProcessResult::Noop);
+}
+
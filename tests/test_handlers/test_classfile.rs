@@ -0,0 +1,132 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::rc::Rc;
+
+use add_determinism::handlers;
+use add_determinism::handlers::classfile;
+use add_determinism::options;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+// The class was compiled by javac, and then SourceFile was changed to
+// an absolute path and a SourceDebugExtension attribute was added, like
+// the ones written by JSP compilers. The original "Hello.java" string
+// (#44) is still in the constant pool, the path is #45.
+const SOURCE_PATH: &[u8] = b"/builddir/build/BUILD/hello-1.0/src/Hello.java";
+const SMAP: &[u8] = b"SMAP\nHello.java\nJSP\n";
+
+fn make_strip_handler() -> Box<dyn handlers::Processor> {
+    let mut cfg = options::Config::empty(1704106800, false);
+    cfg.classfile_strip_source_info = true;
+    classfile::Classfile::boxed(&Rc::new(cfg))
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|w| w == needle)
+}
+
+fn pool_count(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[8], data[9]])
+}
+
+#[test]
+fn test_source_debug_extension() {
+    test_corpus_file(make_strip_handler(), "tests/cases/source-debug-extension.class");
+
+    let orig = fs::read("tests/cases/source-debug-extension.class").unwrap();
+    let fixed = fs::read("tests/cases/source-debug-extension.class.fixed").unwrap();
+
+    assert!(contains(&orig, SMAP));
+    assert!(!contains(&fixed, SMAP));
+
+    // The path is only used by SourceFile, so it is replaced by the base name
+    assert!(contains(&orig, SOURCE_PATH));
+    assert!(!contains(&fixed, SOURCE_PATH));
+    assert_eq!(pool_count(&orig), pool_count(&fixed));
+
+    // The only attribute left is SourceFile, with the length 2 and the
+    // same index as before (#45)
+    let count = u16::from_be_bytes(fixed[fixed.len() - 10..fixed.len() - 8].try_into().unwrap());
+    assert_eq!(count, 1);
+    assert_eq!(fixed[fixed.len() - 6..], [0, 0, 0, 2, 0, 45]);
+
+    let (_dir, input) = prepare_dir("tests/cases/source-debug-extension.class").unwrap();
+    let h = make_strip_handler();
+    assert_eq!(h.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(h.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_kept_by_default() {
+    let (_dir, input) = prepare_dir("tests/cases/source-debug-extension.class").unwrap();
+
+    let classfile = make_handler(1704106800, false, classfile::Classfile::boxed).unwrap();
+    assert!(!classfile.filter(&input).unwrap());
+    assert_eq!(classfile.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+/// Make the String constant #16 refer to the path (#45) instead of
+/// "Hello from " (#17), so that the path is not only used by SourceFile
+fn share_source_path(data: &mut [u8]) {
+    let pos = data.windows(3).position(|w| w == b"\x08\x00\x11").unwrap();
+    data[pos + 2] = 45;
+}
+
+#[test]
+fn test_source_path_shared() {
+    let (_dir, input) = prepare_dir("tests/cases/source-debug-extension.class").unwrap();
+
+    let mut data = fs::read(&*input).unwrap();
+    share_source_path(&mut data);
+    fs::write(&*input, &data).unwrap();
+
+    let h = make_strip_handler();
+    assert_eq!(h.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    // The path is kept, and SourceFile refers to "Hello.java" (#44)
+    let new = fs::read(&*input).unwrap();
+    assert_eq!(pool_count(&new), pool_count(&data));
+    assert!(contains(&new, SOURCE_PATH));
+    assert_eq!(new[new.len() - 6..], [0, 0, 0, 2, 0, 44]);
+
+    assert_eq!(h.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_source_file_added() {
+    let (_dir, input) = prepare_dir("tests/cases/source-debug-extension.class").unwrap();
+
+    // Rename the string with the base name, so that it must be added
+    let mut data = fs::read(&*input).unwrap();
+    share_source_path(&mut data);
+    let pos = data.windows(12).position(|w| w == b"\x00\x0aHello.java").unwrap();
+    data[pos + 3] = b'a';
+    fs::write(&*input, &data).unwrap();
+
+    let h = make_strip_handler();
+    assert_eq!(h.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let new = fs::read(&*input).unwrap();
+    assert_eq!(pool_count(&new), pool_count(&data) + 1);
+    assert!(contains(&new, b"\x01\x00\x0aHello.java"));
+    assert!(contains(&new, b"\x00\x0aHallo.java"));
+    assert!(!contains(&new, SMAP));
+
+    assert_eq!(h.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_bad_classfile() {
+    let h = make_strip_handler();
+
+    let (_dir, input) = prepare_dir("tests/cases/source-debug-extension.class").unwrap();
+    let data = fs::read(&*input).unwrap();
+    fs::write(&*input, &data[..data.len() - 1]).unwrap();
+    let err = h.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let err = h.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
@@ -0,0 +1,108 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::path::Path;
+
+use add_determinism::handlers;
+use add_determinism::handlers::cpio;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+struct Member {
+    name: String,
+    ino: u32,
+    owner: (u32, u32),
+    nlink: u32,
+    mtime: u32,
+    content: Vec<u8>,
+}
+
+/// A minimal independent newc parser
+fn read_members(path: &Path) -> Vec<Member> {
+    let data = fs::read(path).unwrap();
+    let mut members = vec![];
+    let mut offset = 0;
+
+    loop {
+        assert_eq!(&data[offset..offset + 6], b"070701");
+        let field = |n: usize| {
+            let start = offset + 6 + n * 8;
+            u32::from_str_radix(std::str::from_utf8(&data[start..start + 8]).unwrap(), 16).unwrap()
+        };
+        let (filesize, namesize) = (field(6) as usize, field(11) as usize);
+        let name = String::from_utf8(data[offset + 110..offset + 110 + namesize - 1].to_vec()).unwrap();
+
+        let start = (offset + 110 + namesize).next_multiple_of(4);
+        let content = data[start..start + filesize].to_vec();
+
+        if name == "TRAILER!!!" {
+            break;
+        }
+        members.push(Member {
+            name,
+            ino: field(0),
+            owner: (field(2), field(3)),
+            nlink: field(4),
+            mtime: field(5),
+            content,
+        });
+        offset = (start + filesize).next_multiple_of(4);
+    }
+
+    members
+}
+
+#[test]
+fn test_hardlinks() {
+    let cpio = make_handler(1704106800, false, cpio::Cpio::boxed).unwrap();
+    test_corpus_file(cpio, "tests/cases/hardlinks-a.cpio");
+
+    let members = read_members(Path::new("tests/cases/hardlinks-a.cpio.fixed"));
+    let orig = read_members(Path::new("tests/cases/hardlinks-a.cpio"));
+    assert_eq!(members.len(), 6);
+
+    for (member, orig) in members.iter().zip(&orig) {
+        assert_eq!(member.name, orig.name);
+        assert_eq!(member.owner, (0, 0));
+        assert!(member.mtime <= 1704106800);
+        assert_eq!(member.content, orig.content);
+        if member.name.starts_with("usr/bin/foo") {
+            assert_eq!(member.nlink, 2);
+        }
+    }
+
+    // The hardlinked pair still shares an inode number, and nothing else does
+    let inos: Vec<u32> = members.iter().map(|m| m.ino).collect();
+    assert_eq!(inos, [1, 2, 3, 3, 4, 5]);
+
+    // The timestamps which are older are kept
+    assert_eq!(members[4].mtime, 1600000000);
+}
+
+#[test]
+fn test_hardlinks_converge() {
+    let (_dir_a, input_a) = prepare_dir("tests/cases/hardlinks-a.cpio").unwrap();
+    let (_dir_b, input_b) = prepare_dir("tests/cases/hardlinks-b.cpio").unwrap();
+
+    // Different inode and device numbers and directory link counts
+    assert_ne!(fs::read(&*input_a).unwrap(), fs::read(&*input_b).unwrap());
+
+    let cpio = make_handler(1704106800, false, cpio::Cpio::boxed).unwrap();
+    assert_eq!(cpio.process(&input_a).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(cpio.process(&input_b).unwrap(), handlers::ProcessResult::Replaced);
+
+    assert_eq!(fs::read(&*input_a).unwrap(), fs::read(&*input_b).unwrap());
+
+    assert_eq!(cpio.process(&input_a).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_truncated() {
+    let (_dir, input) = prepare_dir("tests/cases/hardlinks-a.cpio").unwrap();
+    let data = fs::read(&*input).unwrap();
+    fs::write(&*input, &data[..300]).unwrap();
+
+    let cpio = make_handler(1704106800, false, cpio::Cpio::boxed).unwrap();
+    assert!(cpio.process(&input).is_err());
+    assert_eq!(fs::read(&*input).unwrap(), &data[..300]);
+}
@@ -0,0 +1,94 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use add_determinism::options;
+use add_determinism::handlers;
+use add_determinism::handlers::elf;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+fn make_strip_comment_handler() -> Box<dyn handlers::Processor> {
+    let mut cfg = options::Config::empty(111, false);
+    cfg.elf_strip_comment = true;
+    elf::Elf::boxed(&Rc::new(cfg))
+}
+
+#[test]
+fn test_strip_comment() {
+    let elf = make_strip_comment_handler();
+    test_corpus_file(elf, "tests/cases/testrelro.o");
+
+    // Only the contents of the .comment section differ
+    let orig = fs::read("tests/cases/testrelro.o").unwrap();
+    let fixed = fs::read("tests/cases/testrelro.o.fixed").unwrap();
+    assert_eq!(orig.len(), fixed.len());
+
+    let (_, sections) = elf::read_sections(&mut Cursor::new(&fixed), fixed.len() as u64).unwrap();
+    let comment = sections.iter().find(|s| s.name == ".comment").unwrap();
+    let range = comment.offset as usize..(comment.offset + comment.size) as usize;
+
+    assert!(orig[range.clone()].starts_with(b"\0GCC: "));
+    assert!(fixed[range.clone()].iter().all(|b| *b == 0));
+    assert_eq!(orig[..range.start], fixed[..range.start]);
+    assert_eq!(orig[range.end..], fixed[range.end..]);
+
+    // The sections are the same
+    let (_, orig_sections) = elf::read_sections(&mut Cursor::new(&orig), orig.len() as u64).unwrap();
+    assert_eq!(orig_sections, sections);
+}
+
+#[test]
+fn test_comment_kept_by_default() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.o").unwrap();
+
+    let elf = make_handler(111, false, elf::Elf::boxed).unwrap();
+    assert!(!elf.filter(&input).unwrap());
+    assert_eq!(elf.process(&input).unwrap(), handlers::ProcessResult::Noop);
+
+    assert_eq!(fs::read(&*input).unwrap(), fs::read("tests/cases/testrelro.o").unwrap());
+}
+
+#[test]
+fn test_linker_script() {
+    let (dir, _) = prepare_dir("tests/cases/testrelro.o").unwrap();
+    let script = b"/* GNU ld script */\nINPUT(libfoo.so.1 AS_NEEDED(libfoo_nonshared.a))\n";
+
+    let elf = make_strip_comment_handler();
+    for (name, contents) in [("libfoo.so", &script[..]), ("empty.o", b""), ("short.o", b"\x7fE")] {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+
+        assert!(elf.filter(&path).unwrap());
+        assert_eq!(elf.process(&path).unwrap(), handlers::ProcessResult::Noop);
+        assert_eq!(fs::read(&path).unwrap(), contents);
+    }
+}
+
+#[test]
+fn test_already_stripped() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.o.fixed").unwrap();
+
+    let elf = make_strip_comment_handler();
+    assert_eq!(elf.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_loaded_comment_refused() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.o").unwrap();
+
+    // Set SHF_ALLOC on .comment, which is section 7 in this 64-bit little-endian file
+    let mut data = fs::read(&*input).unwrap();
+    let shoff = u64::from_le_bytes(data[0x28..0x30].try_into().unwrap()) as usize;
+    let flags = shoff + 7 * 64 + 8;
+    data[flags] |= 0x2;
+    fs::write(&*input, &data).unwrap();
+
+    let elf = make_strip_comment_handler();
+    let err = elf.process(&input).unwrap_err();
+    assert!(err.to_string().contains("part of a loadable segment"));
+
+    assert_eq!(fs::read(&*input).unwrap(), data);
+}
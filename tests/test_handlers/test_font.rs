@@ -0,0 +1,134 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+
+use add_determinism::handlers;
+use add_determinism::handlers::font;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+const LONGDATETIME_OFFSET: i64 = 2082844800;
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Return the created and modified timestamps from the head table
+fn head_timestamps(data: &[u8]) -> (i64, i64) {
+    let tables = font::read_tables(data).unwrap();
+    let head = tables.iter().find(|t| &t.tag == b"head").unwrap();
+    let get = |offset| {
+        i64::from_be_bytes(data[head.offset + offset..head.offset + offset + 8].try_into().unwrap())
+            - LONGDATETIME_OFFSET
+    };
+    (get(20), get(28))
+}
+
+/// Check the table checksums and checksumAdjustment, as described in
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/otff#calculating-checksums
+fn check_checksums(data: &[u8]) {
+    let sum = |bytes: &[u8]| {
+        bytes.chunks(4)
+            .map(|c| { let mut w = [0; 4]; w[..c.len()].copy_from_slice(c); u32::from_be_bytes(w) })
+            .fold(0u32, |a, b| a.wrapping_add(b))
+    };
+
+    let num_tables = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let mut adjustment = None;
+
+    for n in 0..num_tables {
+        let record = 12 + n * 16;
+        let tag = &data[record..record + 4];
+        let offset = be32(data, record + 8) as usize;
+        let length = be32(data, record + 12) as usize;
+
+        let mut table = data[offset..offset + length].to_vec();
+        if tag == b"head" {
+            adjustment = Some(be32(&table, 8));
+            table[8..12].fill(0);
+        }
+        assert_eq!(sum(&table), be32(data, record + 4), "table {:?}", String::from_utf8_lossy(tag));
+    }
+
+    // checksumAdjustment is calculated so that the whole font sums to the magic value
+    assert!(adjustment.is_some());
+    assert_eq!(sum(data), 0xb1b0afba);
+}
+
+#[test]
+fn test_head_timestamps() {
+    let font = make_handler(1704106800, false, font::Font::boxed).unwrap();
+    test_corpus_file(font, "tests/cases/font-timestamps.ttf");
+
+    let orig = fs::read("tests/cases/font-timestamps.ttf").unwrap();
+    let fixed = fs::read("tests/cases/font-timestamps.ttf.fixed").unwrap();
+
+    check_checksums(&orig);
+    check_checksums(&fixed);
+
+    assert_eq!(head_timestamps(&orig), (1710000000, 1710000100));
+    assert_eq!(head_timestamps(&fixed), (1704106800, 1704106800));
+    assert_eq!(orig.len(), fixed.len());
+}
+
+#[test]
+fn test_old_timestamps() {
+    let (_dir, input) = prepare_dir("tests/cases/font-timestamps.ttf").unwrap();
+
+    let font = make_handler(1800000000, false, font::Font::boxed).unwrap();
+    assert!(font.filter(&input).unwrap());
+    assert_eq!(font.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_out_of_range_timestamp() {
+    let (_dir, input) = prepare_dir("tests/cases/font-timestamps.ttf").unwrap();
+
+    // The created timestamp cannot be converted to the Unix epoch
+    let mut data = fs::read(&*input).unwrap();
+    let tables = font::read_tables(&data).unwrap();
+    let created = tables.iter().find(|t| &t.tag == b"head").unwrap().offset + 20;
+    data[created..created + 8].copy_from_slice(&i64::MIN.to_be_bytes());
+    fs::write(&*input, &data).unwrap();
+
+    let font = make_handler(1704106800, false, font::Font::boxed).unwrap();
+    assert_eq!(font.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    // It is left as is, the modified timestamp is still clamped
+    let data = fs::read(&*input).unwrap();
+    check_checksums(&data);
+    assert_eq!(data[created..created + 8], i64::MIN.to_be_bytes());
+    assert_eq!(i64::from_be_bytes(data[created + 8..created + 16].try_into().unwrap()) - LONGDATETIME_OFFSET,
+               1704106800);
+}
+
+#[test]
+fn test_opentype_magic() {
+    let (_dir, input) = prepare_dir("tests/cases/font-timestamps.ttf").unwrap();
+    let input = input.with_extension("otf");
+    let mut data = fs::read("tests/cases/font-timestamps.ttf").unwrap();
+    data[..4].copy_from_slice(b"OTTO");
+    fs::write(&input, &data).unwrap();
+
+    let font = make_handler(1704106800, false, font::Font::boxed).unwrap();
+    assert_eq!(font.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let data = fs::read(&input).unwrap();
+    check_checksums(&data);
+    assert_eq!(head_timestamps(&data), (1704106800, 1704106800));
+}
+
+#[test]
+fn test_bad_font() {
+    let font = make_handler(1704106800, false, font::Font::boxed).unwrap();
+
+    let (_dir, input) = prepare_dir("tests/cases/font-timestamps.ttf").unwrap();
+    let data = fs::read(&*input).unwrap();
+    fs::write(&*input, &data[..40]).unwrap();
+    let err = font.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let err = font.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
@@ -14,11 +14,11 @@ fn test_javadoc_example() {
 
     let javadoc = make_handler(1704106800, false, javadoc::Javadoc::boxed).unwrap();
 
-    assert!(javadoc.filter(&*input).unwrap());
+    assert!(javadoc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(javadoc.process(&*input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(javadoc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
 
     let new = input.metadata().unwrap();
     // because of timestamp granularity, creation ts might be equal
@@ -36,11 +36,11 @@ fn test_javadoc_fixed() {
 
     let javadoc = make_handler(1704106800, false, javadoc::Javadoc::boxed).unwrap();
 
-    assert!(javadoc.filter(&*input).unwrap());
+    assert!(javadoc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(javadoc.process(&*input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(javadoc.process(&input).unwrap(), handlers::ProcessResult::Noop);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -54,14 +54,44 @@ fn test_invalid_utf8() {
 
     let javadoc = make_handler(1704106800, false, javadoc::Javadoc::boxed).unwrap();
 
-    assert!(javadoc.filter(&*input).unwrap());
+    assert!(javadoc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(javadoc.process(&*input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(javadoc.process(&input).unwrap(), handlers::ProcessResult::Noop);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
     assert_eq!(orig.modified().unwrap(), new.modified().unwrap());
     assert_eq!(orig.st_ino(), new.st_ino());
 }
+
+#[test]
+fn test_javadoc_htm() {
+    // The header block as written by javadoc from JDK 17
+    let (_dir, input) = prepare_dir("tests/cases/javadoc-header.htm").unwrap();
+
+    let javadoc = make_handler(1704106800, false, javadoc::Javadoc::boxed).unwrap();
+    assert!(javadoc.filter(&input).unwrap());
+    assert_eq!(javadoc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    assert_eq!(fs::read_to_string(&*input).unwrap(),
+               fs::read_to_string("tests/cases/javadoc-header.fixed.htm").unwrap());
+
+    assert_eq!(javadoc.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_other_generator() {
+    let (_dir, input) = prepare_dir("tests/cases/javadoc-header.htm").unwrap();
+
+    // Without the javadoc signature, the comment is left alone
+    let data = fs::read_to_string(&*input).unwrap()
+        .replace("<!-- Generated by javadoc (17)", "<!-- Generated by mkdocs (17)")
+        .replace("dc.created", "dc.modified");
+    fs::write(&*input, &data).unwrap();
+
+    let javadoc = make_handler(1704106800, false, javadoc::Javadoc::boxed).unwrap();
+    assert_eq!(javadoc.process(&input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(fs::read_to_string(&*input).unwrap(), data);
+}
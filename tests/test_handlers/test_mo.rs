@@ -0,0 +1,108 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::os::linux::fs::MetadataExt;
+use std::path::Path;
+
+use add_determinism::handlers;
+use add_determinism::handlers::mo;
+
+use super::{prepare_dir, make_handler};
+
+/// A minimal independent .mo parser to verify that the output is still valid.
+fn read_catalog(path: &Path) -> Vec<(String, String)> {
+    let data = fs::read(path).unwrap();
+
+    let big_endian = data[..4] == [0x95, 0x04, 0x12, 0xde];
+    assert!(big_endian || data[..4] == [0xde, 0x12, 0x04, 0x95]);
+
+    let u32_at = |offset: usize| {
+        let bytes = data[offset..offset + 4].try_into().unwrap();
+        (if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }) as usize
+    };
+    let string_at = |entry: usize| {
+        let (length, offset) = (u32_at(entry), u32_at(entry + 4));
+        assert_eq!(data[offset + length], 0);
+        String::from_utf8(data[offset..offset + length].to_vec()).unwrap()
+    };
+
+    let (count, originals, translations) = (u32_at(8), u32_at(12), u32_at(16));
+    assert_eq!(originals % 4, 0);
+    assert_eq!(translations % 4, 0);
+
+    (0..count)
+        .map(|i| (string_at(originals + 8 * i), string_at(translations + 8 * i)))
+        .collect()
+}
+
+fn test_catalog(filename: &str) {
+    let (_dir, input) = prepare_dir(filename).unwrap();
+
+    let mo = make_handler(1704106800, false, mo::Mo::boxed).unwrap();
+
+    assert!(mo.filter(&input).unwrap());
+
+    let catalog = read_catalog(&input);
+    assert!(catalog[0].1.contains("\nPO-Revision-Date: "));
+    assert!(!catalog[0].1.contains("\nPO-Revision-Date: 2024-01-01 11:00+0000\n"));
+
+    let orig = input.metadata().unwrap();
+
+    assert_eq!(mo.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let new = input.metadata().unwrap();
+    assert_eq!(orig.modified().unwrap(), new.modified().unwrap());
+    assert_ne!(orig.st_ino(), new.st_ino());
+
+    let catalog2 = read_catalog(&input);
+    assert_eq!(catalog.len(), catalog2.len());
+    assert_eq!(catalog2[0].0, "");
+    assert!(catalog2[0].1.contains("\nPO-Revision-Date: 2024-01-01 11:00+0000\n"));
+    assert!(catalog2[0].1.contains("\nLast-Translator: Some One <someone@example.com>\n"));
+    assert_eq!(catalog[1..], catalog2[1..]);
+    assert!(catalog2.contains(&("Hello".to_string(), "Cześć".to_string())));
+
+    // Processing again is a noop
+    assert_eq!(mo.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_messages() {
+    test_catalog("tests/cases/messages.mo");
+}
+
+#[test]
+fn test_messages_big_endian() {
+    test_catalog("tests/cases/messages-be.mo");
+}
+
+#[test]
+fn test_messages_short_date() {
+    // The date is formatted differently, so the replacement is longer
+    // and the strings after it must be moved.
+    test_catalog("tests/cases/messages-short-date.mo");
+}
+
+#[test]
+fn test_messages_old() {
+    let (_dir, input) = prepare_dir("tests/cases/messages.mo").unwrap();
+
+    let mo = make_handler(1800000000, false, mo::Mo::boxed).unwrap();
+
+    let orig = input.metadata().unwrap();
+
+    assert_eq!(mo.process(&input).unwrap(), handlers::ProcessResult::Noop);
+
+    let new = input.metadata().unwrap();
+    assert_eq!(orig.st_ino(), new.st_ino());
+}
+
+#[test]
+fn test_not_mo() {
+    let (_dir, input) = prepare_dir("tests/cases/javadoc-example.html").unwrap();
+
+    let mo = make_handler(1704106800, false, mo::Mo::boxed).unwrap();
+
+    let err = mo.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
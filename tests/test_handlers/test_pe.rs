@@ -0,0 +1,135 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+
+use add_determinism::handlers;
+use add_determinism::handlers::pe;
+
+use super::{prepare_dir, make_handler};
+
+// The offsets in tests/cases/pe-timestamps.dll, a PE32+ image with one
+// section which contains an export directory and a debug directory entry
+const CHECKSUM: usize = 0x98 + 64;
+const EXPORT_TIME_DATE_STAMP: usize = 0x204;
+const DEBUG_TIME_DATE_STAMP: usize = 0x234;
+const CERTIFICATE_TABLE: usize = 0x98 + 112 + 4 * 8;
+const CLR_RUNTIME_HEADER: usize = 0x98 + 112 + 14 * 8;
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset .. offset + 4].try_into().unwrap())
+}
+
+fn coff_time_date_stamp(data: &[u8]) -> u32 {
+    let pe_header = u32_at(data, 0x3c) as usize;
+    assert_eq!(&data[pe_header .. pe_header + 4], b"PE\0\0");
+    u32_at(data, pe_header + 8)
+}
+
+#[test]
+fn test_pe_timestamps() {
+    let (_dir, input) = prepare_dir("tests/cases/pe-timestamps.dll").unwrap();
+
+    let pe = make_handler(111, false, pe::Pe::boxed).unwrap();
+    assert!(pe.filter(&input).unwrap());
+    assert_eq!(pe.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let orig = fs::read("tests/cases/pe-timestamps.dll").unwrap();
+    let fixed = fs::read(&*input).unwrap();
+    assert_eq!(orig.len(), fixed.len());
+
+    assert_eq!(coff_time_date_stamp(&orig), 1710000000);
+    assert_eq!(coff_time_date_stamp(&fixed), 111);
+    assert_eq!(u32_at(&orig, EXPORT_TIME_DATE_STAMP), 1710000000);
+    assert_eq!(u32_at(&fixed, EXPORT_TIME_DATE_STAMP), 111);
+    assert_eq!(u32_at(&orig, DEBUG_TIME_DATE_STAMP), 1710000000);
+    assert_eq!(u32_at(&fixed, DEBUG_TIME_DATE_STAMP), 0);
+
+    // The checksum is updated
+    let mut zeroed = fixed.clone();
+    zeroed[CHECKSUM .. CHECKSUM + 4].fill(0);
+    assert_ne!(u32_at(&orig, CHECKSUM), u32_at(&fixed, CHECKSUM));
+    assert_eq!(u32_at(&fixed, CHECKSUM), pe::checksum(&zeroed));
+
+    let mut zeroed = orig.clone();
+    zeroed[CHECKSUM .. CHECKSUM + 4].fill(0);
+    assert_eq!(u32_at(&orig, CHECKSUM), pe::checksum(&zeroed));
+
+    assert_eq!(pe.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_zero_checksum() {
+    let (_dir, input) = prepare_dir("tests/cases/pe-timestamps.dll").unwrap();
+
+    let mut data = fs::read(&*input).unwrap();
+    data[CHECKSUM .. CHECKSUM + 4].fill(0);
+    fs::write(&*input, &data).unwrap();
+
+    // A zero checksum is not verified, so it is not filled in
+    let pe = make_handler(111, false, pe::Pe::boxed).unwrap();
+    assert_eq!(pe.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(u32_at(&fs::read(&*input).unwrap(), CHECKSUM), 0);
+}
+
+#[test]
+fn test_newer_epoch() {
+    let (_dir, input) = prepare_dir("tests/cases/pe-timestamps.dll").unwrap();
+
+    // The timestamps in the headers are older, but the debug entry is still zeroed
+    let pe = make_handler(1800000000, false, pe::Pe::boxed).unwrap();
+    assert_eq!(pe.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let fixed = fs::read(&*input).unwrap();
+    assert_eq!(coff_time_date_stamp(&fixed), 1710000000);
+    assert_eq!(u32_at(&fixed, EXPORT_TIME_DATE_STAMP), 1710000000);
+    assert_eq!(u32_at(&fixed, DEBUG_TIME_DATE_STAMP), 0);
+}
+
+#[test]
+fn test_bad_pe_header() {
+    let (_dir, input) = prepare_dir("tests/cases/pe-timestamps.dll").unwrap();
+
+    // e_lfanew points at the DOS stub
+    let mut data = fs::read(&*input).unwrap();
+    data[0x3c] = 0x40;
+    fs::write(&*input, &data).unwrap();
+
+    let pe = make_handler(111, false, pe::Pe::boxed).unwrap();
+    assert!(pe.process(&input).is_err());
+    assert_eq!(fs::read(&*input).unwrap(), data);
+}
+
+#[test]
+fn test_signed() {
+    let (_dir, input) = prepare_dir("tests/cases/pe-timestamps.dll").unwrap();
+
+    // Append a dummy WIN_CERTIFICATE and point the security directory at it
+    let mut data = fs::read(&*input).unwrap();
+    let offset = data.len() as u32;
+    data.extend_from_slice(&16_u32.to_le_bytes());
+    data.extend_from_slice(&0x0200_u16.to_le_bytes());
+    data.extend_from_slice(&0x0002_u16.to_le_bytes());
+    data.extend_from_slice(&[0; 8]);
+    data[CERTIFICATE_TABLE .. CERTIFICATE_TABLE + 4].copy_from_slice(&offset.to_le_bytes());
+    data[CERTIFICATE_TABLE + 4 .. CERTIFICATE_TABLE + 8].copy_from_slice(&16_u32.to_le_bytes());
+    fs::write(&*input, &data).unwrap();
+
+    let pe = make_handler(111, false, pe::Pe::boxed).unwrap();
+    assert_eq!(pe.process(&input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(fs::read(&*input).unwrap(), data);
+}
+
+#[test]
+fn test_dotnet_assembly() {
+    let (_dir, input) = prepare_dir("tests/cases/pe-timestamps.dll").unwrap();
+
+    // Point the CLR runtime header directory somewhere, it is not parsed
+    let mut data = fs::read(&*input).unwrap();
+    data[CLR_RUNTIME_HEADER .. CLR_RUNTIME_HEADER + 4].copy_from_slice(&0x1000_u32.to_le_bytes());
+    data[CLR_RUNTIME_HEADER + 4 .. CLR_RUNTIME_HEADER + 8].copy_from_slice(&72_u32.to_le_bytes());
+    fs::write(&*input, &data).unwrap();
+
+    let pe = make_handler(111, false, pe::Pe::boxed).unwrap();
+    assert_eq!(pe.process(&input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(fs::read(&*input).unwrap(), data);
+}
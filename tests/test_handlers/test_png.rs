@@ -0,0 +1,105 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::os::linux::fs::MetadataExt;
+use std::rc::Rc;
+
+use add_determinism::options;
+use add_determinism::handlers;
+use add_determinism::handlers::png;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+fn chunk_types(data: &[u8]) -> Vec<String> {
+    let mut types = vec![];
+    let mut offset = 8;
+
+    while offset < data.len() {
+        let length = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        types.push(String::from_utf8(data[offset + 4..offset + 8].to_vec()).unwrap());
+        offset += 12 + length;
+    }
+
+    types
+}
+
+#[test]
+fn test_image_metadata() {
+    let png = make_handler(1704106800, false, png::Png::boxed).unwrap();
+    test_corpus_file(png, "tests/cases/image-metadata.png");
+}
+
+#[test]
+fn test_image_metadata_strip_text() {
+    let (_dir, input) = prepare_dir("tests/cases/image-metadata.png").unwrap();
+
+    let mut cfg = options::Config::empty(1704106800, false);
+    cfg.png_strip_text = true;
+    let png = png::Png::boxed(&Rc::new(cfg));
+
+    assert!(png.filter(&input).unwrap());
+
+    let orig = input.metadata().unwrap();
+
+    assert_eq!(chunk_types(&fs::read(&*input).unwrap()),
+               vec!["IHDR", "tIME", "iTXt", "tEXt", "IDAT", "IEND"]);
+
+    assert_eq!(png.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let new = input.metadata().unwrap();
+    assert_eq!(orig.modified().unwrap(), new.modified().unwrap());
+    assert_ne!(orig.st_ino(), new.st_ino());
+
+    assert_eq!(chunk_types(&fs::read(&*input).unwrap()),
+               vec!["IHDR", "IDAT", "IEND"]);
+}
+
+#[test]
+fn test_image_metadata_fixed() {
+    let (_dir, input) = prepare_dir("tests/cases/image-metadata.png.fixed").unwrap();
+    let input2 = input.with_extension("png");
+    fs::rename(&*input, &input2).unwrap();
+
+    let png = make_handler(1704106800, false, png::Png::boxed).unwrap();
+
+    let orig = input2.metadata().unwrap();
+
+    assert_eq!(png.process(&input2).unwrap(), handlers::ProcessResult::Noop);
+
+    let new = input2.metadata().unwrap();
+    assert_eq!(orig.modified().unwrap(), new.modified().unwrap());
+    assert_eq!(orig.st_ino(), new.st_ino());
+}
+
+#[test]
+fn test_truncated() {
+    let (_dir, input) = prepare_dir("tests/cases/image-metadata.png").unwrap();
+
+    let data = fs::read(&*input).unwrap();
+    fs::write(&*input, &data[..data.len() - 7]).unwrap();
+
+    let png = make_handler(1704106800, false, png::Png::boxed).unwrap();
+
+    let err = png.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
+
+#[test]
+fn test_not_png() {
+    let (_dir, input) = prepare_dir("tests/cases/image-metadata.png").unwrap();
+    let png = make_handler(1704106800, false, png::Png::boxed).unwrap();
+
+    // A JPEG image with the wrong extension
+    let data = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00";
+    fs::write(&*input, data).unwrap();
+    assert!(png.filter(&input).unwrap());
+
+    let err = png.process(&input).unwrap_err();
+    assert!(matches!(err.downcast_ref::<handlers::Error>(), Some(handlers::Error::BadMagic(0, _, _))));
+    assert_eq!(fs::read(&*input).unwrap(), data);
+
+    // Too short for the signature
+    fs::write(&*input, b"\x89PNG").unwrap();
+    let err = png.process(&input).unwrap_err();
+    assert!(matches!(err.downcast_ref::<handlers::Error>(), Some(handlers::Error::UnexpectedEOF(0, 8))));
+}
@@ -3,6 +3,7 @@
 use std::fs;
 use std::fs::File;
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 use add_determinism::handlers;
@@ -30,11 +31,11 @@ fn test_adapters() {
 
     let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
 
-    assert!(pyc.filter(&*input).unwrap());
+    assert!(pyc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(pyc.process(&*input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
 
     let new = input.metadata().unwrap();
     // because of timestamp granularity, creation ts might be equal
@@ -49,13 +50,13 @@ fn test_adapters_hardlinked() {
 
     let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
 
-    assert!(pyc.filter(&*input).unwrap());
+    assert!(pyc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
     fs::hard_link(&*input, (*input).with_extension("pyc.evenbetter")).unwrap();
 
-    assert_eq!(pyc.process(&*input).unwrap(), handlers::ProcessResult::Rewritten);
+    assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Rewritten);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
@@ -69,11 +70,11 @@ fn test_adapters_opt_1() {
 
     let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
 
-    assert!(pyc.filter(&*input).unwrap());
+    assert!(pyc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(pyc.process(&*input).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
 
     let new = input.metadata().unwrap();
     // because of timestamp granularity, creation ts might be equal
@@ -82,6 +83,75 @@ fn test_adapters_opt_1() {
     assert_ne!(orig.st_ino(), new.st_ino());
 }
 
+#[test]
+fn test_optimization_level_names() {
+    // The optimization level is part of the file name, before the
+    // extension. The file must be replaced in place, under the same name,
+    // and the temporary file must not be left behind.
+    for name in [
+        "adapters.cpython-312.pyc",
+        "adapters.cpython-312.opt-1.pyc",
+        "adapters.cpython-312.opt-2.pyc",
+    ] {
+        let dir = tempfile::TempDir::new().unwrap();
+        let input = dir.path().join(name);
+        fs::copy("tests/cases/adapters.cpython-312.pyc", &input).unwrap();
+
+        let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
+
+        assert!(pyc.filter(&input).unwrap());
+        assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+        let files: Vec<_> = fs::read_dir(dir.path()).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files, vec![name]);
+
+        assert_eq!(fs::read(&input).unwrap(),
+                   fs::read("tests/cases/adapters.cpython-312~fixed.pyc").unwrap());
+    }
+}
+
+#[test]
+fn test_adapters_mode() {
+    for mode in [0o755, 0o4755, 0o600] {
+        let (_dir, input) = prepare_dir("tests/cases/adapters.cpython-312.pyc").unwrap();
+        fs::set_permissions(&*input, fs::Permissions::from_mode(mode)).unwrap();
+
+        let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
+
+        let orig = input.metadata().unwrap();
+
+        assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+        let new = input.metadata().unwrap();
+        assert_ne!(orig.st_ino(), new.st_ino());
+        assert_eq!(new.permissions().mode() & 0o7777, mode);
+        assert_eq!(orig.st_uid(), new.st_uid());
+        assert_eq!(orig.st_gid(), new.st_gid());
+    }
+}
+
+#[test]
+fn test_adapters_xattr() {
+    let (_dir, input) = prepare_dir("tests/cases/adapters.cpython-312.pyc").unwrap();
+
+    if let Err(e) = xattr::set(&*input, "user.add-determinism", b"value") {
+        // Not all filesystems support user xattrs, e.g. tmpfs on older kernels.
+        eprintln!("Cannot set user xattr, skipping test: {e}");
+        return;
+    }
+
+    let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
+
+    let orig = input.metadata().unwrap();
+
+    assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let new = input.metadata().unwrap();
+    assert_ne!(orig.st_ino(), new.st_ino());
+    assert_eq!(xattr::get(&*input, "user.add-determinism").unwrap().unwrap(), b"value");
+}
 
 #[test]
 fn test_testrelro_fixed() {
@@ -89,11 +159,11 @@ fn test_testrelro_fixed() {
 
     let pyc = make_handler(111, false, pyc::Pyc::boxed).unwrap();
 
-    assert!(pyc.filter(&*input).unwrap());
+    assert!(pyc.filter(&input).unwrap());
 
     let orig = input.metadata().unwrap();
 
-    assert_eq!(pyc.process(&*input).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(pyc.process(&input).unwrap(), handlers::ProcessResult::Noop);
 
     let new = input.metadata().unwrap();
     assert_eq!(orig.created().unwrap(), new.created().unwrap());
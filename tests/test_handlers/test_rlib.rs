@@ -0,0 +1,97 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use add_determinism::options;
+use add_determinism::handlers;
+use add_determinism::handlers::{elf, rlib};
+
+use super::{prepare_dir, make_handler};
+
+/// The members of an archive: the raw name, the mtime, the owner, and the contents
+fn members(data: &[u8]) -> Vec<(String, i64, String, Vec<u8>)> {
+    let field = |pos: usize, range: std::ops::Range<usize>| {
+        std::str::from_utf8(&data[pos + range.start .. pos + range.end]).unwrap().trim_end().to_string()
+    };
+
+    let mut members = vec![];
+    let mut pos = 8;
+    while pos < data.len() {
+        let size = field(pos, 48..58).parse::<usize>().unwrap();
+        members.push((
+            field(pos, 0..16),
+            field(pos, 16..28).parse().unwrap_or(0),
+            format!("{}:{}", field(pos, 28..34), field(pos, 34..40)),
+            data[pos + 60 .. pos + 60 + size].to_vec(),
+        ));
+        pos += 60 + size + size % 2;
+    }
+    members
+}
+
+fn member<'a>(members: &'a [(String, i64, String, Vec<u8>)], name: &str) -> &'a [u8] {
+    &members.iter().find(|m| m.0 == name).unwrap().3
+}
+
+#[test]
+fn test_member_mtimes() {
+    let (_dir, input) = prepare_dir("tests/cases/libhello.rlib").unwrap();
+
+    let rlib = make_handler(0, false, rlib::Rlib::boxed).unwrap();
+    assert!(rlib.filter(&input).unwrap());
+    assert_eq!(rlib.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let orig = members(&fs::read("tests/cases/libhello.rlib").unwrap());
+    let fixed = members(&fs::read(&*input).unwrap());
+    assert_eq!(orig.len(), fixed.len());
+
+    for (name, mtime, owner, _) in &orig {
+        if name != "//" {
+            assert_eq!(*mtime, 1710000000);
+            assert_eq!(owner, "1000:1000");
+        }
+    }
+    for (name, mtime, owner, _) in &fixed {
+        if name != "//" {
+            assert_eq!(*mtime, 0);
+            assert_eq!(owner, "0:0");
+        }
+    }
+
+    // The contents are not modified without --elf-strip-comment
+    for (o, f) in orig.iter().zip(&fixed) {
+        assert_eq!(o.3, f.3);
+    }
+
+    assert_eq!(rlib.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_strip_comment() {
+    let (_dir, input) = prepare_dir("tests/cases/libhello.rlib").unwrap();
+
+    let mut cfg = options::Config::empty(0, false);
+    cfg.elf_strip_comment = true;
+    let rlib = rlib::Rlib::boxed(&Rc::new(cfg));
+    assert_eq!(rlib.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let orig = members(&fs::read("tests/cases/libhello.rlib").unwrap());
+    let fixed = members(&fs::read(&*input).unwrap());
+
+    // The metadata is wrapped in an ELF file, but it is passed through untouched
+    assert!(member(&orig, "lib.rmeta/").starts_with(b"\x7fELF"));
+    assert_eq!(member(&orig, "lib.rmeta/"), member(&fixed, "lib.rmeta/"));
+
+    // The object file has a long name
+    let object = member(&fixed, "/0");
+    let (_, sections) = elf::read_sections(&mut Cursor::new(object), object.len() as u64).unwrap();
+    let comment = sections.iter().find(|s| s.name == ".comment").unwrap();
+    let range = comment.offset as usize..(comment.offset + comment.size) as usize;
+
+    assert!(member(&orig, "/0")[range.clone()].starts_with(b"\0rustc version "));
+    assert!(object[range.clone()].iter().all(|b| *b == 0));
+    assert_eq!(member(&orig, "/0")[..range.start], object[..range.start]);
+    assert_eq!(member(&orig, "/0")[range.end..], object[range.end..]);
+}
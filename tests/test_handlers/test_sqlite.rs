@@ -0,0 +1,84 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+
+use add_determinism::handlers;
+use add_determinism::handlers::sqlite;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+// The database has one table, created and then filled in
+// three separate transactions.
+const CHANGE_COUNTER: usize = 24;
+const DATABASE_SIZE: usize = 28;
+const VERSION_VALID_FOR: usize = 92;
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn test_change_counter() {
+    let sqlite = make_handler(1704106800, false, sqlite::Sqlite::boxed).unwrap();
+    test_corpus_file(sqlite, "tests/cases/sqlite-counter.sqlite");
+
+    let orig = fs::read("tests/cases/sqlite-counter.sqlite").unwrap();
+    let fixed = fs::read("tests/cases/sqlite-counter.sqlite.fixed").unwrap();
+
+    assert_eq!(be32(&orig, CHANGE_COUNTER), 4);
+    assert_eq!(be32(&orig, VERSION_VALID_FOR), 4);
+    assert_eq!(be32(&fixed, CHANGE_COUNTER), 0);
+    assert_eq!(be32(&fixed, VERSION_VALID_FOR), 0);
+
+    // The size in the header is still valid and is kept, and so is everything else
+    assert_eq!(be32(&fixed, DATABASE_SIZE), 2);
+    assert_eq!(orig[..CHANGE_COUNTER], fixed[..CHANGE_COUNTER]);
+    assert_eq!(orig[CHANGE_COUNTER + 4..VERSION_VALID_FOR], fixed[CHANGE_COUNTER + 4..VERSION_VALID_FOR]);
+    assert_eq!(orig[VERSION_VALID_FOR + 4..], fixed[VERSION_VALID_FOR + 4..]);
+}
+
+#[test]
+fn test_stale_database_size() {
+    let (_dir, input) = prepare_dir("tests/cases/sqlite-counter.sqlite").unwrap();
+
+    // A writer which doesn't maintain the size in the header
+    let mut data = fs::read(&*input).unwrap();
+    data[VERSION_VALID_FOR..VERSION_VALID_FOR + 4].copy_from_slice(&1_u32.to_be_bytes());
+    fs::write(&*input, &data).unwrap();
+
+    let sqlite = make_handler(1704106800, false, sqlite::Sqlite::boxed).unwrap();
+    assert_eq!(sqlite.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let data = fs::read(&*input).unwrap();
+    assert_eq!(be32(&data, CHANGE_COUNTER), 0);
+    assert_eq!(be32(&data, VERSION_VALID_FOR), 0);
+    assert_eq!(be32(&data, DATABASE_SIZE), 0);
+
+    assert_eq!(sqlite.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_journal() {
+    let (dir, input) = prepare_dir("tests/cases/sqlite-counter.sqlite").unwrap();
+    let data = fs::read(&*input).unwrap();
+
+    let journal = dir.path().join("sqlite-counter.sqlite-journal");
+    fs::write(&journal, b"").unwrap();
+
+    let sqlite = make_handler(1704106800, false, sqlite::Sqlite::boxed).unwrap();
+    let err = sqlite.process(&input).unwrap_err();
+    assert!(err.to_string().contains("-journal exists"));
+    assert_eq!(fs::read(&*input).unwrap(), data);
+
+    fs::remove_file(&journal).unwrap();
+    assert_eq!(sqlite.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+}
+
+#[test]
+fn test_bad_magic() {
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+
+    let sqlite = make_handler(1704106800, false, sqlite::Sqlite::boxed).unwrap();
+    let err = sqlite.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
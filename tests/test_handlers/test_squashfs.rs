@@ -0,0 +1,85 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+
+use add_determinism::handlers;
+use add_determinism::handlers::squashfs;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+// The images contain one file "hello.txt" in the root directory,
+// with uncompressed metadata and data. The inode table follows the
+// file data at 0x60 and directly starts with the inode of the file.
+const INODE_TABLE: usize = 0x60 + 6;
+const FILE_MTIME: usize = INODE_TABLE + 2 + 8;
+
+#[test]
+fn test_mkfs_time() {
+    let (_dir, input) = prepare_dir("tests/cases/squashfs-tiny.squashfs").unwrap();
+    let orig = fs::read(&*input).unwrap();
+
+    let squashfs = make_handler(1704106800, false, squashfs::Squashfs::boxed).unwrap();
+    assert!(squashfs.filter(&input).unwrap());
+    assert_eq!(squashfs.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    // The inode mtimes are older than $SOURCE_DATE_EPOCH,
+    // so only the superblock field is changed.
+    let new = fs::read(&*input).unwrap();
+    assert_eq!(orig.len(), new.len());
+    assert_eq!(&orig[8..12], &1710000000_u32.to_le_bytes());
+    assert_eq!(&new[8..12], &1704106800_u32.to_le_bytes());
+    assert_eq!(orig[..8], new[..8]);
+    assert_eq!(orig[12..], new[12..]);
+
+    assert_eq!(squashfs.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_inode_mtimes() {
+    let squashfs = make_handler(1704106800, false, squashfs::Squashfs::boxed).unwrap();
+    test_corpus_file(squashfs, "tests/cases/squashfs-mtimes.squashfs");
+
+    let fixed = fs::read("tests/cases/squashfs-mtimes.squashfs.fixed").unwrap();
+    assert_eq!(&fixed[FILE_MTIME..FILE_MTIME + 4], &1704106800_u32.to_le_bytes());
+}
+
+#[test]
+fn test_compressed_inodes() {
+    let (_dir, input) = prepare_dir("tests/cases/squashfs-mtimes.squashfs").unwrap();
+
+    // Mark the metadata block as compressed. We cannot parse it anymore,
+    // so the inodes must be left alone.
+    let mut data = fs::read(&*input).unwrap();
+    data[INODE_TABLE + 1] &= 0x7f;
+    fs::write(&*input, &data).unwrap();
+
+    let squashfs = make_handler(1704106800, false, squashfs::Squashfs::boxed).unwrap();
+    assert_eq!(squashfs.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let new = fs::read(&*input).unwrap();
+    assert_eq!(&new[8..12], &1704106800_u32.to_le_bytes());
+    assert_eq!(data[12..], new[12..]);
+}
+
+#[test]
+fn test_bad_superblock() {
+    let squashfs = make_handler(1704106800, false, squashfs::Squashfs::boxed).unwrap();
+
+    // Version 3
+    let (_dir, input) = prepare_dir("tests/cases/squashfs-tiny.squashfs").unwrap();
+    let mut data = fs::read(&*input).unwrap();
+    data[28] = 3;
+    fs::write(&*input, &data).unwrap();
+    let err = squashfs.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+
+    // Truncated
+    fs::write(&*input, &data[..50]).unwrap();
+    let err = squashfs.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+
+    // Not a squashfs image
+    let (_dir, input) = prepare_dir("tests/cases/testrelro.a").unwrap();
+    let err = squashfs.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
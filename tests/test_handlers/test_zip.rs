@@ -0,0 +1,372 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+
+use std::fs;
+use std::io::Read;
+use std::rc::Rc;
+
+use add_determinism::options;
+use add_determinism::handlers;
+use add_determinism::handlers::zip;
+
+use super::{prepare_dir, make_handler, test_corpus_file};
+
+/// Return (version made by, external attributes) for each central directory entry.
+fn central_entries(data: &[u8]) -> Vec<(u16, u32)> {
+    let u16_at = |o: usize| u16::from_le_bytes(data[o..o + 2].try_into().unwrap());
+    let u32_at = |o: usize| u32::from_le_bytes(data[o..o + 4].try_into().unwrap());
+
+    let eocd = data.len() - 22;
+    assert_eq!(&data[eocd..eocd + 4], b"PK\x05\x06");
+
+    let count = u16_at(eocd + 10);
+    let mut offset = u32_at(eocd + 16) as usize;
+    let mut entries = vec![];
+
+    for _ in 0..count {
+        assert_eq!(&data[offset..offset + 4], b"PK\x01\x02");
+        entries.push((u16_at(offset + 4), u32_at(offset + 38)));
+        offset += 46 + u16_at(offset + 28) as usize + u16_at(offset + 30) as usize + u16_at(offset + 32) as usize;
+    }
+
+    entries
+}
+
+#[test]
+fn test_mixed_hosts() {
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    test_corpus_file(zip, "tests/cases/mixed-hosts.whl");
+}
+
+#[test]
+fn test_version_made_by() {
+    let (_dir, input) = prepare_dir("tests/cases/mixed-hosts.whl").unwrap();
+
+    let entries = central_entries(&fs::read(&*input).unwrap());
+    let versions: Vec<u16> = entries.iter().map(|e| e.0).collect();
+    assert_eq!(versions, vec![0x0014, 0x0014, 0x003f, 0x0314, 0x032d]);
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let entries = central_entries(&fs::read(&*input).unwrap());
+    let versions: Vec<u16> = entries.iter().map(|e| e.0).collect();
+    assert_eq!(versions, vec![0x0314; 5]);
+
+    // Entries which came from a different host got a UNIX mode
+    let modes: Vec<u32> = entries.iter().map(|e| e.1 >> 16).collect();
+    assert_eq!(modes, vec![0o40755, 0o100644, 0o100444, 0o100755, 0o100644]);
+
+    // Processing again gives the same result
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_truncated() {
+    let (_dir, input) = prepare_dir("tests/cases/mixed-hosts.whl").unwrap();
+
+    let data = fs::read(&*input).unwrap();
+    fs::write(&*input, &data[..data.len() - 7]).unwrap();
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+
+    let err = zip.process(&input).unwrap_err();
+    assert!(err.downcast_ref::<handlers::Error>().is_some());
+}
+
+#[test]
+fn test_orphan_pyc() {
+    let (_dir, input) = prepare_dir("tests/cases/orphan-pyc.whl").unwrap();
+
+    let mut cfg = options::Config::empty(1704106800, false);
+    cfg.warn_orphan_pyc = true;
+    let zip = zip::Zip::boxed(&Rc::new(cfg));
+
+    // The check is only a diagnostic, the archive is not modified.
+    // The warnings themselves are checked in test_cli::test_warn_orphan_pyc.
+    assert!(zip.filter(&input).unwrap());
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+fn read_entry(path: &std::path::Path, name: &str) -> String {
+    let mut archive = ::zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+    let mut content = String::new();
+    archive.by_name(name).unwrap().read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn test_windows_record() {
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    test_corpus_file(zip, "tests/cases/windows-record.whl");
+}
+
+#[test]
+fn test_windows_record_converges() {
+    let (_dir, input) = prepare_dir("tests/cases/windows-record.whl").unwrap();
+
+    assert!(read_entry(&input, "foo-1.0.dist-info/RECORD").contains("foo\\bar\\baz.py,"));
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    assert_eq!(read_entry(&input, "foo-1.0.dist-info/RECORD"), indoc::indoc!("
+        foo-1.0.dist-info/RECORD,,
+        foo-1.0.dist-info/WHEEL,sha256=JcDW0bsb6ujED5-MXueaDBe6dnYsOleIV6Jl6pAuyjw,51
+        foo/__init__.py,sha256=ypeBEsobvcr6wjGzmiPcTaeG7_gUfE5yuYB3ha_uSLs,6
+        foo/bar/baz.py,sha256=ypeBEsobvcr6wjGzmiPcTaeG7_gUfE5yuYB3ha_uSLs,6
+    "));
+
+    // Other entries are not modified
+    assert_eq!(read_entry(&input, "foo/bar/baz.py"), "x = 1\n");
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_sort_extra_fields() {
+    let (_dir_a, input_a) = prepare_dir("tests/cases/extra-fields-a.zip").unwrap();
+    let (_dir_b, input_b) = prepare_dir("tests/cases/extra-fields-b.zip").unwrap();
+
+    let data_a = fs::read(&*input_a).unwrap();
+    let data_b = fs::read(&*input_b).unwrap();
+    assert_ne!(data_a, data_b);
+    assert_eq!(data_a.len(), data_b.len());
+
+    // Without the option, the order is kept
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input_b).unwrap(), handlers::ProcessResult::Noop);
+
+    let mut cfg = options::Config::empty(1704106800, false);
+    cfg.zip_sort_extra_fields = true;
+    let zip = zip::Zip::boxed(&Rc::new(cfg));
+
+    // UT (0x5455) before ux (0x7875) is already sorted
+    assert_eq!(zip.process(&input_a).unwrap(), handlers::ProcessResult::Noop);
+    assert_eq!(zip.process(&input_b).unwrap(), handlers::ProcessResult::Replaced);
+
+    assert_eq!(fs::read(&*input_b).unwrap(), data_a);
+}
+
+#[test]
+fn test_multi_tag() {
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    test_corpus_file(zip, "tests/cases/multi-tag-a.whl");
+}
+
+#[test]
+fn test_multi_tag_converges() {
+    let (_dir_a, input_a) = prepare_dir("tests/cases/multi-tag-a.whl").unwrap();
+    let (_dir_b, input_b) = prepare_dir("tests/cases/multi-tag-b.whl").unwrap();
+
+    assert_ne!(read_entry(&input_a, "foo-1.0.dist-info/WHEEL"),
+               read_entry(&input_b, "foo-1.0.dist-info/WHEEL"));
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input_a).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(zip.process(&input_b).unwrap(), handlers::ProcessResult::Replaced);
+
+    let wheel = read_entry(&input_a, "foo-1.0.dist-info/WHEEL");
+    assert!(wheel.ends_with(indoc::indoc!("
+        Tag: cp312-abi3-linux_x86_64
+        Tag: cp312-cp312-manylinux2014_x86_64
+        Tag: cp312-cp312-manylinux_2_17_x86_64
+    ")));
+
+    // The hash of the modified file is updated
+    let record = read_entry(&input_a, "foo-1.0.dist-info/RECORD");
+    assert!(record.contains(&format!(
+        "foo-1.0.dist-info/WHEEL,sha256=y2iA_vPg-Hz9yu9-sfrvyLgPnK7meX61s0FW0raEV5U,{}\n", wheel.len())));
+
+    assert_eq!(fs::read(&*input_a).unwrap(), fs::read(&*input_b).unwrap());
+
+    assert_eq!(zip.process(&input_a).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_windows_multi_tag() {
+    let (_dir, input) = prepare_dir("tests/cases/windows-multi-tag.whl").unwrap();
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+
+    let wheel = read_entry(&input, "foo-1.0.dist-info/WHEEL");
+    assert!(wheel.ends_with("Tag: cp312-abi3-win_amd64\r\nTag: cp312-cp312-win_amd64\r\n"));
+
+    // The entry with a backslash in the path is found and its hash updated
+    assert_eq!(read_entry(&input, "foo-1.0.dist-info/RECORD"), indoc::indoc!("
+        foo-1.0.dist-info/RECORD,,
+        foo-1.0.dist-info/WHEEL,sha256=61lNFNloiIle9MPC_JGnZz9ID5nAl_CYH-qCmtytko8,132
+        foo/__init__.py,sha256=nia_NpkRxFwkPGhBR7I_yeHc_PJX0pmhxjIBam_NM_Q,6
+    "));
+    assert_eq!(wheel.len(), 132);
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_metadata_folding() {
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    test_corpus_file(zip, "tests/cases/metadata-folding-a.whl");
+}
+
+#[test]
+fn test_metadata_folding_converges() {
+    let (_dir_a, input_a) = prepare_dir("tests/cases/metadata-folding-a.whl").unwrap();
+    let (_dir_b, input_b) = prepare_dir("tests/cases/metadata-folding-b.whl").unwrap();
+
+    assert_ne!(read_entry(&input_a, "foo-1.0.dist-info/METADATA"),
+               read_entry(&input_b, "foo-1.0.dist-info/METADATA"));
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    assert_eq!(zip.process(&input_a).unwrap(), handlers::ProcessResult::Replaced);
+    assert_eq!(zip.process(&input_b).unwrap(), handlers::ProcessResult::Replaced);
+
+    let metadata = read_entry(&input_a, "foo-1.0.dist-info/METADATA");
+    assert_eq!(metadata, concat!(
+        "Metadata-Version: 2.1\n",
+        "Name: foo\n",
+        "Version: 1.0\n",
+        "Summary: Foo\n",
+        "Description: foo\n",
+        "        ===\n",
+        "        \n",
+        "        A package.\n",
+        "        \n",
+        "            code example\n",
+        "Platform: UNKNOWN\n",
+    ));
+
+    // The hash of the modified file is updated
+    let record = read_entry(&input_a, "foo-1.0.dist-info/RECORD");
+    assert!(record.contains(&format!(
+        "foo-1.0.dist-info/METADATA,sha256=IWTC1-wuobF1X-36lTt56wBJI4ZY1cqEgO4CHa2eABs,{}\n", metadata.len())));
+
+    assert_eq!(fs::read(&*input_a).unwrap(), fs::read(&*input_b).unwrap());
+
+    assert_eq!(zip.process(&input_a).unwrap(), handlers::ProcessResult::Noop);
+}
+
+#[test]
+fn test_html_description_scrubbed() {
+    let mut cfg = options::Config::empty(1704106800, false);
+    cfg.wheel_scrub_description = true;
+    let zip = zip::Zip::boxed(&Rc::new(cfg));
+    test_corpus_file(zip, "tests/cases/html-description.whl");
+
+    let fixed = std::path::Path::new("tests/cases/html-description.whl.fixed");
+    let metadata = read_entry(fixed, "foo-1.0.dist-info/METADATA");
+    assert!(metadata.contains("<meta name=\"generator\" content=\"Docutils\" />\n"));
+    assert!(metadata.contains("<meta name=\"date\" content=\"2024-01-01\">\n"));
+    assert!(metadata.contains("<!-- Generated by pandoc -->\n"));
+    assert!(metadata.contains("<body><p>Foo is a package.</p></body>\n"));
+}
+
+#[test]
+fn test_html_description_kept_by_default() {
+    let (_dir, input) = prepare_dir("tests/cases/html-description.whl").unwrap();
+    let metadata = read_entry(&input, "foo-1.0.dist-info/METADATA");
+
+    let zip = make_handler(1704106800, false, zip::Zip::boxed).unwrap();
+    zip.process(&input).unwrap();
+
+    assert_eq!(read_entry(&input, "foo-1.0.dist-info/METADATA"), metadata);
+}
+
+/// Return the data of all extra fields with the given id,
+/// in the local headers and then the central headers.
+fn extra_fields(data: &[u8], id: u16) -> Vec<Vec<u8>> {
+    let u16_at = |o: usize| u16::from_le_bytes(data[o..o + 2].try_into().unwrap()) as usize;
+    let u32_at = |o: usize| u32::from_le_bytes(data[o..o + 4].try_into().unwrap()) as usize;
+
+    let mut fields = vec![];
+    let mut offset = 0;
+
+    loop {
+        let (extra, next) = match &data[offset..offset + 4] {
+            b"PK\x03\x04" => {
+                let start = offset + 30 + u16_at(offset + 26);
+                (start..start + u16_at(offset + 28), start + u16_at(offset + 28) + u32_at(offset + 18))
+            }
+            b"PK\x01\x02" => {
+                let start = offset + 46 + u16_at(offset + 28);
+                (start..start + u16_at(offset + 30), start + u16_at(offset + 30) + u16_at(offset + 32))
+            }
+            _ => break,
+        };
+
+        let mut o = extra.start;
+        while o < extra.end {
+            if u16_at(o) == id as usize {
+                fields.push(data[o + 4..o + 4 + u16_at(o + 2)].to_vec());
+            }
+            o += 4 + u16_at(o + 2);
+        }
+        offset = next;
+    }
+
+    fields
+}
+
+/// Process zip-uid-gid.zip with the given options, and return
+/// (uids and gids zeroed, mtimes clamped).
+fn process_uid_gid(strip_uid_gid: bool, clamp_time: bool) -> (bool, bool) {
+    let (_dir, input) = prepare_dir("tests/cases/zip-uid-gid.zip").unwrap();
+
+    let mut cfg = options::Config::empty(1704106800, false);
+    cfg.zip_strip_uid_gid = strip_uid_gid;
+    cfg.zip_clamp_time = clamp_time;
+    let zip = zip::Zip::boxed(&Rc::new(cfg));
+
+    // The archive has three entries, with uid=gid=1000,
+    // created by Info-ZIP zip. All mtimes are after the epoch.
+    let orig = fs::read(&*input).unwrap();
+    let uid_gid = extra_fields(&orig, 0x7875);
+    assert_eq!(uid_gid.len(), 6);
+    assert!(uid_gid.iter().all(|f| f == &[1, 4, 0xe8, 0x03, 0, 0, 4, 0xe8, 0x03, 0, 0]));
+
+    assert_eq!(zip.process(&input).unwrap(), handlers::ProcessResult::Replaced);
+    let data = fs::read(&*input).unwrap();
+    assert_eq!(data.len(), orig.len());
+
+    let uid_gid = extra_fields(&data, 0x7875);
+    assert_eq!(uid_gid.len(), 6);
+    let zeroed = uid_gid.iter().all(|f| f == &[1, 4, 0, 0, 0, 0, 4, 0, 0, 0, 0]);
+    assert!(zeroed || uid_gid == extra_fields(&orig, 0x7875));
+
+    let timestamps = extra_fields(&data, 0x5455);
+    assert_eq!(timestamps.len(), 6);
+    let clamped = timestamps.iter().all(|f| f[1..5] == 1704106800_u32.to_le_bytes());
+    assert!(clamped || timestamps == extra_fields(&orig, 0x5455));
+
+    // The DOS timestamps are clamped together with the extended timestamps
+    let dos_mtime = |path: &std::path::Path| {
+        let mut archive = ::zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+        let mtime = archive.by_name("hello.txt").unwrap().last_modified();
+        mtime.to_time().unwrap().unix_timestamp()
+    };
+    assert_eq!(dos_mtime(std::path::Path::new("tests/cases/zip-uid-gid.zip")), 1710000000);
+    assert_eq!(dos_mtime(&input), if clamped { 1704106800 } else { 1710000000 });
+
+    // The contents are not touched
+    assert_eq!(read_entry(&input, "dir/mod.py"), "x = 1\n");
+
+    (zeroed, clamped)
+}
+
+#[test]
+fn test_zip_strip_uid_gid() {
+    assert_eq!(process_uid_gid(true, false), (true, false));
+}
+
+#[test]
+fn test_zip_clamp_time() {
+    assert_eq!(process_uid_gid(false, true), (false, true));
+}
+
+#[test]
+fn test_zip_strip_uid_gid_and_clamp_time() {
+    assert_eq!(process_uid_gid(true, true), (true, true));
+}